@@ -1,80 +1,115 @@
 mod storage;
 mod test;
 
-use crate::storage::{Database, QueryResult, SelectResultResponse};
+use crate::storage::{Database, QueryResult, SelectResultResponse, Session, Storage};
 use serde_json::json;
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+
+const DEFAULT_DATA_DIR: &str = "data";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let listener = TcpListener::bind("127.0.0.1:5000").await?;
 
-    let database = init_db();
+    // The data directory can be given as the first argument
+    let data_dir = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_DATA_DIR.to_string());
+    // and the number of WAL records between automatic checkpoints as the second
+    let checkpoint_interval = std::env::args()
+        .nth(2)
+        .map(|arg| arg.parse::<u64>())
+        .transpose()?;
+    // and a buffer pool size in pages as the third, which keeps the rows in page files
+    let buffer_pages = std::env::args()
+        .nth(3)
+        .map(|arg| arg.parse::<usize>())
+        .transpose()?;
+    let database = init_db(&data_dir, checkpoint_interval, buffer_pages)?;
+    println!("Using data directory {}", data_dir);
     println!("Server listening on 127.0.0.1:5000");
 
     loop {
-        let (mut socket, _) = listener.accept().await?;
+        let (socket, _) = listener.accept().await?;
 
         let db = database.clone();
-        tokio::spawn(async move {
-            let mut buffer = [0; 1024];
+        tokio::spawn(handle_session(socket, db));
+    }
+}
 
-            loop {
-                let n = match socket.read(&mut buffer).await {
-                    Ok(0) => return,
-                    Ok(n) => n,
-                    Err(e) => {
-                        eprintln!("Failed to read from socket: {}", e);
-                        return;
-                    }
-                };
+// Serve the queries of one client, one per read, until it disconnects. The database is locked
+// for one statement at a time; a transaction lives in the session until it commits, so it
+// keeps no other session waiting and nobody sees its changes before the COMMIT. One that
+// disconnects in the middle is simply forgotten.
+async fn handle_session(mut socket: TcpStream, db: Arc<Mutex<Database>>) {
+    let mut buffer = [0; 1024];
+    let mut session = Session::default();
+
+    loop {
+        let n = match socket.read(&mut buffer).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) => {
+                eprintln!("Failed to read from socket: {}", e);
+                break;
+            }
+        };
 
-                let query = String::from_utf8_lossy(&buffer[..n]).to_string();
+        let query = String::from_utf8_lossy(&buffer[..n]).to_string();
 
-                // Execute query and prepare response
-                let response = {
-                    // Acquire the write lock
-                    let mut db_guard = match db.write() {
-                        Ok(guard) => guard,
-                        Err(e) => {
-                            eprintln!("Failed to acquire database lock: {}", e);
-                            return;
-                        }
-                    };
+        // Execute query and prepare response
+        let response = {
+            let mut db_guard = db.lock().await;
 
-                    // Execute query
-                    let result = db_guard.execute(&query);
-                    match result {
-                        Ok(q) => match q {
-                            QueryResult::Success(s) => format!("Received query: {:?}\n", s),
+            // Execute query
+            let result = session.execute(&mut db_guard, &query);
+            for warning in db_guard.take_warnings() {
+                eprintln!("{}", warning);
+            }
+            match result {
+                Ok(q) => match q {
+                    QueryResult::Success(s) => format!("Received query: {:?}\n", s),
+                    QueryResult::Upserted { inserted, updated } => format!(
+                        "Received query: \"Inserted {} row(s), updated {} row(s)\"\n",
+                        inserted, updated
+                    ),
 
-                            QueryResult::Rows(r) => {
-                                let r: SelectResultResponse = r.into();
-                                format!("Response from query: {}\n", json!(r).to_string())
-                            }
-                        },
-                        Err(e) => {
-                            format!("Query failed with error: {:?}\n", e)
-                        }
+                    QueryResult::Rows(r) => {
+                        let r: SelectResultResponse = r.into();
+                        format!("Response from query: {}\n", json!(r))
                     }
-                    // Prepare response
-                    //format!("Received query: {:?}\n", result)
-                    // Lock is released here when db_guard goes out of scope
-                };
-
-                // Write response
-                if let Err(e) = socket.write_all(response.as_bytes()).await {
-                    eprintln!("Failed to write to socket: {}", e);
-                    return;
+                },
+                Err(e) => {
+                    format!("Query failed with error: {:?}\n", e)
                 }
-                return;
             }
-        });
+        };
+
+        // Write response
+        if let Err(e) = socket.write_all(response.as_bytes()).await {
+            eprintln!("Failed to write to socket: {}", e);
+            break;
+        }
     }
 }
 
-fn init_db() -> Arc<RwLock<Database>> {
-    Arc::new(RwLock::new(Database::new()))
+fn init_db(
+    data_dir: &str,
+    checkpoint_interval: Option<u64>,
+    buffer_pages: Option<usize>,
+) -> Result<Arc<Mutex<Database>>, Box<dyn std::error::Error>> {
+    let mut database = match buffer_pages {
+        Some(buffer_pages) => Database::open_with(data_dir, Storage::Paged { buffer_pages })?,
+        None => Database::open(data_dir)?,
+    };
+    if let Some(interval) = checkpoint_interval {
+        database.set_checkpoint_interval(interval);
+    }
+    for warning in database.take_warnings() {
+        eprintln!("{}", warning);
+    }
+    Ok(Arc::new(Mutex::new(database)))
 }
@@ -0,0 +1,370 @@
+// Row changes of one UPDATE or DELETE, planned in full before any is applied.
+//
+// Changing or deleting a row can make foreign keys act on the rows referencing it, and those
+// actions can trigger more in turn. Planning follows all of them, then every constraint is
+// checked against the state the statement would leave behind, so a violation anywhere leaves
+// every table untouched.
+
+use super::constraint::{describe_columns, describe_key, ForeignKey};
+use super::store::RowId;
+use super::undo::Undo;
+use super::{Database, DbResult, Row, Table, Value};
+use sqlparser::ast::ReferentialAction;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+#[derive(Default)]
+pub struct Changes {
+    deleted: HashMap<String, BTreeSet<RowId>>,
+    // New versions of the updated rows
+    updated: HashMap<String, BTreeMap<RowId, Row>>,
+    // Rows a RESTRICT or NO ACTION foreign key forbids to lose the parent key they reference
+    restricted: Vec<Restricted>,
+}
+
+struct Restricted {
+    table: String,
+    id: RowId,
+    // Position of the foreign key among the table's
+    foreign_key: usize,
+    key: Vec<Value>,
+}
+
+impl Changes {
+    fn is_deleted(&self, table: &str, id: RowId) -> bool {
+        self.deleted.get(table).is_some_and(|ids| ids.contains(&id))
+    }
+}
+
+impl Database {
+    // Plan deleting the row `id` of `table`, currently holding `row`
+    pub(super) fn plan_delete(
+        &self,
+        changes: &mut Changes,
+        table: &str,
+        id: RowId,
+        row: Row,
+    ) -> DbResult<()> {
+        if !changes
+            .deleted
+            .entry(table.to_string())
+            .or_default()
+            .insert(id)
+        {
+            return Ok(());
+        }
+        if let Some(rows) = changes.updated.get_mut(table) {
+            rows.remove(&id);
+        }
+        self.follow_references(changes, table, &row, None)
+    }
+
+    // Plan replacing the row `id` of `table`, currently holding `old`, by `new`
+    pub(super) fn plan_update(
+        &self,
+        changes: &mut Changes,
+        table: &str,
+        id: RowId,
+        old: Row,
+        new: Row,
+    ) -> DbResult<()> {
+        if changes.is_deleted(table, id) {
+            return Ok(());
+        }
+        changes
+            .updated
+            .entry(table.to_string())
+            .or_default()
+            .insert(id, new.clone());
+        self.follow_references(changes, table, &old, Some(&new))
+    }
+
+    // Apply the actions of the foreign keys referencing `parent` to the rows that reference
+    // `old`, which is being deleted or, when `new` is given, updated
+    fn follow_references(
+        &self,
+        changes: &mut Changes,
+        parent: &str,
+        old: &Row,
+        new: Option<&Row>,
+    ) -> DbResult<()> {
+        let parent_table = self.tables.get(parent).ok_or("Table not found")?;
+        for (child, child_table) in &self.tables {
+            for (position, foreign_key) in child_table.foreign_keys.iter().enumerate() {
+                if foreign_key.parent != parent {
+                    continue;
+                }
+                let referred = Self::referred_columns(foreign_key, parent_table)?;
+                let Some(old_key) = Self::key_of(old, &referred) else {
+                    continue;
+                };
+                let new_key = new.map(|row| Self::values_of(row, &referred));
+                if new_key
+                    .as_ref()
+                    .is_some_and(|key| Self::same_key(key, &old_key))
+                {
+                    continue;
+                }
+                let action = match new_key {
+                    Some(_) => foreign_key.on_update,
+                    None => foreign_key.on_delete,
+                };
+                let referencing =
+                    self.planned_rows(changes, child, &foreign_key.columns, &old_key)?;
+                for (id, row) in referencing {
+                    let mut data = row.data.clone();
+                    match (action, &new_key) {
+                        (ReferentialAction::Cascade, None) => {
+                            self.plan_delete(changes, child, id, row)?;
+                            continue;
+                        }
+                        (ReferentialAction::Cascade, Some(new_key)) => {
+                            for (&column, value) in foreign_key.columns.iter().zip(new_key) {
+                                data[column] =
+                                    Table::coerce(&child_table.columns[column], value.clone())?;
+                            }
+                        }
+                        (ReferentialAction::SetNull, _) => {
+                            for &column in &foreign_key.columns {
+                                data[column] = Value::Null;
+                            }
+                        }
+                        (ReferentialAction::SetDefault, _) => {
+                            for &column in &foreign_key.columns {
+                                data[column] = self.column_default(child_table, column)?;
+                            }
+                        }
+                        (ReferentialAction::Restrict | ReferentialAction::NoAction, _) => {
+                            changes.restricted.push(Restricted {
+                                table: child.clone(),
+                                id,
+                                foreign_key: position,
+                                key: old_key.clone(),
+                            });
+                            continue;
+                        }
+                    }
+                    self.plan_update(changes, child, id, row, Row::new(data))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Check every constraint against the planned changes, then apply them
+    pub(super) fn apply_changes(&mut self, changes: Changes) -> DbResult<()> {
+        for restricted in &changes.restricted {
+            if changes.is_deleted(&restricted.table, restricted.id) {
+                continue;
+            }
+            let table = &self.tables[&restricted.table];
+            let foreign_key = &table.foreign_keys[restricted.foreign_key];
+            let row = self.planned_row(&changes, &restricted.table, restricted.id)?;
+            let still_referenced = foreign_key
+                .key(&row)
+                .is_some_and(|key| Self::same_key(&key, &restricted.key));
+            if still_referenced && !self.parent_has_key(&changes, foreign_key, &restricted.key)? {
+                return Err(format!(
+                    "{} violated: rows in '{}' still reference {} through {}",
+                    foreign_key.label(),
+                    restricted.table,
+                    describe_key(&restricted.key),
+                    describe_columns(&table.column_names(&foreign_key.columns))
+                )
+                .into());
+            }
+        }
+
+        for (name, rows) in &changes.updated {
+            let table = &self.tables[name];
+            for row in rows.values() {
+                self.check_constraints(table, row)?;
+                self.check_references(&changes, name, row)?;
+            }
+            // Deleted rows are gone before the updates are applied and free their keys
+            let mut replaced: HashSet<RowId> = rows.keys().copied().collect();
+            if let Some(deleted) = changes.deleted.get(name) {
+                replaced.extend(deleted);
+            }
+            let rows: Vec<&Row> = rows.values().collect();
+            table.check_unique(&rows, &replaced)?;
+        }
+
+        let Changes {
+            deleted, updated, ..
+        } = changes;
+        for (name, ids) in deleted {
+            let table = self.tables.get_mut(&name).ok_or("Table not found")?;
+            let ids: Vec<RowId> = ids.into_iter().collect();
+            let rows = table.delete_rows(&ids)?;
+            for (id, row) in ids.into_iter().zip(rows) {
+                self.undo.push(Undo::Deleted {
+                    table: name.clone(),
+                    id,
+                    row,
+                });
+            }
+        }
+        for (name, rows) in updated {
+            let table = self.tables.get_mut(&name).ok_or("Table not found")?;
+            let ids: Vec<RowId> = rows.keys().copied().collect();
+            let replaced = table.update_rows(rows.into_iter().collect())?;
+            for (id, (new_id, row)) in ids.into_iter().zip(replaced) {
+                self.undo.push(Undo::Updated {
+                    table: name.clone(),
+                    id,
+                    new_id,
+                    row,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Check that every foreign key of `table` finds the parent row `row` references, in the
+    // state the planned changes leave behind
+    pub(super) fn check_references(
+        &self,
+        changes: &Changes,
+        table: &str,
+        row: &Row,
+    ) -> DbResult<()> {
+        let table = self.tables.get(table).ok_or("Table not found")?;
+        for foreign_key in &table.foreign_keys {
+            let Some(key) = foreign_key.key(row) else {
+                continue;
+            };
+            let parent = self
+                .tables
+                .get(&foreign_key.parent)
+                .ok_or_else(|| format!("Table '{}' not found", foreign_key.parent))?;
+            // A row may reference itself
+            let referred = Self::referred_columns(foreign_key, parent)?;
+            let own_key = foreign_key.parent == table.name
+                && Self::same_key(&Self::values_of(row, &referred), &key);
+            if !own_key && !self.parent_has_key(changes, foreign_key, &key)? {
+                return Err(format!(
+                    "{} violated: no row in '{}' matches {} for {}",
+                    foreign_key.label(),
+                    foreign_key.parent,
+                    describe_key(&key),
+                    describe_columns(&table.column_names(&foreign_key.columns))
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    fn parent_has_key(
+        &self,
+        changes: &Changes,
+        foreign_key: &ForeignKey,
+        key: &[Value],
+    ) -> DbResult<bool> {
+        let parent = self
+            .tables
+            .get(&foreign_key.parent)
+            .ok_or("Table not found")?;
+        let referred = Self::referred_columns(foreign_key, parent)?;
+        Ok(!self
+            .planned_rows(changes, &foreign_key.parent, &referred, key)?
+            .is_empty())
+    }
+
+    // Rows of `table` whose `columns` hold `key` once the planned changes are applied
+    fn planned_rows(
+        &self,
+        changes: &Changes,
+        table: &str,
+        columns: &[usize],
+        key: &[Value],
+    ) -> DbResult<Vec<(RowId, Row)>> {
+        let stored = self.tables.get(table).ok_or("Table not found")?;
+        let updated = changes.updated.get(table);
+        // An index on the first column narrows down the stored rows
+        let lookup = stored.index_on(columns[0], true).and_then(|index| {
+            let value = Table::exact_key(key[0].clone(), &stored.columns[columns[0]])?;
+            Some(index.equal(&value))
+        });
+        let candidates: Vec<RowId> = match lookup {
+            Some(mut ids) => {
+                ids.extend(updated.into_iter().flat_map(|rows| rows.keys().copied()));
+                ids.sort_unstable();
+                ids.dedup();
+                ids
+            }
+            None => stored
+                .store
+                .scan()
+                .map(|entry| entry.map(|(id, _)| id))
+                .collect::<DbResult<Vec<RowId>>>()?,
+        };
+        let mut rows = Vec::new();
+        for id in candidates {
+            if changes.is_deleted(table, id) {
+                continue;
+            }
+            let row = self.planned_row(changes, table, id)?;
+            if Self::same_key(&Self::values_of(&row, columns), key) {
+                rows.push((id, row));
+            }
+        }
+        Ok(rows)
+    }
+
+    fn planned_row(&self, changes: &Changes, table: &str, id: RowId) -> DbResult<Row> {
+        match changes.updated.get(table).and_then(|rows| rows.get(&id)) {
+            Some(row) => Ok(row.clone()),
+            None => self.tables[table].store.get(id),
+        }
+    }
+
+    // Positions of the parent columns a foreign key references
+    pub(super) fn referred_columns(
+        foreign_key: &ForeignKey,
+        parent: &Table,
+    ) -> DbResult<Vec<usize>> {
+        if foreign_key.referred.is_empty() {
+            if parent.primary_key.is_empty() {
+                return Err(format!(
+                    "{} references table '{}' which has no primary key",
+                    foreign_key.label(),
+                    parent.name
+                )
+                .into());
+            }
+            return Ok(parent.primary_key.clone());
+        }
+        foreign_key
+            .referred
+            .iter()
+            .map(|name| {
+                parent
+                    .columns
+                    .iter()
+                    .position(|column| column.name.value == *name)
+                    .ok_or_else(|| format!("Column '{}' not found", name).into())
+            })
+            .collect()
+    }
+
+    fn values_of(row: &Row, columns: &[usize]) -> Vec<Value> {
+        columns
+            .iter()
+            .map(|&column| row.data[column].clone())
+            .collect()
+    }
+
+    // Values of `columns`, None when one is NULL
+    fn key_of(row: &Row, columns: &[usize]) -> Option<Vec<Value>> {
+        let key = Self::values_of(row, columns);
+        (!key.contains(&Value::Null)).then_some(key)
+    }
+
+    fn same_key(left: &[Value], right: &[Value]) -> bool {
+        left.iter()
+            .zip(right)
+            .all(|(left, right)| left.compare(right) == Some(Ordering::Equal))
+    }
+}
@@ -0,0 +1,167 @@
+// Compact binary encoding of engine values, shared by snapshots and data pages.
+//
+// Every value is a one byte tag followed by its payload in little endian; text and bytes are
+// prefixed with their length.
+
+use super::value::Decimal;
+use super::{DbResult, Row, Value};
+use std::error::Error;
+use std::io::{self, Read};
+
+const NULL: u8 = 0;
+const BOOLEAN: u8 = 1;
+const INTEGER: u8 = 2;
+const FLOAT: u8 = 3;
+const DECIMAL: u8 = 4;
+const TEXT: u8 = 5;
+const BYTES: u8 = 6;
+const DATE: u8 = 7;
+const TIME: u8 = 8;
+const TIMESTAMP: u8 = 9;
+
+pub fn put_u32(data: &mut Vec<u8>, value: usize) -> DbResult<()> {
+    let value = u32::try_from(value).map_err(|_| "Value too large to encode")?;
+    data.extend_from_slice(&value.to_le_bytes());
+    Ok(())
+}
+
+pub fn put_bytes(data: &mut Vec<u8>, bytes: &[u8]) -> DbResult<()> {
+    put_u32(data, bytes.len())?;
+    data.extend_from_slice(bytes);
+    Ok(())
+}
+
+pub fn put_value(data: &mut Vec<u8>, value: &Value) -> DbResult<()> {
+    match value {
+        Value::Null => data.push(NULL),
+        Value::Boolean(b) => data.extend_from_slice(&[BOOLEAN, *b as u8]),
+        Value::Integer(i) => {
+            data.push(INTEGER);
+            data.extend_from_slice(&i.to_le_bytes());
+        }
+        Value::Float(f) => {
+            data.push(FLOAT);
+            data.extend_from_slice(&f.to_bits().to_le_bytes());
+        }
+        Value::Decimal(d) => {
+            data.push(DECIMAL);
+            data.extend_from_slice(&d.mantissa().to_le_bytes());
+            data.extend_from_slice(&d.scale().to_le_bytes());
+        }
+        Value::Text(s) => {
+            data.push(TEXT);
+            put_bytes(data, s.as_bytes())?;
+        }
+        Value::Bytes(b) => {
+            data.push(BYTES);
+            put_bytes(data, b)?;
+        }
+        Value::Date(d) => {
+            data.push(DATE);
+            data.extend_from_slice(&d.to_le_bytes());
+        }
+        Value::Time(t) => {
+            data.push(TIME);
+            data.extend_from_slice(&t.to_le_bytes());
+        }
+        Value::Timestamp(t) => {
+            data.push(TIMESTAMP);
+            data.extend_from_slice(&t.to_le_bytes());
+        }
+    }
+    Ok(())
+}
+
+// A row is just its values one after the other
+pub fn encode_row(row: &Row) -> DbResult<Vec<u8>> {
+    let mut record = Vec::new();
+    for value in &row.data {
+        put_value(&mut record, value)?;
+    }
+    Ok(record)
+}
+
+pub fn decode_row(record: &[u8]) -> DbResult<Row> {
+    let mut reader = Reader::new(record);
+    let mut data = Vec::new();
+    while !reader.is_at_end() {
+        data.push(reader.value()?);
+    }
+    Ok(Row::new(data))
+}
+
+// Decodes from a byte slice or, a value at a time, from a file
+pub struct Reader<R> {
+    source: R,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(source: R) -> Self {
+        Reader { source }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+    pub fn take<const N: usize>(&mut self) -> DbResult<[u8; N]> {
+        let mut bytes = [0; N];
+        self.source.read_exact(&mut bytes).map_err(truncated)?;
+        Ok(bytes)
+    }
+
+    pub fn u32(&mut self) -> DbResult<u32> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn u64(&mut self) -> DbResult<u64> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    pub fn bytes(&mut self) -> DbResult<Vec<u8>> {
+        let length = self.u32()? as usize;
+        // Read rather than allocate up front, a corrupt length must not reserve gigabytes
+        let mut bytes = Vec::new();
+        (&mut self.source)
+            .take(length as u64)
+            .read_to_end(&mut bytes)?;
+        if bytes.len() < length {
+            return Err("Unexpected end of encoded data".into());
+        }
+        Ok(bytes)
+    }
+
+    pub fn value(&mut self) -> DbResult<Value> {
+        let [tag] = self.take()?;
+        Ok(match tag {
+            NULL => Value::Null,
+            BOOLEAN => Value::Boolean(self.take::<1>()? != [0]),
+            INTEGER => Value::Integer(i64::from_le_bytes(self.take()?)),
+            FLOAT => Value::Float(f64::from_bits(self.u64()?)),
+            DECIMAL => {
+                let mantissa = i128::from_le_bytes(self.take()?);
+                Value::Decimal(Decimal::new(mantissa, self.u32()?))
+            }
+            TEXT => Value::Text(String::from_utf8(self.bytes()?)?),
+            BYTES => Value::Bytes(self.bytes()?),
+            DATE => Value::Date(i32::from_le_bytes(self.take()?)),
+            TIME => Value::Time(i64::from_le_bytes(self.take()?)),
+            TIMESTAMP => Value::Timestamp(i64::from_le_bytes(self.take()?)),
+            other => return Err(format!("Unknown value tag {}", other).into()),
+        })
+    }
+}
+
+impl Reader<&[u8]> {
+    pub fn is_at_end(&self) -> bool {
+        self.source.is_empty()
+    }
+}
+
+fn truncated(e: io::Error) -> Box<dyn Error> {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        "Unexpected end of encoded data".into()
+    } else {
+        e.into()
+    }
+}
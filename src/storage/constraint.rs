@@ -0,0 +1,281 @@
+// Integrity constraints declared by CREATE TABLE, on a column or for the whole table.
+//
+// PRIMARY KEY and UNIQUE constraints are enforced by unique indexes the table builds for them,
+// NOT NULL by checking every row before it is stored. A primary key also makes its columns
+// NOT NULL. CHECK conditions need the expression evaluator and foreign keys involve two tables,
+// so the database enforces those. Errors name the violated constraint and the column.
+
+use super::index::{IndexDef, IndexKind};
+use super::{DbResult, Row, Value};
+use sqlparser::ast::{ColumnDef, ColumnOption, Expr, Ident, ReferentialAction, TableConstraint};
+
+// Name of a primary key declared without one, as in MySQL
+const PRIMARY_KEY_NAME: &str = "PRIMARY";
+
+// What a table definition asks to enforce
+pub struct Constraints {
+    pub not_null: Vec<NotNull>,
+    // Unique indexes for the PRIMARY KEY and UNIQUE constraints
+    pub keys: Vec<IndexDef>,
+    // Positions of the primary key columns, empty without a primary key
+    pub primary_key: Vec<usize>,
+    pub foreign_keys: Vec<ForeignKey>,
+    pub checks: Vec<Check>,
+}
+
+// A column that rejects NULL
+pub struct NotNull {
+    position: usize,
+    // How errors refer to the constraint, e.g. `PRIMARY KEY constraint 'PRIMARY'`
+    label: String,
+}
+
+impl NotNull {
+    pub fn check(&self, columns: &[ColumnDef], row: &Row) -> DbResult<()> {
+        if row.data[self.position] == Value::Null {
+            return Err(format!(
+                "{} violated: column '{}' cannot be null",
+                self.label, columns[self.position].name
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+// Columns whose values must match a row of the parent table, unless one of them is NULL
+pub struct ForeignKey {
+    pub name: String,
+    // Positions of the referencing columns
+    pub columns: Vec<usize>,
+    pub parent: String,
+    // Referenced columns of the parent, its primary key when none are named
+    pub referred: Vec<String>,
+    pub on_delete: ReferentialAction,
+    pub on_update: ReferentialAction,
+}
+
+impl ForeignKey {
+    pub fn label(&self) -> String {
+        format!("FOREIGN KEY constraint '{}'", self.name)
+    }
+
+    // Values of the referencing columns, None when one is NULL and nothing is referenced
+    pub fn key(&self, row: &Row) -> Option<Vec<Value>> {
+        let key: Vec<Value> = self
+            .columns
+            .iter()
+            .map(|&position| row.data[position].clone())
+            .collect();
+        (!key.contains(&Value::Null)).then_some(key)
+    }
+}
+
+// A condition every row has to meet. Like in a WHERE clause an unknown (NULL) result is not a
+// failure, unlike there it lets the row in.
+pub struct Check {
+    pub name: String,
+    pub expr: Expr,
+    // Column the CHECK was declared on, None for a table-level one
+    pub column: Option<usize>,
+}
+
+impl Check {
+    pub fn label(&self) -> String {
+        format!("CHECK constraint '{}'", self.name)
+    }
+}
+
+impl Constraints {
+    // The constraints of `table` with `columns` and the table-level `constraints`
+    pub fn derive(
+        table: &str,
+        columns: &[ColumnDef],
+        constraints: &[TableConstraint],
+    ) -> DbResult<Self> {
+        let mut derived = Constraints {
+            not_null: Vec::new(),
+            keys: Vec::new(),
+            primary_key: Vec::new(),
+            foreign_keys: Vec::new(),
+            checks: Vec::new(),
+        };
+        for (position, column) in columns.iter().enumerate() {
+            for option in &column.options {
+                match &option.option {
+                    ColumnOption::NotNull => {
+                        let label = match &option.name {
+                            Some(name) => format!("NOT NULL constraint '{}'", name),
+                            None => "NOT NULL constraint".to_string(),
+                        };
+                        derived.not_null.push(NotNull { position, label });
+                    }
+                    ColumnOption::Unique { is_primary } => derived.add_key(
+                        columns,
+                        option.name.as_ref(),
+                        std::slice::from_ref(&column.name),
+                        *is_primary,
+                    )?,
+                    // Same as the table-level constraint on just this column
+                    ColumnOption::ForeignKey {
+                        foreign_table,
+                        referred_columns,
+                        on_delete,
+                        on_update,
+                    } => derived.add_foreign_key(
+                        table,
+                        columns,
+                        &TableConstraint::ForeignKey {
+                            name: option.name.clone(),
+                            columns: vec![column.name.clone()],
+                            foreign_table: foreign_table.clone(),
+                            referred_columns: referred_columns.clone(),
+                            on_delete: *on_delete,
+                            on_update: *on_update,
+                        },
+                    )?,
+                    ColumnOption::Check(expr) => {
+                        derived.add_check(table, option.name.as_ref(), expr, Some(position))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for constraint in constraints {
+            match constraint {
+                TableConstraint::Unique {
+                    name,
+                    columns: key_columns,
+                    is_primary,
+                } => derived.add_key(columns, name.as_ref(), key_columns, *is_primary)?,
+                TableConstraint::ForeignKey { .. } => {
+                    derived.add_foreign_key(table, columns, constraint)?
+                }
+                TableConstraint::Check { name, expr } => {
+                    derived.add_check(table, name.as_ref(), expr, None)
+                }
+                _ => {}
+            }
+        }
+        Ok(derived)
+    }
+
+    fn add_key(
+        &mut self,
+        columns: &[ColumnDef],
+        name: Option<&Ident>,
+        key_columns: &[Ident],
+        is_primary: bool,
+    ) -> DbResult<()> {
+        let name = match name {
+            Some(name) => name.value.clone(),
+            None if is_primary => PRIMARY_KEY_NAME.to_string(),
+            // MySQL names an anonymous unique key after its first column
+            None => key_columns
+                .first()
+                .map_or(String::new(), |c| c.value.clone()),
+        };
+        let kind = if is_primary { "PRIMARY KEY" } else { "UNIQUE" };
+        let label = format!("{} constraint '{}'", kind, name);
+        if is_primary {
+            if !self.primary_key.is_empty() {
+                return Err("Multiple primary keys defined".into());
+            }
+            for column in key_columns {
+                let position = position(columns, column)?;
+                self.primary_key.push(position);
+                self.not_null.push(NotNull {
+                    position,
+                    label: label.clone(),
+                });
+            }
+        }
+        self.keys.push(IndexDef {
+            name,
+            columns: key_columns.iter().map(|c| c.value.clone()).collect(),
+            unique: true,
+            kind: IndexKind::BTree,
+            constraint: Some(label),
+        });
+        Ok(())
+    }
+
+    fn add_check(&mut self, table: &str, name: Option<&Ident>, expr: &Expr, column: Option<usize>) {
+        // MySQL numbers anonymous checks per table
+        let name = match name {
+            Some(name) => name.value.clone(),
+            None => format!("{}_chk_{}", table, self.checks.len() + 1),
+        };
+        self.checks.push(Check {
+            name,
+            expr: expr.clone(),
+            column,
+        });
+    }
+
+    fn add_foreign_key(
+        &mut self,
+        table: &str,
+        columns: &[ColumnDef],
+        constraint: &TableConstraint,
+    ) -> DbResult<()> {
+        let TableConstraint::ForeignKey {
+            name,
+            columns: key_columns,
+            foreign_table,
+            referred_columns: referred,
+            on_delete,
+            on_update,
+        } = constraint
+        else {
+            return Ok(());
+        };
+        // MySQL numbers anonymous foreign keys per table
+        let name = match name {
+            Some(name) => name.value.clone(),
+            None => format!("{}_ibfk_{}", table, self.foreign_keys.len() + 1),
+        };
+        if !referred.is_empty() && referred.len() != key_columns.len() {
+            return Err(format!(
+                "FOREIGN KEY constraint '{}' references {} columns with {}",
+                name,
+                referred.len(),
+                key_columns.len()
+            )
+            .into());
+        }
+        self.foreign_keys.push(ForeignKey {
+            name,
+            columns: key_columns
+                .iter()
+                .map(|column| position(columns, column))
+                .collect::<DbResult<Vec<usize>>>()?,
+            parent: foreign_table.to_string(),
+            referred: referred.iter().map(|c| c.value.clone()).collect(),
+            on_delete: on_delete.unwrap_or(ReferentialAction::NoAction),
+            on_update: on_update.unwrap_or(ReferentialAction::NoAction),
+        });
+        Ok(())
+    }
+}
+
+// How errors refer to some columns: `column 'a'` or `columns 'a, b'`
+pub fn describe_columns(names: &[String]) -> String {
+    match names {
+        [name] => format!("column '{}'", name),
+        names => format!("columns '{}'", names.join(", ")),
+    }
+}
+
+// How errors show the values of a key: `'1'` or `'1-2'`, as MySQL does
+pub fn describe_key(values: &[Value]) -> String {
+    let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+    format!("'{}'", values.join("-"))
+}
+
+fn position(columns: &[ColumnDef], name: &Ident) -> DbResult<usize> {
+    columns
+        .iter()
+        .position(|column| column.name.value == name.value)
+        .ok_or_else(|| format!("Column '{}' not found", name).into())
+}
@@ -0,0 +1,263 @@
+// Secondary indexes: maps from the values of some columns to the ids of the rows holding them,
+// kept up to date by every change to the table. Ordered indexes answer equality and range
+// lookups, hash indexes only equality but in constant time.
+
+use super::constraint::{describe_columns, describe_key};
+use super::store::RowId;
+use super::{DbResult, Row, Value};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::ops::Bound;
+
+// What an index is made of, enough to rebuild it from the rows
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexDef {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
+    #[serde(default)]
+    pub kind: IndexKind,
+    // How errors refer to the PRIMARY KEY or UNIQUE constraint the index enforces. Such indexes
+    // are derived from the table definition, so snapshots do not store them.
+    #[serde(skip)]
+    pub constraint: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum IndexKind {
+    #[default]
+    BTree,
+    Hash,
+}
+
+pub struct Index {
+    pub def: IndexDef,
+    // Positions of the indexed columns in the table
+    positions: Vec<usize>,
+    entries: Entries,
+}
+
+enum Entries {
+    Ordered(BTreeMap<Key, Vec<RowId>>),
+    Hashed(HashMap<Key, Vec<RowId>>),
+}
+
+impl Entries {
+    fn get(&self, key: &Key) -> Option<&Vec<RowId>> {
+        match self {
+            Entries::Ordered(map) => map.get(key),
+            Entries::Hashed(map) => map.get(key),
+        }
+    }
+
+    fn insert(&mut self, key: Key, id: RowId) {
+        match self {
+            Entries::Ordered(map) => map.entry(key).or_default().push(id),
+            Entries::Hashed(map) => map.entry(key).or_default().push(id),
+        }
+    }
+
+    fn remove(&mut self, key: &Key, id: RowId) {
+        let ids = match self {
+            Entries::Ordered(map) => map.get_mut(key),
+            Entries::Hashed(map) => map.get_mut(key),
+        };
+        let Some(ids) = ids else {
+            return;
+        };
+        ids.retain(|other| *other != id);
+        if ids.is_empty() {
+            match self {
+                Entries::Ordered(map) => map.remove(key),
+                Entries::Hashed(map) => map.remove(key),
+            };
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Entries::Ordered(map) => map.clear(),
+            Entries::Hashed(map) => map.clear(),
+        }
+    }
+}
+
+// Index key, ordered like ORDER BY orders the values
+#[derive(Debug, Clone)]
+struct Key(Vec<Value>);
+
+// Consistent with the ordering: values that compare equal hash the same. Column values share
+// the column type, so only the two float zeros need care.
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for value in &self.0 {
+            match value {
+                Value::Float(f) if *f == 0.0 => Value::Float(0.0).hash(state),
+                value => value.hash(state),
+            }
+        }
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .iter()
+            .zip(&other.0)
+            .map(|(a, b)| a.total_cmp(b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| self.0.len().cmp(&other.0.len()))
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Key {}
+
+impl Index {
+    pub fn new(def: IndexDef, positions: Vec<usize>) -> Self {
+        let entries = match def.kind {
+            IndexKind::BTree => Entries::Ordered(BTreeMap::new()),
+            IndexKind::Hash => Entries::Hashed(HashMap::new()),
+        };
+        Index {
+            def,
+            positions,
+            entries,
+        }
+    }
+
+    // Whether this is the index created with `name`, not one enforcing a constraint
+    pub fn is_named(&self, name: &str) -> bool {
+        self.def.constraint.is_none() && self.def.name == name
+    }
+
+    fn key(&self, row: &Row) -> Key {
+        Key(self
+            .positions
+            .iter()
+            .map(|&position| row.data[position].clone())
+            .collect())
+    }
+
+    // Whether lookups can use this index for the column at `position`: ordered indexes serve
+    // their first column, hash indexes only equality on their single column
+    pub fn serves(&self, position: usize, equality: bool) -> bool {
+        self.positions[0] == position
+            && match self.def.kind {
+                IndexKind::BTree => true,
+                IndexKind::Hash => equality && self.positions.len() == 1,
+            }
+    }
+
+    pub fn insert(&mut self, row: &Row, id: RowId) {
+        let key = self.key(row);
+        self.entries.insert(key, id);
+    }
+
+    pub fn remove(&mut self, row: &Row, id: RowId) {
+        self.entries.remove(&self.key(row), id);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // For a unique index, fail when two of `rows`, or one of them and a row already indexed,
+    // share a key. Indexed rows in `replaced` are about to change and do not count. Keys
+    // containing NULL never collide.
+    pub fn check_unique(&self, rows: &[&Row], replaced: &HashSet<RowId>) -> DbResult<()> {
+        if !self.def.unique {
+            return Ok(());
+        }
+        let mut seen = BTreeSet::new();
+        for row in rows {
+            let key = self.key(row);
+            if key.0.contains(&Value::Null) {
+                continue;
+            }
+            let taken = self
+                .entries
+                .get(&key)
+                .is_some_and(|ids| ids.iter().any(|id| !replaced.contains(id)));
+            if taken || !seen.insert(key.clone()) {
+                let values = describe_key(&key.0);
+                return Err(match &self.def.constraint {
+                    Some(label) => format!(
+                        "{} violated: duplicate entry {} for {}",
+                        label,
+                        values,
+                        describe_columns(&self.def.columns)
+                    ),
+                    None => format!("Duplicate entry {} for key '{}'", values, self.def.name),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    // Rows holding the same key as `row`, none when the key contains NULL
+    pub fn find(&self, row: &Row) -> Vec<RowId> {
+        let key = self.key(row);
+        if key.0.contains(&Value::Null) {
+            return Vec::new();
+        }
+        self.entries.get(&key).cloned().unwrap_or_default()
+    }
+
+    // Rows whose first indexed column equals `value`
+    pub fn equal(&self, value: &Value) -> Vec<RowId> {
+        match &self.entries {
+            Entries::Hashed(map) => map
+                .get(&Key(vec![value.clone()]))
+                .cloned()
+                .unwrap_or_default(),
+            Entries::Ordered(_) => self
+                .range(Bound::Included(value), Bound::Included(value))
+                .unwrap_or_default(),
+        }
+    }
+
+    // Rows whose first indexed column lies within the bounds, NULLs never do. None for a hash
+    // index, which has no order.
+    pub fn range(&self, lower: Bound<&Value>, upper: Bound<&Value>) -> Option<Vec<RowId>> {
+        let Entries::Ordered(entries) = &self.entries else {
+            return None;
+        };
+        let start = match lower {
+            Bound::Included(value) | Bound::Excluded(value) => {
+                Bound::Included(Key(vec![value.clone()]))
+            }
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let ids = entries
+            .range((start, Bound::Unbounded))
+            .map(|(key, ids)| (&key.0[0], ids))
+            .filter(|(first, _)| **first != Value::Null)
+            .skip_while(|(first, _)| match lower {
+                Bound::Excluded(value) => first.total_cmp(value).is_eq(),
+                _ => false,
+            })
+            .take_while(|(first, _)| match upper {
+                Bound::Included(value) => first.total_cmp(value).is_le(),
+                Bound::Excluded(value) => first.total_cmp(value).is_lt(),
+                Bound::Unbounded => true,
+            })
+            .flat_map(|(_, ids)| ids.iter().copied())
+            .collect();
+        Some(ids)
+    }
+}
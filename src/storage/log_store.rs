@@ -0,0 +1,143 @@
+// Table rows stored in an append-only log file.
+//
+// Every insert and update appends the new version of the row as `[length: u32][row]`; only the
+// offset of the latest version of every live row is kept in memory. Rows never move, so their
+// ids stay stable across updates. Old versions are not reclaimed until the table is emptied or
+// the database restarts.
+
+use super::codec::{decode_row, encode_row};
+use super::store::{self, RowId, RowIter, StorageEngine, TableStore};
+use super::{DbResult, Row};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+// Keeps every table in its own log file in `dir`
+pub struct LogEngine {
+    dir: PathBuf,
+}
+
+impl LogEngine {
+    pub fn open(dir: PathBuf) -> DbResult<Self> {
+        store::reset_dir(&dir)?;
+        Ok(LogEngine { dir })
+    }
+}
+
+impl StorageEngine for LogEngine {
+    fn create_store(&self, table: &str) -> DbResult<Box<dyn TableStore>> {
+        let path = self.dir.join(format!("{}.log", table));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(Box::new(LogStore {
+            path,
+            file: Mutex::new(file),
+            offsets: BTreeMap::new(),
+            next_id: 0,
+            end: 0,
+        }))
+    }
+}
+
+pub struct LogStore {
+    path: PathBuf,
+    file: Mutex<File>,
+    // Where the latest version of every live row starts
+    offsets: BTreeMap<RowId, u64>,
+    next_id: RowId,
+    end: u64,
+}
+
+impl LogStore {
+    fn file(&mut self) -> DbResult<&mut File> {
+        self.file
+            .get_mut()
+            .map_err(|_| "Log file lock poisoned".into())
+    }
+
+    fn append(&mut self, row: &Row) -> DbResult<u64> {
+        let record = encode_row(row)?;
+        let length = u32::try_from(record.len()).map_err(|_| "Row too large for the log")?;
+        let offset = self.end;
+        let file = self.file()?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&length.to_le_bytes())?;
+        file.write_all(&record)?;
+        self.end += 4 + record.len() as u64;
+        Ok(offset)
+    }
+
+    fn read(&self, offset: u64) -> DbResult<Row> {
+        let mut file = self.file.lock().map_err(|_| "Log file lock poisoned")?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut length = [0; 4];
+        file.read_exact(&mut length)?;
+        let mut record = vec![0; u32::from_le_bytes(length) as usize];
+        file.read_exact(&mut record)?;
+        decode_row(&record)
+    }
+}
+
+impl TableStore for LogStore {
+    fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    fn scan(&self) -> RowIter<'_> {
+        Box::new(
+            self.offsets
+                .iter()
+                .map(|(id, offset)| Ok((*id, self.read(*offset)?))),
+        )
+    }
+
+    fn get(&self, id: RowId) -> DbResult<Row> {
+        self.read(*self.offsets.get(&id).ok_or("Row not found")?)
+    }
+
+    fn lookup(&self, ids: Vec<RowId>) -> RowIter<'_> {
+        Box::new(store::sorted_ids(ids).map(|id| Ok((id, self.get(id)?))))
+    }
+
+    fn insert(&mut self, row: Row) -> DbResult<RowId> {
+        let offset = self.append(&row)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.offsets.insert(id, offset);
+        Ok(id)
+    }
+
+    fn update(&mut self, id: RowId, row: Row) -> DbResult<RowId> {
+        if !self.offsets.contains_key(&id) {
+            return Err("Row not found".into());
+        }
+        let offset = self.append(&row)?;
+        self.offsets.insert(id, offset);
+        Ok(id)
+    }
+
+    fn delete(&mut self, id: RowId) -> DbResult<()> {
+        self.offsets.remove(&id).ok_or("Row not found")?;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> DbResult<()> {
+        self.file()?.set_len(0)?;
+        self.offsets.clear();
+        self.end = 0;
+        Ok(())
+    }
+}
+
+impl Drop for LogStore {
+    // Like page files, the log is only working storage
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
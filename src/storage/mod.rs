@@ -1,17 +1,98 @@
 use serde::{Deserialize, Serialize};
 use sqlparser::ast::SetExpr::Values;
 use sqlparser::ast::{
-    BinaryOperator, ColumnDef, DataType, Expr, Function, FunctionArg, FunctionArgExpr, ObjectName,
-    ObjectType, Offset, Query, SelectItem, SetExpr, Statement, TableFactor, Value, Values as Val,
+    Assignment, BinaryOperator, ColumnDef, ColumnOption, ConflictTarget, DoUpdate, Expr, Function,
+    FunctionArg, FunctionArgExpr, Ident, Join, JoinConstraint, JoinOperator, ObjectName,
+    ObjectType, Offset, OnConflict, OnConflictAction, OnInsert, OrderByExpr, Query, SelectItem,
+    SetExpr, Statement, TableAlias, TableConstraint, TableFactor, TableWithJoins, UnaryOperator,
+    Value as SqlValue,
 };
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::fs;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+mod changes;
+mod codec;
+mod constraint;
+mod index;
+mod log_store;
+mod paged;
+mod pattern;
+mod session;
+mod snapshot;
+mod store;
+mod transaction;
+mod undo;
+mod value;
+mod wal;
+
+use changes::Changes;
+use constraint::{describe_columns, describe_key, Check, Constraints, ForeignKey, NotNull};
+use index::{Index, IndexDef, IndexKind};
+use log_store::LogEngine;
+use paged::PagedEngine;
+pub use session::Session;
+use store::{MemoryEngine, RowId, RowIter, StorageEngine, TableStore};
+use transaction::{Command, Transaction};
+use undo::Undo;
+use value::Arithmetic;
+pub use value::Value;
+use wal::Wal;
+
+// Name of the write-ahead log inside the data directory
+const WAL_FILE: &str = "wal.log";
+// Number of WAL records after which a checkpoint is taken automatically
+const DEFAULT_CHECKPOINT_INTERVAL: u64 = 1000;
+// Directories inside the data directory that hold the files of paged and log tables
+const PAGES_DIR: &str = "pages";
+const LOGS_DIR: &str = "logs";
+// Pages cached per paged table unless configured otherwise
+const DEFAULT_BUFFER_PAGES: usize = 64;
+// Names of the built-in storage engines
+const MEMORY_ENGINE: &str = "memory";
+const PAGED_ENGINE: &str = "paged";
+const LOG_ENGINE: &str = "log";
+// Qualifier of the proposed row in the assignments of an upsert
+const EXCLUDED: &str = "excluded";
 
 // Main database struct
-#[derive(Clone)]
 pub struct Database {
     tables: HashMap<String, Table>,
+    // Only present for a database opened from a data directory
+    durability: Option<Durability>,
+    // Storage engines by name, the ones keeping files only exist with a data directory
+    engines: HashMap<String, Box<dyn StorageEngine>>,
+    // Engine of tables created without an ENGINE clause
+    default_engine: String,
+    // Row changes of the statement being executed, or of the open transaction, taken back when
+    // it fails or rolls back
+    undo: Vec<Undo>,
+    transaction: Option<Transaction>,
+    // Transactions started so far, which numbers the open one
+    transactions: u64,
+    // Problems the database worked around, such as a failed automatic checkpoint, until the
+    // caller takes them to report them
+    warnings: Vec<String>,
+}
+
+// Where the rows of tables created without an ENGINE clause are kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Storage {
+    Memory,
+    // Slotted pages in one file per table, at most `buffer_pages` pages of each are cached
+    Paged { buffer_pages: usize },
+}
+
+// On-disk state of a durable database: the latest snapshot plus the WAL written since
+struct Durability {
+    data_dir: PathBuf,
+    wal: Wal,
+    checkpoint_interval: u64,
+    // WAL records at which an automatic checkpoint that failed is tried again, 0 if none failed
+    checkpoint_retry: u64,
 }
 
 // Result type for database operations
@@ -23,33 +104,251 @@ impl Database {
         // Implementation details are up to you
         Database {
             tables: HashMap::new(),
+            durability: None,
+            engines: HashMap::from([(
+                MEMORY_ENGINE.to_string(),
+                Box::new(MemoryEngine) as Box<dyn StorageEngine>,
+            )]),
+            default_engine: MEMORY_ENGINE.to_string(),
+            undo: Vec::new(),
+            transaction: None,
+            transactions: 0,
+            warnings: Vec::new(),
+        }
+    }
+
+    // Open a durable database stored in `data_dir`, keeping its rows in memory
+    pub fn open(data_dir: impl AsRef<Path>) -> DbResult<Self> {
+        Self::open_with(data_dir, Storage::Memory)
+    }
+
+    // Open a durable database stored in `data_dir`: load the latest snapshot, then replay the
+    // write-ahead log records it does not cover yet
+    pub fn open_with(data_dir: impl AsRef<Path>, storage: Storage) -> DbResult<Self> {
+        let data_dir = data_dir.as_ref();
+        fs::create_dir_all(data_dir)?;
+        let mut database = Database::new();
+        let buffer_pages = match storage {
+            Storage::Memory => DEFAULT_BUFFER_PAGES,
+            Storage::Paged { buffer_pages } => {
+                database.default_engine = PAGED_ENGINE.to_string();
+                buffer_pages
+            }
+        };
+        database.engines.insert(
+            PAGED_ENGINE.to_string(),
+            Box::new(PagedEngine::open(data_dir.join(PAGES_DIR), buffer_pages)?),
+        );
+        database.engines.insert(
+            LOG_ENGINE.to_string(),
+            Box::new(LogEngine::open(data_dir.join(LOGS_DIR))?),
+        );
+
+        let (lsn, tables) = snapshot::read(data_dir, |name, columns, constraints, engine| {
+            database.new_table(name, columns, constraints, engine.as_deref())
+        })?
+        .unwrap_or_default();
+        database.tables = tables;
+        let wal_path = data_dir.join(WAL_FILE);
+        let (wal, statements) = Wal::open(&wal_path, lsn)?;
+        if wal.discarded() > 0 {
+            database.warnings.push(format!(
+                "Discarded {} bytes of incomplete records at the end of {}",
+                wal.discarded(),
+                wal_path.display()
+            ));
+        }
+        for (index, sql) in statements.iter().enumerate() {
+            database
+                .execute(sql)
+                .map_err(|e| format!("Failed to replay WAL record {}: {}", index + 1, e))?;
         }
+        // The log ends inside a transaction whose COMMIT never made it to disk
+        if database.in_transaction() {
+            database
+                .warnings
+                .push("Rolled back a transaction cut short at the end of the WAL".to_string());
+            database.rollback()?;
+        }
+        database.durability = Some(Durability {
+            data_dir: data_dir.to_path_buf(),
+            wal,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            checkpoint_retry: 0,
+        });
+        Ok(database)
+    }
+
+    // Warnings since the last call, oldest first
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    // Take a checkpoint automatically once the WAL holds `records` records, 0 disables it
+    pub fn set_checkpoint_interval(&mut self, records: u64) {
+        if let Some(durability) = &mut self.durability {
+            durability.checkpoint_interval = records;
+        }
+    }
+
+    // Make every later WAL write fail, as on a broken disk
+    #[cfg(test)]
+    pub(crate) fn break_wal(&mut self) -> DbResult<()> {
+        if let Some(durability) = &mut self.durability {
+            durability.wal.break_writes()?;
+        }
+        Ok(())
+    }
+
+    // Write a snapshot of every table and truncate the WAL it makes redundant
+    pub fn checkpoint(&mut self) -> DbResult<QueryResult> {
+        // The snapshot would contain changes that may still be rolled back
+        if self.in_transaction() {
+            return Err("CHECKPOINT cannot be used in a transaction".into());
+        }
+        let durability = self
+            .durability
+            .as_mut()
+            .ok_or("CHECKPOINT requires a database opened from a data directory")?;
+        snapshot::write(
+            &durability.data_dir,
+            durability.wal.last_lsn(),
+            &self.tables,
+        )?;
+        // A crash before the truncation is harmless, the snapshot's LSN skips the old records
+        durability.wal.truncate()?;
+        durability.checkpoint_retry = 0;
+        Ok(QueryResult::Success("Checkpoint completed".to_string()))
     }
 
     // Execute a SQL statement
     pub fn execute(&mut self, sql: &str) -> DbResult<QueryResult> {
+        // CHECKPOINT and some transaction statements are not known to the SQL parser
+        if sql
+            .trim()
+            .trim_end_matches(';')
+            .trim_end()
+            .eq_ignore_ascii_case("CHECKPOINT")
+        {
+            return self.checkpoint();
+        }
+        match Command::parse(sql) {
+            Some(Command::RollbackTo(name)) => return self.rollback_to_savepoint(&name),
+            Some(Command::Release(name)) => return self.release_savepoint(&name),
+            None => {}
+        }
         // Parse the SQL statement using sqlparser
         let ast = sqlparser::parser::Parser::parse_sql(&sqlparser::dialect::MySqlDialect {}, sql);
         match ast {
-            Ok(ast) => self.execute_statement(&ast[0]),
+            Ok(ast) => {
+                let stmt = ast.first().ok_or("Empty query")?;
+                // Schema changes cannot be rolled back, they commit an open transaction first
+                // as in MySQL
+                if Self::is_schema_change(stmt) {
+                    self.commit()?;
+                }
+                // A statement that fails leaves nothing behind, inside a transaction the
+                // statements before it stay
+                let mark = self.undo.len();
+                let result = match self.execute_statement(stmt) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        self.rollback_to(mark)?;
+                        return Err(e);
+                    }
+                };
+                // Only successful changes are logged, so replaying the log cannot fail. A
+                // change that cannot be logged is taken back, it would be lost on restart.
+                if Self::is_mutating(stmt) {
+                    if let Err(e) = self.record(sql) {
+                        self.rollback_to(mark)?;
+                        return Err(e);
+                    }
+                }
+                if !self.in_transaction() {
+                    self.undo.clear();
+                }
+                Ok(result)
+            }
             Err(e) => {
                 println!("{}", e);
-                return Err(format!("{}", e).into());
+                Err(format!("{}", e).into())
             }
         }
 
         // Execute the parsed statement
     }
 
+    // Append statements to the WAL of a durable database, taking a checkpoint when it is due.
+    // The statements are durable once appended, a failed checkpoint only leaves a warning and is
+    // tried again after another interval.
+    fn log(&mut self, statements: &[&str]) -> DbResult<()> {
+        if let Some(durability) = &mut self.durability {
+            durability.wal.append(statements)?;
+            let interval = durability.checkpoint_interval;
+            let records = durability.wal.records();
+            if interval > 0 && records >= interval.max(durability.checkpoint_retry) {
+                if let Err(e) = self.checkpoint() {
+                    self.warnings
+                        .push(format!("Automatic checkpoint failed: {}", e));
+                    if let Some(durability) = &mut self.durability {
+                        durability.checkpoint_retry = records + interval;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     // Internal method to execute a parsed statement
     fn execute_statement(&mut self, stmt: &Statement) -> DbResult<QueryResult> {
         match stmt {
-            Statement::CreateTable { name, columns, .. } => {
-                self.create_table(name.to_string(), columns)
+            Statement::CreateTable {
+                name,
+                columns,
+                constraints,
+                engine,
+                ..
+            } => self.create_table(name.to_string(), columns, constraints, engine.as_deref()),
+            Statement::CreateIndex {
+                name,
+                table_name,
+                using,
+                columns,
+                unique,
+                if_not_exists,
+            } => {
+                let kind = match using.as_ref().map(|using| using.value.to_lowercase()) {
+                    None => IndexKind::BTree,
+                    Some(kind) if kind == "btree" => IndexKind::BTree,
+                    Some(kind) if kind == "hash" => IndexKind::Hash,
+                    Some(kind) => return Err(format!("Unsupported index type '{}'", kind).into()),
+                };
+                self.create_index(
+                    name.to_string(),
+                    table_name.to_string(),
+                    columns,
+                    *unique,
+                    kind,
+                    *if_not_exists,
+                )
             }
             Statement::Insert {
-                table_name, source, ..
-            } => self.insert(table_name, source),
+                table_name,
+                columns,
+                source,
+                on,
+                ..
+            } => self.insert(table_name, columns, source, on.as_ref()),
+            Statement::Update {
+                table,
+                assignments,
+                selection,
+                ..
+            } => self.update(table, assignments, selection),
+            Statement::Delete {
+                from, selection, ..
+            } => self.delete(from, selection),
             Statement::Drop {
                 object_type,
                 if_exists,
@@ -57,121 +356,157 @@ impl Database {
                 ..
             } => match object_type {
                 ObjectType::Table => self.drop_table(names[0].to_string(), *if_exists),
+                ObjectType::Index => self.drop_index(names[0].to_string(), *if_exists),
                 _ => {
                     unimplemented!("It will probably be not implemented")
                 }
             },
             Statement::Query(query) => self.select(*query.clone()),
+            Statement::StartTransaction { .. } => self.begin(),
+            Statement::Commit { chain } => {
+                let result = self.commit()?;
+                if *chain {
+                    self.begin()?;
+                }
+                Ok(result)
+            }
+            Statement::Rollback { chain } => {
+                let result = self.rollback()?;
+                if *chain {
+                    self.begin()?;
+                }
+                Ok(result)
+            }
+            Statement::Savepoint { name } => self.savepoint(&name.value),
             _ => Err("Unimplemented".into()),
         }
     }
 
-    pub fn select(&mut self, query: Query) -> DbResult<QueryResult> {
-        let (table_name, select_items, group_by, having) = match &*query.body {
-            SetExpr::Select(select) => {
-                let table_name = if let Some(table_with_join) = select.from.first() {
-                    match &table_with_join.relation {
-                        TableFactor::Table { name, .. } => name.to_string(),
-                        _ => return Err("Unsupported FROM clause".into()),
-                    }
-                } else {
-                    return Err("No table specified in FROM clause".into());
-                };
-                (
-                    table_name,
-                    select.projection.clone(),
-                    select.group_by.clone(),
-                    select.having.clone(),
-                )
-            }
+    // Statements that change the schema, which transactions cannot take back
+    fn is_schema_change(stmt: &Statement) -> bool {
+        matches!(
+            stmt,
+            Statement::CreateTable { .. } | Statement::CreateIndex { .. } | Statement::Drop { .. }
+        )
+    }
+
+    // Statements that change the database and therefore go to the write-ahead log
+    fn is_mutating(stmt: &Statement) -> bool {
+        matches!(
+            stmt,
+            Statement::CreateTable { .. }
+                | Statement::CreateIndex { .. }
+                | Statement::Insert { .. }
+                | Statement::Update { .. }
+                | Statement::Delete { .. }
+                | Statement::Drop { .. }
+        )
+    }
+
+    pub fn select(&self, query: Query) -> DbResult<QueryResult> {
+        Ok(QueryResult::Rows(self.run_query(&query, None)?))
+    }
+
+    // Run a query. `outer` is the current row of the enclosing query when this is a subquery,
+    // correlated column references resolve against it.
+    fn run_query(&self, query: &Query, outer: Option<&OuterRow>) -> DbResult<SelectResult> {
+        let select = match &*query.body {
+            SetExpr::Select(select) => select,
             _ => return Err("Unsupported query type".into()),
         };
+        if select.from.is_empty() {
+            return Err("No table specified in FROM clause".into());
+        }
 
-        let table = self.tables.get(&table_name).ok_or("Table not found")?;
+        let (columns, rows) = self.scan_from(&select.from, select.selection.as_ref(), outer)?;
+        let scope = Scope {
+            columns: &columns,
+            outer,
+            group: None,
+        };
+        let mut select_columns = Self::plan_projection(&select.projection, &columns)?;
 
-        let select_columns: Vec<(String, Option<Function>)> = if select_items
-            .iter()
-            .any(|item| matches!(item, SelectItem::Wildcard(..)))
-        {
-            table
-                .columns
-                .iter()
-                .map(|v| Ok((v.name.to_string().clone(), None)))
-                .collect::<Result<Vec<_>, Box<dyn Error>>>()?
-        } else {
-            select_items
+        let mut filtered_rows = Vec::new();
+        for row in rows {
+            if let Some(selection) = &select.selection {
+                if !self.evaluate_condition(selection, &row, &scope)? {
+                    continue;
+                }
+            }
+            filtered_rows.push(row);
+        }
+
+        // Aggregates without GROUP BY collapse the whole input into a single group
+        let is_aggregate_query = !select.group_by.is_empty()
+            || select.having.is_some()
+            || select_columns.iter().any(|(_, p)| match p {
+                Projection::Column(_) => false,
+                Projection::Function(f) => Self::function_contains_aggregate(f),
+                Projection::Expr(expr) => Self::contains_aggregate(expr),
+            })
+            || query
+                .order_by
                 .iter()
-                .map(|item| match item {
-                    SelectItem::UnnamedExpr(Expr::Identifier(ident)) => {
-                        let value = ident.value.clone();
-                        table
-                            .columns
-                            .iter()
-                            .position(|c| c.name.to_string().eq(&value))
-                            .ok_or_else(|| format!("Column '{}' not found", value))?;
-                        Ok((ident.value.clone(), None))
-                    }
-                    SelectItem::UnnamedExpr(Expr::Function(func)) => {
-                        Ok((func.name.to_string(), Some(func.clone())))
+                .any(|order| Self::contains_aggregate(&order.expr));
+        // ORDER BY keys with aggregates are computed per group like the select list, as hidden
+        // columns dropped after sorting
+        let visible = select_columns.len();
+        if is_aggregate_query {
+            for order in &query.order_by {
+                // Positions only count the selected columns
+                if let Expr::Value(SqlValue::Number(n, _)) = &order.expr {
+                    if n.parse::<usize>()
+                        .map_or(true, |position| position > visible)
+                    {
+                        return Err(format!("ORDER BY position {} is not in select list", n).into());
                     }
-                    SelectItem::ExprWithAlias {
-                        expr: Expr::Function(func),
-                        alias,
-                    } => Ok((alias.value.clone(), Some(func.clone()))),
-                    _ => Err("Unsupported select item".into()),
-                })
-                .collect::<Result<Vec<_>, Box<dyn Error>>>()?
-        };
-
-        let filtered_rows = match &*query.body {
-            SetExpr::Select(select) => {
-                if let Some(selection) = &select.selection {
-                    table
-                        .rows
-                        .iter()
-                        .filter(|row| self.evaluate_condition(selection, row, &table.columns))
-                        .cloned()
-                        .collect()
-                } else {
-                    table.rows.clone()
+                }
+                if Self::contains_aggregate(&order.expr) {
+                    select_columns
+                        .push((order.expr.to_string(), Projection::Expr(order.expr.clone())));
                 }
             }
-            _ => return Err("Unsupported query type".into()),
-        };
+        }
 
-        let mut result_rows = if !group_by.is_empty() {
+        // Every output row keeps the source row it was projected from, ORDER BY may need it
+        let mut projected_rows = if is_aggregate_query {
             self.group_and_aggregate(
                 &filtered_rows,
                 &select_columns,
-                &group_by,
-                &having,
-                &table.columns,
+                &select.group_by,
+                &select.having,
+                &scope,
             )?
         } else {
             filtered_rows
                 .into_iter()
                 .map(|row| {
-                    select_columns
+                    let output = select_columns
                         .iter()
-                        .map(|(col, func)| {
-                            if let Some(f) = func {
-                                let mut rs = Vec::new();
-                                rs.push(row.clone());
-                                self.evaluate_function(f, &rs, &table.columns)
-                            } else {
-                                let index = table
-                                    .columns
-                                    .iter()
-                                    .position(|c| c.name.value == *col)
-                                    .unwrap();
-                                row.data[index].clone()
-                            }
+                        .map(|(_, projection)| match projection {
+                            Projection::Column(index) => Ok(row.data[*index].clone()),
+                            Projection::Function(f) => self.evaluate_function(f, &row, &scope),
+                            Projection::Expr(expr) => self.evaluate_expr(expr, &row, &scope),
                         })
-                        .collect()
+                        .collect::<DbResult<Vec<Value>>>()?;
+                    Ok((output, row))
                 })
-                .collect()
+                .collect::<DbResult<Vec<_>>>()?
         };
 
+        if !query.order_by.is_empty() {
+            projected_rows =
+                self.order_rows(&query.order_by, projected_rows, &select_columns, &scope)?;
+        }
+        let mut result_rows: Vec<Vec<Value>> = projected_rows
+            .into_iter()
+            .map(|(mut output, _)| {
+                output.truncate(visible);
+                output
+            })
+            .collect();
+        select_columns.truncate(visible);
+
         if let Some(offset) = &query.offset {
             let offset_value = Self::evaluate_offset_expr(offset)?;
             result_rows = result_rows.into_iter().skip(offset_value).collect();
@@ -184,484 +519,2278 @@ impl Database {
 
         let mut response = SelectResult::new();
         response.rows = result_rows;
-        response.columns = select_columns
-            .into_iter()
-            .map(|(col, func)| {
-                if let Some(f) = func {
-                    match &f.name {
-                        ObjectName(parts) => {
-                            let func_name = parts.last().unwrap().value.clone();
-                            if let Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(
-                                Expr::Identifier(ident),
-                            ))) = f.args.first()
-                            {
-                                format!("{}({})", func_name, ident.value)
-                            } else {
-                                func_name
-                            }
-                        }
-                    }
-                } else {
-                    col
-                }
-            })
-            .collect();
-        Ok(QueryResult::Rows(response))
+        response.columns = select_columns.into_iter().map(|(col, _)| col).collect();
+        Ok(response)
     }
 
-    fn group_and_aggregate(
-        &self,
-        rows: &Vec<Row>,
-        select_columns: &[(String, Option<Function>)],
-        group_by: &[Expr],
-        having: &Option<Expr>,
-        table_columns: &Vec<ColumnDef>,
-    ) -> DbResult<Vec<Vec<Value>>> {
-        let mut grouped_data: HashMap<Vec<Value>, Vec<Row>> = HashMap::new();
-
-        for row in rows {
-            let group_key: Vec<Value> = group_by
-                .iter()
-                .map(|expr| self.evaluate_expr(expr, row, table_columns))
-                .collect();
-
-            grouped_data.entry(group_key).or_default().push(row.clone());
-        }
-
-        let result: Vec<Vec<Value>> = grouped_data
-            .into_iter()
-            .filter_map(|(key, group)| {
-                let aggregated_row: Vec<Value> = select_columns
-                    .iter()
-                    .enumerate()
-                    .map(|(i, (col, func))| {
-                        if i < group_by.len() {
-                            key[i].clone()
-                        } else if let Some(f) = func {
-                            self.evaluate_function(f, &group, table_columns)
-                        } else {
-                            let index = table_columns
-                                .iter()
-                                .position(|c| c.name.value == *col)
-                                .unwrap();
-                            group[0].data[index].clone()
+    // Resolve the SELECT list against the columns of the FROM relation
+    fn plan_projection(
+        select_items: &[SelectItem],
+        columns: &[RelationColumn],
+    ) -> DbResult<Vec<(String, Projection)>> {
+        let mut select_columns = Vec::new();
+        for item in select_items {
+            match item {
+                SelectItem::Wildcard(..) => {
+                    for (index, column) in columns.iter().enumerate() {
+                        if !column.hidden {
+                            select_columns.push((column.name.clone(), Projection::Column(index)));
                         }
-                    })
-                    .collect();
-
-                // Apply HAVING condition
-                if let Some(having_expr) = having {
-                    if self.evaluate_having_condition(having_expr, &aggregated_row, select_columns)
-                    {
-                        Some(aggregated_row)
-                    } else {
-                        None
                     }
-                } else {
-                    Some(aggregated_row)
                 }
-            })
-            .collect();
-
-        Ok(result)
-    }
-    fn evaluate_having_condition(
-        &self,
-        condition: &Expr,
-        row: &Vec<Value>,
-        select_columns: &[(String, Option<Function>)],
-    ) -> bool {
-        match condition {
-            Expr::BinaryOp { left, op, right } => {
-                let left_result = self.evaluate_having_condition(left, row, select_columns);
-                let right_result = self.evaluate_having_condition(right, row, select_columns);
-                match op {
-                    BinaryOperator::And => left_result && right_result,
-                    BinaryOperator::Or => left_result || right_result,
-                    _ => {
-                        let left_value = self.evaluate_having_expr(left, row, select_columns);
-                        let right_value = self.evaluate_having_expr(right, row, select_columns);
-                        match op {
-                            BinaryOperator::Gt => {
-                                Self::compare_values(left_value, right_value)
-                                    == Some(Ordering::Greater)
-                            }
-                            BinaryOperator::Lt => {
-                                Self::compare_values(left_value, right_value)
-                                    == Some(Ordering::Less)
-                            }
-                            BinaryOperator::GtEq => matches!(
-                                Self::compare_values(left_value, right_value),
-                                Some(Ordering::Greater | Ordering::Equal)
-                            ),
-                            BinaryOperator::LtEq => matches!(
-                                Self::compare_values(left_value, right_value),
-                                Some(Ordering::Less | Ordering::Equal)
-                            ),
-                            BinaryOperator::Eq => {
-                                Self::compare_values(left_value, right_value)
-                                    == Some(Ordering::Equal)
-                            }
-                            BinaryOperator::NotEq => {
-                                Self::compare_values(left_value, right_value)
-                                    != Some(Ordering::Equal)
-                            }
-                            _ => false,
+                SelectItem::QualifiedWildcard(table_name, ..) => {
+                    let start = select_columns.len();
+                    for (index, column) in columns.iter().enumerate() {
+                        if column.matches_qualifier(&table_name.0) {
+                            select_columns.push((column.name.clone(), Projection::Column(index)));
                         }
                     }
+                    if select_columns.len() == start {
+                        return Err(
+                            format!("Table '{}' not found in FROM clause", table_name).into()
+                        );
+                    }
+                }
+                SelectItem::UnnamedExpr(
+                    expr @ (Expr::Identifier(_) | Expr::CompoundIdentifier(_)),
+                ) => {
+                    let index = Self::resolve_column(expr, columns)?;
+                    select_columns.push((expr.to_string(), Projection::Column(index)));
+                }
+                SelectItem::ExprWithAlias {
+                    expr: expr @ (Expr::Identifier(_) | Expr::CompoundIdentifier(_)),
+                    alias,
+                } => {
+                    let index = Self::resolve_column(expr, columns)?;
+                    select_columns.push((alias.value.clone(), Projection::Column(index)));
+                }
+                SelectItem::UnnamedExpr(Expr::Function(func)) => {
+                    select_columns.push((func.to_string(), Projection::Function(func.clone())))
+                }
+                SelectItem::ExprWithAlias {
+                    expr: Expr::Function(func),
+                    alias,
+                } => select_columns.push((alias.value.clone(), Projection::Function(func.clone()))),
+                SelectItem::UnnamedExpr(expr) => {
+                    select_columns.push((expr.to_string(), Projection::Expr(expr.clone())))
+                }
+                SelectItem::ExprWithAlias { expr, alias } => {
+                    select_columns.push((alias.value.clone(), Projection::Expr(expr.clone())))
                 }
             }
-            _ => self.evaluate_having_expr(condition, row, select_columns) == Value::Boolean(true),
         }
+        Ok(select_columns)
     }
 
-    fn evaluate_having_expr(
+    // Build the relation described by a FROM clause, joining every listed table
+    fn scan_from(
         &self,
-        expr: &Expr,
-        row: &Vec<Value>,
-        select_columns: &[(String, Option<Function>)],
-    ) -> Value {
-        match expr {
-            Expr::Function(func) => {
-                let index = select_columns
-                    .iter()
-                    .position(|(_, f)| f.as_ref() == Some(func))
-                    .unwrap();
-                row[index].clone()
+        from: &[TableWithJoins],
+        selection: Option<&Expr>,
+        outer: Option<&OuterRow>,
+    ) -> DbResult<(Vec<RelationColumn>, Vec<Row>)> {
+        // Indexes can only narrow down a lone table, with joins WHERE applies to the joined rows
+        let selection = match from {
+            [table_with_joins] if table_with_joins.joins.is_empty() => selection,
+            _ => None,
+        };
+        let mut relation: Option<(Vec<RelationColumn>, Vec<Row>)> = None;
+        for table_with_joins in from {
+            let mut current =
+                self.scan_table_factor(&table_with_joins.relation, selection, outer)?;
+            for join in &table_with_joins.joins {
+                current = match self.index_join(&current, join, outer)? {
+                    Some(joined) => joined,
+                    None => {
+                        let right = self.scan_table_factor(&join.relation, None, outer)?;
+                        self.join(current, right, &join.join_operator, outer)?
+                    }
+                };
             }
-            Expr::Value(v) => v.clone(),
-            _ => Value::Null,
+            // Comma separated tables behave like a CROSS JOIN
+            relation = Some(match relation {
+                Some(left) => self.join(left, current, &JoinOperator::CrossJoin, outer)?,
+                None => current,
+            });
         }
+        relation.ok_or_else(|| "No table specified in FROM clause".into())
     }
 
-    fn evaluate_limit_expr(limit: &Expr) -> DbResult<usize> {
-        match limit {
-            Expr::Value(Value::Number(n, _)) => {
-                n.parse::<usize>().map_err(|_| "Invalid LIMIT value".into())
+    // Scan one table or subquery. For a table, `selection` is the WHERE clause that will filter
+    // the rows and may pick an index to read fewer of them.
+    fn scan_table_factor(
+        &self,
+        factor: &TableFactor,
+        selection: Option<&Expr>,
+        outer: Option<&OuterRow>,
+    ) -> DbResult<(Vec<RelationColumn>, Vec<Row>)> {
+        match factor {
+            TableFactor::Table { name, alias, .. } => {
+                let table_name = name.to_string();
+                let table = self.tables.get(&table_name).ok_or("Table not found")?;
+                let mut columns = table.relation_columns(alias.as_ref());
+                Self::apply_column_aliases(&mut columns, alias.as_ref(), &table_name)?;
+                let rows = self
+                    .candidate_rows(table, &columns, selection)
+                    .map(|entry| entry.map(|(_, row)| row))
+                    .collect::<DbResult<Vec<Row>>>()?;
+                Ok((columns, rows))
             }
-            _ => Err("Unsupported LIMIT expression".into()),
+            TableFactor::Derived {
+                subquery, alias, ..
+            } => {
+                let alias = alias
+                    .as_ref()
+                    .ok_or("Subquery in FROM must have an alias")?;
+                let result = self.run_query(subquery, outer)?;
+                let mut columns: Vec<RelationColumn> = result
+                    .columns
+                    .into_iter()
+                    .map(|name| RelationColumn {
+                        table: Some(alias.name.value.clone()),
+                        name,
+                        hidden: false,
+                    })
+                    .collect();
+                Self::apply_column_aliases(&mut columns, Some(alias), &alias.name.value)?;
+                Ok((columns, result.rows.into_iter().map(Row::new).collect()))
+            }
+            TableFactor::NestedJoin {
+                table_with_joins, ..
+            } => self.scan_from(std::slice::from_ref(table_with_joins), None, outer),
+            _ => Err("Unsupported FROM clause".into()),
         }
     }
 
-    fn evaluate_offset_expr(offset: &Offset) -> DbResult<usize> {
-        match offset.value.clone() {
-            Expr::Value(Value::Number(n, _)) => n
-                .parse::<usize>()
-                .map_err(|_| "Invalid OFFSET value".into()),
-            _ => Err("Unsupported OFFSET expression".into()),
+    // Rename columns following `AS alias (a, b, ...)`
+    fn apply_column_aliases(
+        columns: &mut [RelationColumn],
+        alias: Option<&TableAlias>,
+        table_name: &str,
+    ) -> DbResult<()> {
+        let Some(alias) = alias else {
+            return Ok(());
+        };
+        if alias.columns.len() > columns.len() {
+            return Err(format!(
+                "Table '{}' has {} columns but {} column aliases were given",
+                table_name,
+                columns.len(),
+                alias.columns.len()
+            )
+            .into());
         }
-    }
-
-    // Optional: Add methods for specific operations if you want a programmatic interface
-    pub fn create_table(
-        &mut self,
-        name: String,
-        columns: &Vec<ColumnDef>,
-    ) -> DbResult<QueryResult> {
-        if self.tables.contains_key(&name) {
-            return Err(format!("Table {} alerady exist", name).into());
+        for (column, column_alias) in columns.iter_mut().zip(&alias.columns) {
+            column.name = column_alias.value.clone();
         }
-        let mut table = Table::new(name.clone());
-        table.columns = columns.clone();
-        self.tables.insert(name, table);
-        Ok(QueryResult::Success(
-            "Successfully create table".to_string(),
-        ))
+        Ok(())
     }
 
-    pub fn drop_table(&mut self, name: String, if_exist: bool) -> DbResult<QueryResult> {
-        if !self.tables.contains_key(&name) && !if_exist {
-            return Err(format!("Table {} dos not exist", name).into());
+    // Join `left` with a table through an index on it: for every left row, look up the rows
+    // matching one `left_column = right_column` equality of the ON condition instead of scanning
+    // the whole table. None when the join cannot use an index and needs a full join.
+    fn index_join(
+        &self,
+        left: &(Vec<RelationColumn>, Vec<Row>),
+        join: &Join,
+        outer: Option<&OuterRow>,
+    ) -> DbResult<Option<(Vec<RelationColumn>, Vec<Row>)>> {
+        let (condition, keep_left) = match &join.join_operator {
+            JoinOperator::Inner(JoinConstraint::On(condition)) => (condition, false),
+            JoinOperator::LeftOuter(JoinConstraint::On(condition)) => (condition, true),
+            _ => return Ok(None),
+        };
+        let TableFactor::Table { name, alias, .. } = &join.relation else {
+            return Ok(None);
+        };
+        let table_name = name.to_string();
+        let Some(table) = self.tables.get(&table_name) else {
+            return Ok(None);
+        };
+        let mut right_columns = table.relation_columns(alias.as_ref());
+        Self::apply_column_aliases(&mut right_columns, alias.as_ref(), &table_name)?;
+        let (left_columns, left_rows) = left;
+        // Name clashes are left to join() to report
+        if left_columns
+            .iter()
+            .any(|l| l.table.is_some() && right_columns.iter().any(|r| r.table == l.table))
+        {
+            return Ok(None);
         }
+        let mut columns = left_columns.clone();
+        columns.extend(right_columns.iter().cloned());
+        let Some((left_position, index, right_position)) =
+            Self::join_key(table, condition, &columns, left_columns.len())
+        else {
+            return Ok(None);
+        };
 
-        self.tables.remove(&name);
-        Ok(QueryResult::Success(
-            "Successfuly deleted table".to_string(),
-        ))
-    }
-
-    fn insert(&mut self, table_name: &ObjectName, source: &Query) -> DbResult<QueryResult> {
-        let table_name = table_name.to_string();
-        let table = self.tables.get_mut(&table_name).ok_or("Table not found")?;
-
-        if let Values(values) = &source.body.as_ref() {
-            table.insert(values)
-        } else {
-            Err("Unsupported INSERT format".into())
+        // Every left value must be found by the index exactly as the ON condition compares it
+        let mut keys = Vec::with_capacity(left_rows.len());
+        for row in left_rows {
+            keys.push(match &row.data[left_position] {
+                Value::Null => None,
+                value => match Table::exact_key(value.clone(), &table.columns[right_position]) {
+                    Some(key) => Some(key),
+                    None => return Ok(None),
+                },
+            });
         }
-    }
 
-    fn evaluate_condition(&self, condition: &Expr, row: &Row, columns: &Vec<ColumnDef>) -> bool {
-        match condition {
-            Expr::BinaryOp { left, right, op } => {
-                let left_value = self.evaluate_expr(left, row, columns);
-                let right_value = self.evaluate_expr(right, row, columns);
-                match op {
-                    BinaryOperator::Eq => {
-                        Self::compare_values(left_value, right_value) == Some(Ordering::Equal)
-                    }
-                    BinaryOperator::NotEq => {
-                        Self::compare_values(left_value, right_value) != Some(Ordering::Equal)
-                    }
-                    BinaryOperator::Gt => {
-                        Self::compare_values(left_value, right_value) == Some(Ordering::Greater)
-                    }
-                    BinaryOperator::Lt => {
-                        Self::compare_values(left_value, right_value) == Some(Ordering::Less)
-                    }
-                    BinaryOperator::GtEq => matches!(
-                        Self::compare_values(left_value, right_value),
-                        Some(Ordering::Greater | Ordering::Equal)
-                    ),
-                    BinaryOperator::LtEq => matches!(
-                        Self::compare_values(left_value, right_value),
-                        Some(Ordering::Less | Ordering::Equal)
-                    ),
-                    BinaryOperator::Or => {
-                        self.evaluate_condition(left, row, columns)
-                            || self.evaluate_condition(right, row, columns)
-                    }
-                    BinaryOperator::And => {
-                        self.evaluate_condition(left, row, columns)
-                            && self.evaluate_condition(right, row, columns)
-                    }
-                    // Add more operators as needed
-                    _ => false,
-                }
-            }
-            Expr::Identifier(_) => false,
-            Expr::CompoundIdentifier(_) => false,
-            Expr::JsonAccess { .. } => false,
-            Expr::CompositeAccess { .. } => false,
-            Expr::IsFalse(_) => false,
-            Expr::IsNotFalse(_) => false,
-            Expr::IsTrue(_) => false,
-            Expr::IsNotTrue(_) => false,
-            Expr::IsNull(left) => {
-                matches!(self.evaluate_expr(left, row, columns), Value::Null)
-            }
-            Expr::IsNotNull(left) => !matches!(self.evaluate_expr(left, row, columns), Value::Null),
-            Expr::IsUnknown(_) => false,
-            Expr::IsNotUnknown(_) => false,
-            Expr::IsDistinctFrom(_, _) => false,
-            Expr::IsNotDistinctFrom(_, _) => false,
-            Expr::InList {
-                expr,
-                list,
-                negated,
-            } => {
-                let value = self.evaluate_expr(expr, row, columns);
-                let resoult = list
-                    .iter()
-                    .any(|e| self.evaluate_expr(e, row, columns) == value);
-                if !negated {
-                    resoult
-                } else {
-                    !resoult
+        let scope = Scope {
+            columns: &columns,
+            outer,
+            group: None,
+        };
+        let mut rows = Vec::new();
+        for (left_row, key) in left_rows.iter().zip(keys) {
+            let ids = key.map(|key| index.equal(&key)).unwrap_or_default();
+            let mut matched = false;
+            // Scan order, like a full join
+            for right in table.store.lookup(ids) {
+                let mut data = left_row.data.clone();
+                data.extend(right?.1.data);
+                let row = Row::new(data);
+                if self.evaluate_condition(condition, &row, &scope)? {
+                    matched = true;
+                    rows.push(row);
                 }
             }
-            Expr::InSubquery { .. } => false,
-            Expr::InUnnest { .. } => false,
-            Expr::Between {
-                expr,
-                low,
-                high,
-                negated,
-            } => {
-                let value = self.evaluate_expr(expr, row, columns);
-                let low = self.evaluate_expr(low, row, columns);
-                let high = self.evaluate_expr(high, row, columns);
-                let result = Self::compare_values(value.clone(), low) != Some(Ordering::Less)
-                    && Self::compare_values(value, high) != Some(Ordering::Greater);
-
-                if *negated {
-                    !result
-                } else {
-                    result
-                }
-            }
-            Expr::Like { .. } => false,
-            Expr::ILike { .. } => false,
-            Expr::SimilarTo { .. } => false,
-            Expr::AnyOp(_) => false,
-            Expr::AllOp(_) => false,
-            Expr::UnaryOp { .. } => false,
-            Expr::Cast { .. } => false,
-            Expr::TryCast { .. } => false,
-            Expr::SafeCast { .. } => false,
-            Expr::AtTimeZone { .. } => false,
-            Expr::Extract { .. } => false,
-            Expr::Ceil { .. } => false,
-            Expr::Floor { .. } => false,
-            Expr::Position { .. } => false,
-            Expr::Substring { .. } => false,
-            Expr::Trim { .. } => false,
-            Expr::Overlay { .. } => false,
-            Expr::Collate { .. } => false,
-            Expr::Nested(expr) => self.evaluate_condition(expr, row, columns),
-            Expr::Value(_) => false,
-            Expr::IntroducedString { .. } => false,
-            Expr::TypedString { .. } => false,
-            Expr::MapAccess { .. } => false,
-            Expr::Function(_) => false,
-            Expr::AggregateExpressionWithFilter { .. } => false,
-            Expr::Case { .. } => false,
-            Expr::Exists { .. } => false,
-            Expr::Subquery(_) => false,
-            Expr::ArraySubquery(_) => false,
-            Expr::ListAgg(_) => false,
-            Expr::ArrayAgg(_) => false,
-            Expr::GroupingSets(_) => false,
-            Expr::Cube(_) => false,
-            Expr::Rollup(_) => false,
-            Expr::Tuple(_) => false,
-            Expr::ArrayIndex { .. } => false,
-            Expr::Array(_) => false,
-            Expr::Interval(_) => false,
-            Expr::MatchAgainst { .. } => false,
-        }
-    }
-
-    fn compare_values(left: Value, right: Value) -> Option<Ordering> {
-        match (left, right) {
-            (Value::Number(a, _), Value::Number(b, _)) => {
-                a.parse::<f64>().ok()?.partial_cmp(&b.parse::<f64>().ok()?)
-            }
-            (Value::SingleQuotedString(a), Value::SingleQuotedString(b)) => Some(a.cmp(&b)),
-            (Value::DoubleQuotedString(a), Value::DoubleQuotedString(b)) => Some(a.cmp(&b)),
-            (Value::Boolean(a), Value::Boolean(b)) => Some(a.cmp(&b)),
-            // Add more comparisons as needed
-            _ => None, // Incomparable types
-        }
-    }
-
-    fn evaluate_expr(&self, expr: &Expr, row: &Row, columns: &Vec<ColumnDef>) -> Value {
-        match expr {
-            Expr::Identifier(ident) => {
-                let col_index = columns
-                    .iter()
-                    .position(|c| c.name.value.eq(&ident.value))
-                    .expect("Column not found");
-                row.data[col_index].clone()
+            if !matched && keep_left {
+                let mut data = left_row.data.clone();
+                data.resize(columns.len(), Value::Null);
+                rows.push(Row::new(data));
             }
-            Expr::Value(v) => v.clone(),
-            _ => Value::Null,
         }
+        Ok(Some((columns, rows)))
     }
-    fn evaluate_function(
-        &self,
-        func: &Function,
-        rows: &Vec<Row>,
-        columns: &Vec<ColumnDef>,
-    ) -> Value {
-        match func.name.to_string().to_uppercase().as_str() {
-            "SUM" => {
-                if let Some(arg) = func.args.first() {
-                    match arg {
-                        FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => {
-                            let sum: f64 = rows
-                                .iter()
-                                .map(|row| match self.evaluate_expr(expr, row, columns) {
-                                    Value::Number(n, _) => n.parse::<f64>().unwrap_or(0.0),
-                                    _ => 0.0,
-                                })
-                                .sum();
-                            Value::Number(sum.to_string(), false)
-                        }
-                        _ => Value::Null,
-                    }
-                } else {
-                    Value::Null
-                }
+
+    // An equality in the AND-ed conjuncts of `condition` between a column of the left relation
+    // (the first `split` columns) and a column of `table` it has an index for, as the left
+    // position, the index and the table's column position
+    fn join_key<'a>(
+        table: &'a Table,
+        condition: &Expr,
+        columns: &[RelationColumn],
+        split: usize,
+    ) -> Option<(usize, &'a Index, usize)> {
+        match condition {
+            Expr::Nested(inner) => Self::join_key(table, inner, columns, split),
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::And,
+                right,
+            } => Self::join_key(table, left, columns, split)
+                .or_else(|| Self::join_key(table, right, columns, split)),
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::Eq,
+                right,
+            } => {
+                let left = Self::find_column(left, columns).ok()??;
+                let right = Self::find_column(right, columns).ok()??;
+                let (outer, inner) = match (left < split, right < split) {
+                    (true, false) => (left, right - split),
+                    (false, true) => (right, left - split),
+                    _ => return None,
+                };
+                Some((outer, table.index_on(inner, true)?, inner))
             }
-            // Add other function evaluations here
-            _ => Value::Null,
+            _ => None,
         }
     }
-}
 
-#[derive(Clone)]
-pub struct Table {
-    #[allow(unused)]
-    name: String,
-    columns: Vec<ColumnDef>,
-    rows: Vec<Row>,
-    //todo add support for indexes
-    //indexes: HashMap<String,IndexType>
-}
+    fn join(
+        &self,
+        left: (Vec<RelationColumn>, Vec<Row>),
+        right: (Vec<RelationColumn>, Vec<Row>),
+        operator: &JoinOperator,
+        outer: Option<&OuterRow>,
+    ) -> DbResult<(Vec<RelationColumn>, Vec<Row>)> {
+        let (left_columns, left_rows) = left;
+        let (right_columns, right_rows) = right;
 
-impl Table {
-    pub fn new(table_name: String) -> Self {
-        Table {
-            name: table_name,
-            columns: Vec::new(),
-            rows: Vec::new(),
+        // Every table in FROM needs a distinct name, otherwise qualified references are ambiguous
+        if let Some(table) = left_columns.iter().find_map(|l| {
+            l.table
+                .as_ref()
+                .filter(|t| right_columns.iter().any(|r| r.table.as_ref() == Some(t)))
+        }) {
+            return Err(format!("Table name '{}' specified more than once", table).into());
         }
-    }
 
-    pub fn insert(&mut self, values: &Val) -> DbResult<QueryResult> {
-        for row in &values.rows {
-            let mut new_row = Vec::new();
-            for value in row {
-                match value {
-                    Expr::Value(sql_value) => {
-                        new_row.push(sql_value.clone());
-                    }
-                    _ => {}
-                }
-            }
-            if new_row.len() != self.columns.len() {
-                return Err("Number of values doesn't match number of columns".into());
-            }
+        let (constraint, keep_left, keep_right) = match operator {
+            JoinOperator::Inner(constraint) => (constraint, false, false),
+            JoinOperator::LeftOuter(constraint) => (constraint, true, false),
+            JoinOperator::RightOuter(constraint) => (constraint, false, true),
+            JoinOperator::FullOuter(constraint) => (constraint, true, true),
+            JoinOperator::CrossJoin => (&JoinConstraint::None, false, false),
+            _ => return Err("Unsupported join type".into()),
+        };
 
-            for (value, column) in new_row.iter().zip(self.columns.iter()) {
-                if !Self::type_match(value, &column.data_type) {
-                    return Err(format!("Type mismatch for column '{}'", column.name).into());
-                }
-            }
+        // USING and NATURAL joins compare pairs of equally named columns
+        let using_names: Vec<String> = match constraint {
+            JoinConstraint::Using(idents) => idents.iter().map(|i| i.value.clone()).collect(),
+            JoinConstraint::Natural => left_columns
+                .iter()
+                .filter(|l| {
+                    !l.hidden && right_columns.iter().any(|r| !r.hidden && r.name == l.name)
+                })
+                .map(|l| l.name.clone())
+                .collect(),
+            _ => Vec::new(),
+        };
+        let mut using_pairs = Vec::new();
+        for name in &using_names {
+            let find = |columns: &[RelationColumn], side: &str| {
+                columns
+                    .iter()
+                    .position(|c| !c.hidden && c.name == *name)
+                    .ok_or_else(|| format!("Column '{}' not found in {} side of join", name, side))
+            };
+            using_pairs.push((find(&left_columns, "left")?, find(&right_columns, "right")?));
+        }
 
-            self.rows.push(Row::new(new_row));
-            //table.insert(new_row)?;
+        let mut columns: Vec<RelationColumn> = Vec::new();
+        // Merged USING columns come first and are the only ones reachable without a qualifier
+        for name in &using_names {
+            columns.push(RelationColumn {
+                table: None,
+                name: name.clone(),
+                hidden: false,
+            });
+        }
+        let merged: Vec<usize> = using_pairs.iter().map(|(l, _)| *l).collect();
+        let merged_right: Vec<usize> = using_pairs.iter().map(|(_, r)| *r).collect();
+        for (index, column) in left_columns.iter().enumerate() {
+            let mut column = column.clone();
+            column.hidden |= merged.contains(&index);
+            columns.push(column);
+        }
+        for (index, column) in right_columns.iter().enumerate() {
+            let mut column = column.clone();
+            column.hidden |= merged_right.contains(&index);
+            columns.push(column);
         }
-        Ok(QueryResult::Success(format!(
-            "Inserted {} row(s)",
-            values.rows.len()
-        )))
-    }
 
-    fn type_match(value: &Value, data_type: &DataType) -> bool {
-        match (value, data_type) {
-            // Number types
-            (Value::Number(_, _), DataType::Int(_)) => true,
-            (Value::Number(_, _), DataType::BigInt(_)) => true,
-            (Value::Number(_, _), DataType::Float(_)) => true,
-            (Value::Number(_, _), DataType::Double) => true,
-            (Value::Number(_, _), DataType::Decimal(_)) => true,
+        let combine = |left: Option<&Row>, right: Option<&Row>| {
+            let left_data = match left {
+                Some(row) => row.data.clone(),
+                None => vec![Value::Null; left_columns.len()],
+            };
+            let right_data = match right {
+                Some(row) => row.data.clone(),
+                None => vec![Value::Null; right_columns.len()],
+            };
+            let mut data: Vec<Value> = using_pairs
+                .iter()
+                .map(|(l, r)| match &left_data[*l] {
+                    Value::Null => right_data[*r].clone(),
+                    value => value.clone(),
+                })
+                .collect();
+            data.extend(left_data);
+            data.extend(right_data);
+            Row::new(data)
+        };
+
+        let mut rows = Vec::new();
+        let mut right_matched = vec![false; right_rows.len()];
+        for left_row in &left_rows {
+            let mut matched = false;
+            for (right_index, right_row) in right_rows.iter().enumerate() {
+                let is_match = match constraint {
+                    JoinConstraint::On(condition) => {
+                        let row = combine(Some(left_row), Some(right_row));
+                        let scope = Scope {
+                            columns: &columns,
+                            outer,
+                            group: None,
+                        };
+                        self.evaluate_condition(condition, &row, &scope)?
+                    }
+                    JoinConstraint::Using(_) | JoinConstraint::Natural => {
+                        using_pairs.iter().all(|(l, r)| {
+                            left_row.data[*l].compare(&right_row.data[*r]) == Some(Ordering::Equal)
+                        })
+                    }
+                    JoinConstraint::None => true,
+                };
+                if is_match {
+                    matched = true;
+                    right_matched[right_index] = true;
+                    rows.push(combine(Some(left_row), Some(right_row)));
+                }
+            }
+            if !matched && keep_left {
+                rows.push(combine(Some(left_row), None));
+            }
+        }
+        if keep_right {
+            for (right_row, matched) in right_rows.iter().zip(right_matched) {
+                if !matched {
+                    rows.push(combine(None, Some(right_row)));
+                }
+            }
+        }
+
+        Ok((columns, rows))
+    }
+
+    // Bind a column reference to its position in the relation. Unqualified names must be unique
+    // among the visible columns, qualified ones are matched against the table name or alias.
+    fn resolve_column(expr: &Expr, columns: &[RelationColumn]) -> DbResult<usize> {
+        Self::find_column(expr, columns)?
+            .ok_or_else(|| format!("Column '{}' not found", expr).into())
+    }
+
+    // Like resolve_column, but a missing column is not an error
+    fn find_column(expr: &Expr, columns: &[RelationColumn]) -> DbResult<Option<usize>> {
+        let (qualifier, name) = match expr {
+            Expr::Identifier(ident) => (None, &ident.value),
+            Expr::CompoundIdentifier(idents) => {
+                let (column, qualifier) = idents.split_last().ok_or("Empty column reference")?;
+                (Some(qualifier), &column.value)
+            }
+            _ => return Err(format!("'{}' is not a column reference", expr).into()),
+        };
+
+        let mut candidates = columns
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| {
+                column.name == *name
+                    && match qualifier {
+                        Some(qualifier) => column.matches_qualifier(qualifier),
+                        None => !column.hidden,
+                    }
+            })
+            .map(|(index, _)| index);
+        match (candidates.next(), candidates.next()) {
+            (Some(index), None) => Ok(Some(index)),
+            (Some(_), Some(_)) => Err(format!("Column reference '{}' is ambiguous", expr).into()),
+            (None, _) => Ok(None),
+        }
+    }
+
+    // Read a column of the current row. Names unknown to this query may be correlated
+    // references to a column of the enclosing query.
+    fn column_value(expr: &Expr, row: &Row, scope: &Scope) -> DbResult<Value> {
+        match Self::find_column(expr, scope.columns)? {
+            Some(index) => Ok(row.data[index].clone()),
+            None => match scope.outer {
+                Some(outer) => Self::column_value(expr, outer.row, &outer.scope),
+                None => Err(format!("Column '{}' not found", expr).into()),
+            },
+        }
+    }
+
+    // Run a subquery for the current row and return its only column
+    fn evaluate_subquery(&self, query: &Query, row: &Row, scope: &Scope) -> DbResult<Vec<Value>> {
+        let outer = OuterRow { row, scope: *scope };
+        let result = self.run_query(query, Some(&outer))?;
+        if result.columns.len() != 1 {
+            return Err(format!(
+                "Subquery must return exactly one column, got {}",
+                result.columns.len()
+            )
+            .into());
+        }
+        Ok(result
+            .rows
+            .into_iter()
+            .map(|mut row| row.remove(0))
+            .collect())
+    }
+
+    fn group_and_aggregate(
+        &self,
+        rows: &[Row],
+        select_columns: &[(String, Projection)],
+        group_by: &[Expr],
+        having: &Option<Expr>,
+        scope: &Scope,
+    ) -> DbResult<Vec<(Vec<Value>, Row)>> {
+        // Groups are kept in order of first appearance so the output is deterministic
+        let mut group_positions: HashMap<Vec<Value>, usize> = HashMap::new();
+        let mut grouped_data: Vec<Vec<Row>> = Vec::new();
+
+        for row in rows {
+            let group_key: Vec<Value> = group_by
+                .iter()
+                .map(|expr| self.evaluate_expr(expr, row, scope))
+                .collect::<DbResult<_>>()?;
+
+            let position = *group_positions.entry(group_key).or_insert_with(|| {
+                grouped_data.push(Vec::new());
+                grouped_data.len() - 1
+            });
+            grouped_data[position].push(row.clone());
+        }
+        if group_by.is_empty() && grouped_data.is_empty() {
+            // Whole-table aggregation still yields one row for an empty input, e.g. COUNT(*) = 0
+            grouped_data.push(Vec::new());
+        }
+
+        let mut result = Vec::new();
+        for group in grouped_data {
+            let representative = match group.first() {
+                Some(row) => row.clone(),
+                None => Row::new(vec![Value::Null; scope.columns.len()]),
+            };
+            // Aggregates are computed over the group, plain columns come from its representative
+            let group_scope = Scope {
+                group: Some(&group),
+                ..*scope
+            };
+            let aggregated_row: Vec<Value> = select_columns
+                .iter()
+                .map(|(_, projection)| match projection {
+                    Projection::Function(f) => {
+                        self.evaluate_function(f, &representative, &group_scope)
+                    }
+                    Projection::Column(index) => Ok(representative.data[*index].clone()),
+                    Projection::Expr(expr) => {
+                        self.evaluate_expr(expr, &representative, &group_scope)
+                    }
+                })
+                .collect::<DbResult<_>>()?;
+
+            // Apply HAVING condition
+            if let Some(having_expr) = having {
+                if !self.evaluate_condition(having_expr, &representative, &group_scope)? {
+                    continue;
+                }
+            }
+            result.push((aggregated_row, representative));
+        }
+
+        Ok(result)
+    }
+
+    // Sort projected rows by the ORDER BY keys. A key may name an output column (by alias or
+    // 1-based position) or be any expression over the source row.
+    fn order_rows(
+        &self,
+        order_by: &[OrderByExpr],
+        rows: Vec<(Vec<Value>, Row)>,
+        select_columns: &[(String, Projection)],
+        scope: &Scope,
+    ) -> DbResult<Vec<(Vec<Value>, Row)>> {
+        let mut keyed_rows = Vec::with_capacity(rows.len());
+        for (output, source) in rows {
+            let keys = order_by
+                .iter()
+                .map(|order| {
+                    self.evaluate_order_key(&order.expr, &output, &source, select_columns, scope)
+                })
+                .collect::<DbResult<Vec<Value>>>()?;
+            keyed_rows.push((keys, output, source));
+        }
+
+        // sort_by is stable, rows with equal keys keep their original order
+        keyed_rows.sort_by(|(left, ..), (right, ..)| {
+            for ((left, right), order) in left.iter().zip(right).zip(order_by) {
+                let ascending = order.asc.unwrap_or(true);
+                // NULL sorts as the smallest value, as in MySQL and in indexes, unless NULLS
+                // FIRST / LAST says otherwise
+                let nulls_first = order.nulls_first.unwrap_or(ascending);
+                let ordering = match (left, right) {
+                    (Value::Null, Value::Null) => Ordering::Equal,
+                    (Value::Null, _) if nulls_first => Ordering::Less,
+                    (Value::Null, _) => Ordering::Greater,
+                    (_, Value::Null) if nulls_first => Ordering::Greater,
+                    (_, Value::Null) => Ordering::Less,
+                    _ if ascending => left.total_cmp(right),
+                    _ => left.total_cmp(right).reverse(),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            Ordering::Equal
+        });
+
+        Ok(keyed_rows
+            .into_iter()
+            .map(|(_, output, source)| (output, source))
+            .collect())
+    }
+
+    fn evaluate_order_key(
+        &self,
+        expr: &Expr,
+        output: &[Value],
+        source: &Row,
+        select_columns: &[(String, Projection)],
+        scope: &Scope,
+    ) -> DbResult<Value> {
+        // Expressions of the select list, hidden ORDER BY aggregates included, are computed
+        if let Some(index) = select_columns
+            .iter()
+            .position(|(_, p)| matches!(p, Projection::Expr(e) if e == expr))
+        {
+            return Ok(output[index].clone());
+        }
+        match expr {
+            Expr::Value(SqlValue::Number(n, _)) => {
+                let position = n
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|position| (1..=output.len()).contains(position))
+                    .ok_or_else(|| format!("ORDER BY position {} is not in select list", n))?;
+                Ok(output[position - 1].clone())
+            }
+            Expr::Identifier(ident) => {
+                let mut matching = select_columns
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (name, _))| *name == ident.value);
+                match (matching.next(), matching.next()) {
+                    (Some((index, _)), None) => Ok(output[index].clone()),
+                    _ => self.evaluate_expr(expr, source, scope),
+                }
+            }
+            Expr::Function(func) => {
+                match select_columns
+                    .iter()
+                    .position(|(_, p)| matches!(p, Projection::Function(f) if f == func))
+                {
+                    Some(index) => Ok(output[index].clone()),
+                    None => self.evaluate_expr(expr, source, scope),
+                }
+            }
+            _ => self.evaluate_expr(expr, source, scope),
+        }
+    }
+
+    fn evaluate_limit_expr(limit: &Expr) -> DbResult<usize> {
+        match limit {
+            Expr::Value(SqlValue::Number(n, _)) => {
+                n.parse::<usize>().map_err(|_| "Invalid LIMIT value".into())
+            }
+            _ => Err("Unsupported LIMIT expression".into()),
+        }
+    }
+
+    fn evaluate_offset_expr(offset: &Offset) -> DbResult<usize> {
+        match offset.value.clone() {
+            Expr::Value(SqlValue::Number(n, _)) => n
+                .parse::<usize>()
+                .map_err(|_| "Invalid OFFSET value".into()),
+            _ => Err("Unsupported OFFSET expression".into()),
+        }
+    }
+
+    // Optional: Add methods for specific operations if you want a programmatic interface
+    pub fn create_table(
+        &mut self,
+        name: String,
+        columns: &[ColumnDef],
+        constraints: &[TableConstraint],
+        engine: Option<&str>,
+    ) -> DbResult<QueryResult> {
+        if self.tables.contains_key(&name) {
+            return Err(format!("Table {} alerady exist", name).into());
+        }
+        let table = self.new_table(name.clone(), columns.to_vec(), constraints.to_vec(), engine)?;
+        for (position, column) in table.columns.iter().enumerate() {
+            self.column_default(&table, position).map_err(|e| {
+                format!("Invalid DEFAULT value for column '{}': {}", column.name, e)
+            })?;
+        }
+        // Evaluating the conditions once finds references to unknown columns
+        let nulls = Row::new(vec![Value::Null; table.columns.len()]);
+        let columns = table.relation_columns(None);
+        for check in &table.checks {
+            let scope = Scope {
+                columns: &columns,
+                outer: None,
+                group: None,
+            };
+            self.evaluate_truth(&check.expr, &nulls, &scope)
+                .map_err(|e| format!("Invalid {}: {}", check.label(), e))?;
+        }
+        for foreign_key in &table.foreign_keys {
+            let parent = match self.tables.get(&foreign_key.parent) {
+                Some(parent) => parent,
+                None if foreign_key.parent == name => &table,
+                None => {
+                    return Err(format!(
+                        "{} references unknown table '{}'",
+                        foreign_key.label(),
+                        foreign_key.parent
+                    )
+                    .into())
+                }
+            };
+            // Every row must reference a single parent row
+            let referred = parent.column_names(&Self::referred_columns(foreign_key, parent)?);
+            if !parent
+                .indexes
+                .iter()
+                .any(|index| index.def.unique && index.def.columns == referred)
+            {
+                return Err(format!(
+                    "{} must reference a PRIMARY KEY or UNIQUE key of '{}'",
+                    foreign_key.label(),
+                    foreign_key.parent
+                )
+                .into());
+            }
+        }
+        self.tables.insert(name.clone(), table);
+        self.undo.push(Undo::CreatedTable { table: name });
+        Ok(QueryResult::Success(
+            "Successfully create table".to_string(),
+        ))
+    }
+
+    // Build an empty table whose rows are kept by `engine`, the default one when not given
+    fn new_table(
+        &self,
+        name: String,
+        columns: Vec<ColumnDef>,
+        constraints: Vec<TableConstraint>,
+        engine: Option<&str>,
+    ) -> DbResult<Table> {
+        let engine = engine.map_or(self.default_engine.clone(), str::to_lowercase);
+        let store = match self.engines.get(&engine) {
+            Some(storage_engine) => storage_engine.create_store(&name)?,
+            None if [PAGED_ENGINE, LOG_ENGINE].contains(&engine.as_str()) => {
+                return Err(format!("Storage engine '{}' requires a data directory", engine).into())
+            }
+            None => return Err(format!("Unknown storage engine '{}'", engine).into()),
+        };
+        Table::new(name, columns, constraints, engine, store)
+    }
+
+    fn create_index(
+        &mut self,
+        name: String,
+        table_name: String,
+        columns: &[OrderByExpr],
+        unique: bool,
+        kind: IndexKind,
+        if_not_exists: bool,
+    ) -> DbResult<QueryResult> {
+        if self.find_index(&name).is_some() {
+            if if_not_exists {
+                return Ok(QueryResult::Success(format!(
+                    "Index {} already exists",
+                    name
+                )));
+            }
+            return Err(format!("Index {} already exists", name).into());
+        }
+        let columns = columns
+            .iter()
+            .map(|column| match &column.expr {
+                Expr::Identifier(ident) => Ok(ident.value.clone()),
+                expr => Err(format!("Cannot index expression '{}'", expr).into()),
+            })
+            .collect::<DbResult<Vec<String>>>()?;
+        let table = self.tables.get_mut(&table_name).ok_or("Table not found")?;
+        table.add_index(IndexDef {
+            name: name.clone(),
+            columns,
+            unique,
+            kind,
+            constraint: None,
+        })?;
+        self.undo.push(Undo::CreatedIndex {
+            table: table_name,
+            name,
+        });
+        Ok(QueryResult::Success(
+            "Successfully created index".to_string(),
+        ))
+    }
+
+    fn drop_index(&mut self, name: String, if_exists: bool) -> DbResult<QueryResult> {
+        let Some(table_name) = self.find_index(&name) else {
+            if if_exists {
+                return Ok(QueryResult::Success(format!(
+                    "Index {} does not exist",
+                    name
+                )));
+            }
+            return Err(format!("Index {} does not exist", name).into());
+        };
+        let table = self.tables.get_mut(&table_name).ok_or("Table not found")?;
+        let position = table
+            .indexes
+            .iter()
+            .position(|index| index.is_named(&name))
+            .ok_or("Index not found")?;
+        let index = table.indexes.remove(position);
+        self.undo.push(Undo::DroppedIndex {
+            table: table_name,
+            position,
+            index,
+        });
+        Ok(QueryResult::Success(
+            "Successfully dropped index".to_string(),
+        ))
+    }
+
+    // Name of the table that has the index, index names are unique in the whole database.
+    // Indexes enforcing constraints belong to their table and are not found here.
+    fn find_index(&self, name: &str) -> Option<String> {
+        self.tables
+            .values()
+            .find(|table| table.indexes.iter().any(|index| index.is_named(name)))
+            .map(|table| table.name.clone())
+    }
+
+    pub fn drop_table(&mut self, name: String, if_exist: bool) -> DbResult<QueryResult> {
+        if !self.tables.contains_key(&name) && !if_exist {
+            return Err(format!("Table {} dos not exist", name).into());
+        }
+        if let Some((child, foreign_key)) = self.references_to(&name).find(|(c, _)| **c != name) {
+            return Err(format!(
+                "Cannot drop table '{}' referenced by {} of '{}'",
+                name,
+                foreign_key.label(),
+                child
+            )
+            .into());
+        }
+
+        if let Some(table) = self.tables.remove(&name) {
+            self.undo.push(Undo::DroppedTable {
+                table: Box::new(table),
+            });
+        }
+        Ok(QueryResult::Success(
+            "Successfuly deleted table".to_string(),
+        ))
+    }
+
+    // Foreign keys referencing `table`, with the name of the table they belong to
+    fn references_to<'a>(
+        &'a self,
+        table: &'a str,
+    ) -> impl Iterator<Item = (&'a String, &'a ForeignKey)> + 'a {
+        self.tables.iter().flat_map(move |(child, child_table)| {
+            child_table
+                .foreign_keys
+                .iter()
+                .filter(move |foreign_key| foreign_key.parent == table)
+                .map(move |foreign_key| (child, foreign_key))
+        })
+    }
+
+    fn insert(
+        &mut self,
+        table_name: &ObjectName,
+        columns: &[Ident],
+        source: &Query,
+        on: Option<&OnInsert>,
+    ) -> DbResult<QueryResult> {
+        let table_name = table_name.to_string();
+        let table = self.tables.get(&table_name).ok_or("Table not found")?;
+        let targets = table.insert_targets(columns)?;
+        let upsert = match on {
+            Some(OnInsert::DuplicateKeyUpdate(assignments)) => Some(Upsert {
+                keys: table.conflict_keys(None)?,
+                action: UpsertAction::Update {
+                    assignments,
+                    selection: None,
+                },
+            }),
+            Some(OnInsert::OnConflict(OnConflict {
+                conflict_target,
+                action,
+            })) => Some(Upsert {
+                keys: table.conflict_keys(conflict_target.as_ref())?,
+                action: match action {
+                    OnConflictAction::DoNothing => UpsertAction::Nothing,
+                    OnConflictAction::DoUpdate(DoUpdate {
+                        assignments,
+                        selection,
+                    }) => UpsertAction::Update {
+                        assignments,
+                        selection: selection.as_ref(),
+                    },
+                },
+            }),
+            _ => None,
+        };
+        // An unknown column in the SET clause fails even when no row runs into a key
+        if let Some(Upsert {
+            action: UpsertAction::Update { assignments, .. },
+            ..
+        }) = &upsert
+        {
+            Self::assignment_targets(assignments, &table.relation_columns(None))?;
+        }
+
+        // INSERT ... SELECT reads every row before inserting any, so it can read the table it
+        // inserts into
+        let tuples: Vec<Tuple> = match source.body.as_ref() {
+            Values(values) => values
+                .rows
+                .iter()
+                .map(|exprs| Tuple::Exprs(exprs))
+                .collect(),
+            _ => {
+                let result = self.run_query(source, None)?;
+                result.rows.into_iter().map(Tuple::Values).collect()
+            }
+        };
+
+        // All or nothing: when a row fails, execute takes back what the ones before it did
+        let count = tuples.len();
+        let (mut inserted, mut updated) = (0, 0);
+        for (index, tuple) in tuples.into_iter().enumerate() {
+            match self.insert_tuple(&table_name, &targets, tuple, upsert.as_ref()) {
+                Ok(Outcome::Inserted) => inserted += 1,
+                Ok(Outcome::Updated) => updated += 1,
+                Ok(Outcome::Skipped) => {}
+                Err(e) => {
+                    return Err(match count {
+                        1 => e,
+                        _ => format!("Row {} of {}: {}", index + 1, count, e).into(),
+                    })
+                }
+            }
+        }
+        Ok(match upsert {
+            Some(_) => QueryResult::Upserted { inserted, updated },
+            None => QueryResult::Success(format!("Inserted {} row(s)", inserted)),
+        })
+    }
+
+    // Build the row for one tuple, check it against every constraint of the table and store it.
+    // An upsert acts on the row holding its unique key instead, if there is one.
+    fn insert_tuple(
+        &mut self,
+        table_name: &str,
+        targets: &[usize],
+        tuple: Tuple,
+        upsert: Option<&Upsert>,
+    ) -> DbResult<Outcome> {
+        let table = self.tables.get(table_name).ok_or("Table not found")?;
+        let row = match tuple {
+            Tuple::Exprs(exprs) => self.values_row(table, targets, exprs)?,
+            Tuple::Values(values) => self.build_row(table, targets, values)?,
+        };
+        if let Some(upsert) = upsert {
+            if let Some(id) = table.conflicting_row(&upsert.keys, &row) {
+                return match upsert.action {
+                    UpsertAction::Nothing => Ok(Outcome::Skipped),
+                    UpsertAction::Update {
+                        assignments,
+                        selection,
+                    } => self.upsert_row(table_name, id, row, assignments, selection),
+                };
+            }
+        }
+        self.check_constraints(table, &row)?;
+        self.check_references(&Changes::default(), table_name, &row)?;
+        let table = self.tables.get_mut(table_name).ok_or("Table not found")?;
+        let id = table.insert_row(row)?;
+        self.undo.push(Undo::Inserted {
+            table: table_name.to_string(),
+            id,
+        });
+        Ok(Outcome::Inserted)
+    }
+
+    // Update the row `id` an upsert ran into. The assignments see its columns and, qualified
+    // with `excluded` or through VALUES(column), those of the `proposed` row.
+    fn upsert_row(
+        &mut self,
+        table_name: &str,
+        id: RowId,
+        proposed: Row,
+        assignments: &[Assignment],
+        selection: Option<&Expr>,
+    ) -> DbResult<Outcome> {
+        let table = self.tables.get(table_name).ok_or("Table not found")?;
+        let existing = table.store.get(id)?;
+        let mut columns = table.relation_columns(None);
+        let targets = Self::assignment_targets(assignments, &columns)?;
+        columns.extend(table.columns.iter().map(|column| RelationColumn {
+            table: Some(EXCLUDED.to_string()),
+            name: column.name.value.clone(),
+            hidden: true,
+        }));
+        let scope = Scope {
+            columns: &columns,
+            outer: None,
+            group: None,
+        };
+        let both = Row::new([existing.data.as_slice(), &proposed.data].concat());
+        if let Some(condition) = selection {
+            if !self.evaluate_condition(condition, &both, &scope)? {
+                return Ok(Outcome::Skipped);
+            }
+        }
+        let new_row = self.assign(table, &targets, assignments, &both, &scope)?;
+        let mut changes = Changes::default();
+        self.plan_update(&mut changes, table_name, id, existing, new_row)?;
+        self.apply_changes(changes)?;
+        Ok(Outcome::Updated)
+    }
+
+    // Row for one tuple of INSERT ... VALUES, whose values go to the columns at `targets`.
+    // Values are constant expressions, or DEFAULT.
+    fn values_row(&self, table: &Table, targets: &[usize], tuple: &[Expr]) -> DbResult<Row> {
+        if tuple.len() != targets.len() {
+            return Err("Number of values doesn't match number of columns".into());
+        }
+        let scope = Scope {
+            columns: &[],
+            outer: None,
+            group: None,
+        };
+        let values = tuple
+            .iter()
+            .zip(targets)
+            .map(|(expr, &position)| {
+                if Self::is_default(expr) {
+                    self.column_default(table, position)
+                } else {
+                    self.evaluate_expr(expr, &Row::new(Vec::new()), &scope)
+                }
+            })
+            .collect::<DbResult<Vec<Value>>>()?;
+        self.build_row(table, targets, values)
+    }
+
+    // Row of `table` holding `values` in the columns at `targets` and their default in the
+    // others. Values are converted to the column types here, rows only hold engine values.
+    fn build_row(&self, table: &Table, targets: &[usize], values: Vec<Value>) -> DbResult<Row> {
+        if values.len() != targets.len() {
+            return Err("Number of values doesn't match number of columns".into());
+        }
+        let mut data: Vec<Option<Value>> = vec![None; table.columns.len()];
+        for (&position, value) in targets.iter().zip(values) {
+            data[position] = Some(Table::coerce(&table.columns[position], value)?);
+        }
+        let data = data
+            .into_iter()
+            .enumerate()
+            .map(|(position, value)| match value {
+                Some(value) => Ok(value),
+                None => self.column_default(table, position),
+            })
+            .collect::<DbResult<Vec<Value>>>()?;
+        Ok(Row::new(data))
+    }
+
+    // Whether `expr` is the DEFAULT keyword, which stands for the default of a column in
+    // VALUES and SET
+    fn is_default(expr: &Expr) -> bool {
+        matches!(expr, Expr::Identifier(ident)
+            if ident.quote_style.is_none() && ident.value.eq_ignore_ascii_case("DEFAULT"))
+    }
+
+    // Check `row` against the NOT NULL and CHECK constraints of `table`
+    fn check_constraints(&self, table: &Table, row: &Row) -> DbResult<()> {
+        table.check_row(row)?;
+        if table.checks.is_empty() {
+            return Ok(());
+        }
+        let columns = table.relation_columns(None);
+        let scope = Scope {
+            columns: &columns,
+            outer: None,
+            group: None,
+        };
+        for check in &table.checks {
+            if self.evaluate_truth(&check.expr, row, &scope)? == Some(false) {
+                let column = match check.column {
+                    Some(position) => format!(
+                        " for column '{}' value {}",
+                        table.columns[position].name,
+                        describe_key(&row.data[position..=position])
+                    ),
+                    None => String::new(),
+                };
+                return Err(format!(
+                    "{} violated: {} is false{}",
+                    check.label(),
+                    check.expr,
+                    column
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    // Value of the column at `position` when none is given: its DEFAULT, or NULL without one
+    fn column_default(&self, table: &Table, position: usize) -> DbResult<Value> {
+        let column = &table.columns[position];
+        let default = column
+            .options
+            .iter()
+            .find_map(|option| match &option.option {
+                ColumnOption::Default(expr) => Some(expr),
+                _ => None,
+            });
+        let Some(expr) = default else {
+            return Ok(Value::Null);
+        };
+        let scope = Scope {
+            columns: &[],
+            outer: None,
+            group: None,
+        };
+        let value = self.evaluate_expr(expr, &Row::new(Vec::new()), &scope)?;
+        Table::coerce(column, value)
+    }
+
+    fn update(
+        &mut self,
+        table: &TableWithJoins,
+        assignments: &[Assignment],
+        selection: &Option<Expr>,
+    ) -> DbResult<QueryResult> {
+        let (table_name, alias) = match &table.relation {
+            TableFactor::Table { name, alias, .. } => (name.to_string(), alias.as_ref()),
+            _ => return Err("Unsupported UPDATE target".into()),
+        };
+        let table = self.tables.get(&table_name).ok_or("Table not found")?;
+        let columns = table.relation_columns(alias);
+
+        // Resolve assignment targets up front so an unknown column fails before any row changes
+        let targets = Self::assignment_targets(assignments, &columns)?;
+
+        // Plan every change first, so a type mismatch or a violated constraint leaves the table
+        // untouched
+        let scope = Scope {
+            columns: &columns,
+            outer: None,
+            group: None,
+        };
+        let mut changes = Changes::default();
+        let mut count = 0;
+        for entry in self.candidate_rows(table, &columns, selection.as_ref()) {
+            let (id, row) = entry?;
+            if let Some(condition) = selection {
+                if !self.evaluate_condition(condition, &row, &scope)? {
+                    continue;
+                }
+            }
+
+            let new_row = self.assign(table, &targets, assignments, &row, &scope)?;
+            self.plan_update(&mut changes, &table_name, id, row, new_row)?;
+            count += 1;
+        }
+
+        self.apply_changes(changes)?;
+        Ok(QueryResult::Success(format!("Updated {} row(s)", count)))
+    }
+
+    // Positions of the columns the SET clause of an UPDATE or an upsert assigns to
+    fn assignment_targets(
+        assignments: &[Assignment],
+        columns: &[RelationColumn],
+    ) -> DbResult<Vec<usize>> {
+        assignments
+            .iter()
+            .map(|assignment| {
+                let target = match assignment.id.as_slice() {
+                    [ident] => Expr::Identifier(ident.clone()),
+                    idents => Expr::CompoundIdentifier(idents.to_vec()),
+                };
+                Self::resolve_column(&target, columns)
+            })
+            .collect()
+    }
+
+    // New version of `row` with the assignments applied to the columns at `targets`. The values
+    // are evaluated against `row` as it was, DEFAULT stands for the default of the column.
+    fn assign(
+        &self,
+        table: &Table,
+        targets: &[usize],
+        assignments: &[Assignment],
+        row: &Row,
+        scope: &Scope,
+    ) -> DbResult<Row> {
+        let mut data = row.data[..table.columns.len()].to_vec();
+        for (&col_index, assignment) in targets.iter().zip(assignments) {
+            data[col_index] = if Self::is_default(&assignment.value) {
+                self.column_default(table, col_index)?
+            } else {
+                let value = self.evaluate_expr(&assignment.value, row, scope)?;
+                Table::coerce(&table.columns[col_index], value)?
+            };
+        }
+        Ok(Row::new(data))
+    }
+
+    fn delete(
+        &mut self,
+        from: &[TableWithJoins],
+        selection: &Option<Expr>,
+    ) -> DbResult<QueryResult> {
+        let (table_name, alias) = match from {
+            [table] if table.joins.is_empty() => match &table.relation {
+                TableFactor::Table { name, alias, .. } => (name.to_string(), alias.as_ref()),
+                _ => return Err("Unsupported DELETE target".into()),
+            },
+            _ => return Err("DELETE supports exactly one table".into()),
+        };
+        let table = self.tables.get(&table_name).ok_or("Table not found")?;
+
+        // Without a WHERE clause every row goes, no need to evaluate anything unless foreign
+        // keys have to act on the rows referencing them or the rows may have to come back: in
+        // a transaction, or in a durable database when the statement cannot be logged
+        if selection.is_none()
+            && !self.in_transaction()
+            && self.durability.is_none()
+            && self.references_to(&table_name).next().is_none()
+        {
+            let table = self.tables.get_mut(&table_name).ok_or("Table not found")?;
+            let count = table.store.len();
+            table.clear()?;
+            return Ok(QueryResult::Success(format!("Deleted {} row(s)", count)));
+        }
+
+        let columns = table.relation_columns(alias);
+        let scope = Scope {
+            columns: &columns,
+            outer: None,
+            group: None,
+        };
+        let mut changes = Changes::default();
+        let mut count = 0;
+        for entry in self.candidate_rows(table, &columns, selection.as_ref()) {
+            let (id, row) = entry?;
+            if let Some(condition) = selection {
+                if !self.evaluate_condition(condition, &row, &scope)? {
+                    continue;
+                }
+            }
+            self.plan_delete(&mut changes, &table_name, id, row)?;
+            count += 1;
+        }
+
+        self.apply_changes(changes)?;
+        Ok(QueryResult::Success(format!("Deleted {} row(s)", count)))
+    }
+
+    // Rows of `table` that may satisfy `selection`, read through an index when one applies to
+    // it and by a full scan otherwise. The caller still has to check the condition on them.
+    fn candidate_rows<'a>(
+        &self,
+        table: &'a Table,
+        columns: &[RelationColumn],
+        selection: Option<&Expr>,
+    ) -> RowIter<'a> {
+        let Some(ids) = selection.and_then(|condition| self.index_scan(table, columns, condition))
+        else {
+            return table.store.scan();
+        };
+        table.store.lookup(ids)
+    }
+
+    // Ids of the rows an index finds for the most selective indexable part of an AND chain
+    fn index_scan(
+        &self,
+        table: &Table,
+        columns: &[RelationColumn],
+        condition: &Expr,
+    ) -> Option<Vec<RowId>> {
+        match condition {
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::And,
+                right,
+            } => {
+                let left = self.index_scan(table, columns, left);
+                let right = self.index_scan(table, columns, right);
+                match (left, right) {
+                    (Some(left), Some(right)) if right.len() < left.len() => Some(right),
+                    (Some(left), _) => Some(left),
+                    (None, right) => right,
+                }
+            }
+            Expr::Nested(inner) => self.index_scan(table, columns, inner),
+            Expr::BinaryOp { left, op, right } => {
+                let equality = *op == BinaryOperator::Eq;
+                // Either `column op constant` or `constant op column`
+                let (index, key, op) = match self.index_key(table, columns, left, right, equality) {
+                    Some((index, key)) => (index, key, op.clone()),
+                    None => {
+                        let (index, key) = self.index_key(table, columns, right, left, equality)?;
+                        let flipped = match op {
+                            BinaryOperator::Lt => BinaryOperator::Gt,
+                            BinaryOperator::LtEq => BinaryOperator::GtEq,
+                            BinaryOperator::Gt => BinaryOperator::Lt,
+                            BinaryOperator::GtEq => BinaryOperator::LtEq,
+                            op => op.clone(),
+                        };
+                        (index, key, flipped)
+                    }
+                };
+                let (lower, upper) = match op {
+                    BinaryOperator::Eq => return Some(index.equal(&key)),
+                    BinaryOperator::Lt => (Bound::Unbounded, Bound::Excluded(&key)),
+                    BinaryOperator::LtEq => (Bound::Unbounded, Bound::Included(&key)),
+                    BinaryOperator::Gt => (Bound::Excluded(&key), Bound::Unbounded),
+                    BinaryOperator::GtEq => (Bound::Included(&key), Bound::Unbounded),
+                    _ => return None,
+                };
+                index.range(lower, upper)
+            }
+            Expr::Between {
+                expr,
+                negated: false,
+                low,
+                high,
+            } => {
+                let (index, low) = self.index_key(table, columns, expr, low, false)?;
+                let (_, high) = self.index_key(table, columns, expr, high, false)?;
+                index.range(Bound::Included(&low), Bound::Included(&high))
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated: false,
+            } => {
+                let mut ids = Vec::new();
+                for item in list {
+                    let (index, key) = self.index_key(table, columns, expr, item, true)?;
+                    ids.extend(index.equal(&key));
+                }
+                Some(ids)
+            }
+            _ => None,
+        }
+    }
+
+    // An index on `column` usable for an equality or range lookup, together with `constant`
+    // converted to the column's type. None when the column has no such index or the constant
+    // cannot be looked up in it: it depends on a row, or the conversion would change its value.
+    fn index_key<'a>(
+        &self,
+        table: &'a Table,
+        columns: &[RelationColumn],
+        column: &Expr,
+        constant: &Expr,
+        equality: bool,
+    ) -> Option<(&'a Index, Value)> {
+        let position = Self::find_column(column, columns).ok()??;
+        let index = table.index_on(position, equality)?;
+        let scope = Scope {
+            columns: &[],
+            outer: None,
+            group: None,
+        };
+        let value = self
+            .evaluate_expr(constant, &Row::new(Vec::new()), &scope)
+            .ok()?;
+        Some((index, Table::exact_key(value, &table.columns[position])?))
+    }
+
+    // WHERE, HAVING and ON conditions: a row qualifies only when the condition is TRUE, FALSE
+    // and UNKNOWN both reject it
+    fn evaluate_condition(&self, condition: &Expr, row: &Row, scope: &Scope) -> DbResult<bool> {
+        Ok(self.evaluate_truth(condition, row, scope)? == Some(true))
+    }
+
+    // Evaluate a predicate with SQL three-valued logic, None stands for UNKNOWN
+    fn evaluate_truth(&self, condition: &Expr, row: &Row, scope: &Scope) -> DbResult<Option<bool>> {
+        Ok(match condition {
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::And,
+                right,
+            } => {
+                // FALSE decides an AND on its own, the right side is not evaluated then
+                match self.evaluate_truth(left, row, scope)? {
+                    Some(false) => Some(false),
+                    left => match (left, self.evaluate_truth(right, row, scope)?) {
+                        (_, Some(false)) => Some(false),
+                        (Some(true), Some(true)) => Some(true),
+                        _ => None,
+                    },
+                }
+            }
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::Or,
+                right,
+            } => match self.evaluate_truth(left, row, scope)? {
+                Some(true) => Some(true),
+                left => match (left, self.evaluate_truth(right, row, scope)?) {
+                    (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                },
+            },
+            Expr::BinaryOp { left, op, right } if Self::is_comparison(op) => {
+                let left = self.evaluate_expr(left, row, scope)?;
+                let right = self.evaluate_expr(right, row, scope)?;
+                Self::compare_values(&left, &right)?.map(|ordering| match op {
+                    BinaryOperator::Eq => ordering == Ordering::Equal,
+                    BinaryOperator::NotEq => ordering != Ordering::Equal,
+                    BinaryOperator::Gt => ordering == Ordering::Greater,
+                    BinaryOperator::Lt => ordering == Ordering::Less,
+                    BinaryOperator::GtEq => ordering != Ordering::Less,
+                    _ => ordering != Ordering::Greater,
+                })
+            }
+            Expr::UnaryOp {
+                op: UnaryOperator::Not,
+                expr,
+            } => self.evaluate_truth(expr, row, scope)?.map(|truth| !truth),
+            Expr::Nested(expr) => self.evaluate_truth(expr, row, scope)?,
+            Expr::IsNull(expr) => Some(self.evaluate_expr(expr, row, scope)? == Value::Null),
+            Expr::IsNotNull(expr) => Some(self.evaluate_expr(expr, row, scope)? != Value::Null),
+            Expr::IsTrue(expr) => Some(self.evaluate_truth(expr, row, scope)? == Some(true)),
+            Expr::IsNotTrue(expr) => Some(self.evaluate_truth(expr, row, scope)? != Some(true)),
+            Expr::IsFalse(expr) => Some(self.evaluate_truth(expr, row, scope)? == Some(false)),
+            Expr::IsNotFalse(expr) => Some(self.evaluate_truth(expr, row, scope)? != Some(false)),
+            Expr::IsUnknown(expr) => Some(self.evaluate_truth(expr, row, scope)?.is_none()),
+            Expr::IsNotUnknown(expr) => Some(self.evaluate_truth(expr, row, scope)?.is_some()),
+            Expr::IsDistinctFrom(left, right) => Some(self.is_distinct(left, right, row, scope)?),
+            Expr::IsNotDistinctFrom(left, right) => {
+                Some(!self.is_distinct(left, right, row, scope)?)
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let value = self.evaluate_expr(expr, row, scope)?;
+                let candidates = list
+                    .iter()
+                    .map(|e| self.evaluate_expr(e, row, scope))
+                    .collect::<DbResult<Vec<Value>>>()?;
+                Self::contains_value(&value, &candidates)?.map(|found| found != *negated)
+            }
+            Expr::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => {
+                let value = self.evaluate_expr(expr, row, scope)?;
+                let candidates = self.evaluate_subquery(subquery, row, scope)?;
+                Self::contains_value(&value, &candidates)?.map(|found| found != *negated)
+            }
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                let value = self.evaluate_expr(expr, row, scope)?;
+                let low = self.evaluate_expr(low, row, scope)?;
+                let high = self.evaluate_expr(high, row, scope)?;
+                let above_low = Self::compare_values(&value, &low)?.map(|o| o != Ordering::Less);
+                let below_high =
+                    Self::compare_values(&value, &high)?.map(|o| o != Ordering::Greater);
+                let between = match (above_low, below_high) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                };
+                between.map(|between| between != *negated)
+            }
+            Expr::Like {
+                negated,
+                expr,
+                pattern,
+                escape_char,
+            } => self
+                .evaluate_pattern(expr, pattern, row, scope, |text, pattern| {
+                    pattern::like(text, pattern, *escape_char, false)
+                })?
+                .map(|matched| matched != *negated),
+            Expr::ILike {
+                negated,
+                expr,
+                pattern,
+                escape_char,
+            } => self
+                .evaluate_pattern(expr, pattern, row, scope, |text, pattern| {
+                    pattern::like(text, pattern, *escape_char, true)
+                })?
+                .map(|matched| matched != *negated),
+            Expr::SimilarTo {
+                negated,
+                expr,
+                pattern,
+                escape_char,
+            } => self
+                .evaluate_pattern(expr, pattern, row, scope, |text, pattern| {
+                    pattern::similar_to(text, pattern, *escape_char)
+                })?
+                .map(|matched| matched != *negated),
+            Expr::Exists { subquery, negated } => {
+                let outer = OuterRow { row, scope: *scope };
+                let found = !self.run_query(subquery, Some(&outer))?.rows.is_empty();
+                Some(found != *negated)
+            }
+            // Anything else is a value that has to be a boolean, e.g. a column or CASE
+            _ => Self::truth_value(self.evaluate_expr(condition, row, scope)?)?,
+        })
+    }
+
+    fn is_comparison(op: &BinaryOperator) -> bool {
+        matches!(
+            op,
+            BinaryOperator::Eq
+                | BinaryOperator::NotEq
+                | BinaryOperator::Gt
+                | BinaryOperator::Lt
+                | BinaryOperator::GtEq
+                | BinaryOperator::LtEq
+        )
+    }
+
+    // Compare two values, None when either is NULL. Values of unrelated types are an error
+    // rather than silently unequal.
+    fn compare_values(left: &Value, right: &Value) -> DbResult<Option<Ordering>> {
+        if *left == Value::Null || *right == Value::Null {
+            return Ok(None);
+        }
+        match left.compare(right) {
+            Some(ordering) => Ok(Some(ordering)),
+            None => Err(format!("Cannot compare {} with {}", left, right).into()),
+        }
+    }
+
+    // `value IN (candidates)`: TRUE on a match, otherwise UNKNOWN if NULL was involved
+    fn contains_value(value: &Value, candidates: &[Value]) -> DbResult<Option<bool>> {
+        let mut unknown = false;
+        for candidate in candidates {
+            match Self::compare_values(value, candidate)? {
+                Some(Ordering::Equal) => return Ok(Some(true)),
+                Some(_) => {}
+                None => unknown = true,
+            }
+        }
+        Ok(if unknown { None } else { Some(false) })
+    }
+
+    // IS DISTINCT FROM treats NULL as an ordinary value, so the result is never UNKNOWN
+    fn is_distinct(&self, left: &Expr, right: &Expr, row: &Row, scope: &Scope) -> DbResult<bool> {
+        let left = self.evaluate_expr(left, row, scope)?;
+        let right = self.evaluate_expr(right, row, scope)?;
+        Ok(match (&left, &right) {
+            (Value::Null, Value::Null) => false,
+            (Value::Null, _) | (_, Value::Null) => true,
+            _ => Self::compare_values(&left, &right)? != Some(Ordering::Equal),
+        })
+    }
+
+    // Read a value as a truth value. Numbers count as true when non-zero, like in MySQL.
+    fn truth_value(value: Value) -> DbResult<Option<bool>> {
+        match value {
+            Value::Null => Ok(None),
+            Value::Boolean(b) => Ok(Some(b)),
+            number if number.is_numeric() => Ok(Some(
+                number.compare(&Value::Integer(0)) != Some(Ordering::Equal),
+            )),
+            other => Err(format!("'{}' is not a boolean", other).into()),
+        }
+    }
 
-            // String types
-            (Value::SingleQuotedString(_), DataType::Text) => true,
-            (Value::SingleQuotedString(_), DataType::String) => true,
-            (Value::SingleQuotedString(_), DataType::Varchar(_)) => true,
-            (Value::DoubleQuotedString(_), DataType::Text) => true,
-            (Value::DoubleQuotedString(_), DataType::String) => true,
-            (Value::DoubleQuotedString(_), DataType::Varchar(_)) => true,
+    // Match a value against a LIKE / ILIKE / SIMILAR TO pattern, UNKNOWN when either side is
+    // NULL
+    fn evaluate_pattern(
+        &self,
+        expr: &Expr,
+        pattern: &Expr,
+        row: &Row,
+        scope: &Scope,
+        matcher: impl Fn(&str, &str) -> DbResult<bool>,
+    ) -> DbResult<Option<bool>> {
+        let text = |value: Value| match value {
+            Value::Null => Ok(None),
+            Value::Text(s) => Ok(Some(s)),
+            // Numbers are matched on their textual form, like MySQL does
+            number if number.is_numeric() => Ok(Some(number.to_string())),
+            other => Err(format!(
+                "Pattern matching is not supported for value {}",
+                other
+            )),
+        };
+        let value = text(self.evaluate_expr(expr, row, scope)?)?;
+        let pattern = text(self.evaluate_expr(pattern, row, scope)?)?;
+        match (value, pattern) {
+            (Some(value), Some(pattern)) => Ok(Some(matcher(&value, &pattern)?)),
+            _ => Ok(None),
+        }
+    }
 
-            // Boolean type
-            (Value::Boolean(_), DataType::Boolean) => true,
+    fn evaluate_expr(&self, expr: &Expr, row: &Row, scope: &Scope) -> DbResult<Value> {
+        match expr {
+            Expr::Identifier(_) | Expr::CompoundIdentifier(_) => {
+                Self::column_value(expr, row, scope)
+            }
+            Expr::Subquery(query) => {
+                let mut values = self.evaluate_subquery(query, row, scope)?;
+                match values.len() {
+                    0 => Ok(Value::Null),
+                    1 => Ok(values.remove(0)),
+                    _ => Err("Scalar subquery returned more than one row".into()),
+                }
+            }
+            Expr::Value(v) => Value::from_literal(v),
+            Expr::TypedString { data_type, value } => Value::Text(value.clone())
+                .coerce(data_type)
+                .ok_or_else(|| format!("Invalid {} literal '{}'", data_type, value).into()),
+            Expr::Nested(expr) => self.evaluate_expr(expr, row, scope),
+            Expr::Cast { expr, data_type } => {
+                let value = self.evaluate_expr(expr, row, scope)?;
+                value
+                    .clone()
+                    .cast(data_type)
+                    .ok_or_else(|| format!("Cannot cast '{}' to {}", value, data_type).into())
+            }
+            Expr::BinaryOp { left, op, right } => {
+                let op = match op {
+                    BinaryOperator::Plus => Arithmetic::Add,
+                    BinaryOperator::Minus => Arithmetic::Subtract,
+                    BinaryOperator::Multiply => Arithmetic::Multiply,
+                    BinaryOperator::Divide => Arithmetic::Divide,
+                    BinaryOperator::MyIntegerDivide => Arithmetic::IntegerDivide,
+                    BinaryOperator::Modulo => Arithmetic::Modulo,
+                    BinaryOperator::StringConcat => {
+                        let left = self.evaluate_expr(left, row, scope)?;
+                        let right = self.evaluate_expr(right, row, scope)?;
+                        return Ok(match (left, right) {
+                            (Value::Null, _) | (_, Value::Null) => Value::Null,
+                            (left, right) => Value::Text(format!("{}{}", left, right)),
+                        });
+                    }
+                    // Comparisons and logical operators yield booleans
+                    BinaryOperator::And | BinaryOperator::Or => {
+                        return Ok(Self::truth_to_value(self.evaluate_truth(expr, row, scope)?))
+                    }
+                    op if Self::is_comparison(op) => {
+                        return Ok(Self::truth_to_value(self.evaluate_truth(expr, row, scope)?))
+                    }
+                    _ => return Err(format!("Unsupported operator '{}'", op).into()),
+                };
+                let left = self.evaluate_expr(left, row, scope)?;
+                left.arithmetic(op, &self.evaluate_expr(right, row, scope)?)
+            }
+            Expr::UnaryOp { op, expr: operand } => match op {
+                UnaryOperator::Minus => self.evaluate_expr(operand, row, scope)?.negate(),
+                UnaryOperator::Plus => match self.evaluate_expr(operand, row, scope)? {
+                    value @ Value::Null => Ok(value),
+                    value if value.is_numeric() => Ok(value),
+                    value => Err(format!("Cannot apply unary + to {}", value).into()),
+                },
+                UnaryOperator::Not => Ok(Self::truth_to_value(
+                    self.evaluate_truth(operand, row, scope)?
+                        .map(|truth| !truth),
+                )),
+                _ => Err(format!("Unsupported operator '{}'", op).into()),
+            },
+            Expr::Case {
+                operand,
+                conditions,
+                results,
+                else_result,
+            } => {
+                let operand = match operand {
+                    Some(operand) => Some(self.evaluate_expr(operand, row, scope)?),
+                    None => None,
+                };
+                for (condition, result) in conditions.iter().zip(results) {
+                    // `CASE x WHEN v` compares values, `CASE WHEN cond` evaluates conditions
+                    let matched = match &operand {
+                        Some(value) => {
+                            value.compare(&self.evaluate_expr(condition, row, scope)?)
+                                == Some(Ordering::Equal)
+                        }
+                        None => self.evaluate_condition(condition, row, scope)?,
+                    };
+                    if matched {
+                        return self.evaluate_expr(result, row, scope);
+                    }
+                }
+                match else_result {
+                    Some(else_result) => self.evaluate_expr(else_result, row, scope),
+                    None => Ok(Value::Null),
+                }
+            }
+            Expr::Function(func) => self.evaluate_function(func, row, scope),
+            // Predicates used as values
+            Expr::IsNull(_)
+            | Expr::IsNotNull(_)
+            | Expr::InList { .. }
+            | Expr::InSubquery { .. }
+            | Expr::Between { .. }
+            | Expr::Like { .. }
+            | Expr::ILike { .. }
+            | Expr::SimilarTo { .. }
+            | Expr::Exists { .. }
+            | Expr::IsTrue(_)
+            | Expr::IsNotTrue(_)
+            | Expr::IsFalse(_)
+            | Expr::IsNotFalse(_)
+            | Expr::IsUnknown(_)
+            | Expr::IsNotUnknown(_)
+            | Expr::IsDistinctFrom(..)
+            | Expr::IsNotDistinctFrom(..) => {
+                Ok(Self::truth_to_value(self.evaluate_truth(expr, row, scope)?))
+            }
+            _ => Err(format!("Unsupported expression '{}'", expr).into()),
+        }
+    }
 
-            // Null can match any type
-            (Value::Null, _) => true,
+    fn truth_to_value(truth: Option<bool>) -> Value {
+        truth.map_or(Value::Null, Value::Boolean)
+    }
 
-            // If no match is found, return false
+    // Whether an expression contains an aggregate call, not counting subqueries which are
+    // aggregated on their own
+    fn contains_aggregate(expr: &Expr) -> bool {
+        match expr {
+            Expr::Function(func) => Self::function_contains_aggregate(func),
+            Expr::BinaryOp { left, right, .. } => {
+                Self::contains_aggregate(left) || Self::contains_aggregate(right)
+            }
+            Expr::UnaryOp { expr, .. }
+            | Expr::Nested(expr)
+            | Expr::IsNull(expr)
+            | Expr::IsNotNull(expr)
+            | Expr::Cast { expr, .. } => Self::contains_aggregate(expr),
+            Expr::Between {
+                expr, low, high, ..
+            } => [expr, low, high]
+                .iter()
+                .any(|e| Self::contains_aggregate(e)),
+            Expr::InList { expr, list, .. } => {
+                Self::contains_aggregate(expr) || list.iter().any(Self::contains_aggregate)
+            }
+            Expr::Like { expr, pattern, .. }
+            | Expr::ILike { expr, pattern, .. }
+            | Expr::SimilarTo { expr, pattern, .. } => {
+                Self::contains_aggregate(expr) || Self::contains_aggregate(pattern)
+            }
+            Expr::Case {
+                operand,
+                conditions,
+                results,
+                else_result,
+            } => operand
+                .iter()
+                .chain(else_result)
+                .map(|e| &**e)
+                .chain(conditions)
+                .chain(results)
+                .any(Self::contains_aggregate),
             _ => false,
         }
     }
+
+    fn function_contains_aggregate(func: &Function) -> bool {
+        Self::is_aggregate(func)
+            || func.args.iter().any(|arg| match arg {
+                FunctionArg::Unnamed(FunctionArgExpr::Expr(expr))
+                | FunctionArg::Named {
+                    arg: FunctionArgExpr::Expr(expr),
+                    ..
+                } => Self::contains_aggregate(expr),
+                _ => false,
+            })
+    }
+
+    fn is_aggregate(func: &Function) -> bool {
+        matches!(
+            func.name.to_string().to_uppercase().as_str(),
+            "COUNT" | "SUM" | "AVG" | "MIN" | "MAX"
+        )
+    }
+
+    // Evaluate a function call for the current row. Aggregates read the rows of the group being
+    // aggregated, scalar functions only their arguments.
+    fn evaluate_function(&self, func: &Function, row: &Row, scope: &Scope) -> DbResult<Value> {
+        // VALUES(column) in ON DUPLICATE KEY UPDATE: the value the INSERT proposed
+        if func.name.to_string().eq_ignore_ascii_case("VALUES") {
+            return match func.args.as_slice() {
+                [FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Identifier(column)))] => {
+                    let proposed =
+                        Expr::CompoundIdentifier(vec![Ident::new(EXCLUDED), column.clone()]);
+                    match Self::find_column(&proposed, scope.columns)? {
+                        Some(index) => Ok(row.data[index].clone()),
+                        None => Err(format!(
+                            "{} is only allowed in ON DUPLICATE KEY UPDATE, for a column of the table",
+                            func
+                        )
+                        .into()),
+                    }
+                }
+                _ => Err(format!("Invalid arguments for {}", func).into()),
+            };
+        }
+        if Self::is_aggregate(func) {
+            let rows = scope
+                .group
+                .ok_or_else(|| format!("Aggregate function {} is not allowed here", func))?;
+            // Arguments are evaluated per row, so aggregates cannot be nested
+            let row_scope = Scope {
+                group: None,
+                ..*scope
+            };
+            return self.evaluate_aggregate(func, rows, &row_scope);
+        }
+
+        let args = func
+            .args
+            .iter()
+            .map(|arg| match arg {
+                FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => {
+                    self.evaluate_expr(expr, row, scope)
+                }
+                _ => Err(format!("Invalid arguments for {}", func).into()),
+            })
+            .collect::<DbResult<Vec<Value>>>()?;
+        let name = func.name.to_string().to_uppercase();
+        match (name.as_str(), args.as_slice()) {
+            ("COALESCE", _) => Ok(args
+                .into_iter()
+                .find(|value| *value != Value::Null)
+                .unwrap_or(Value::Null)),
+            ("IFNULL", [Value::Null, fallback]) => Ok(fallback.clone()),
+            ("IFNULL", [value, _]) => Ok(value.clone()),
+            ("NULLIF", [value, other]) => match value.compare(other) {
+                Some(Ordering::Equal) => Ok(Value::Null),
+                _ => Ok(value.clone()),
+            },
+            ("ABS" | "UPPER" | "LOWER" | "LENGTH" | "CHAR_LENGTH", [Value::Null]) => {
+                Ok(Value::Null)
+            }
+            ("ABS", [value]) if value.is_numeric() => match value.compare(&Value::Integer(0)) {
+                Some(Ordering::Less) => value.negate(),
+                _ => Ok(value.clone()),
+            },
+            ("UPPER", [Value::Text(s)]) => Ok(Value::Text(s.to_uppercase())),
+            ("LOWER", [Value::Text(s)]) => Ok(Value::Text(s.to_lowercase())),
+            ("LENGTH", [Value::Text(s)]) => Ok(Value::Integer(s.len() as i64)),
+            ("CHAR_LENGTH", [Value::Text(s)]) => Ok(Value::Integer(s.chars().count() as i64)),
+            ("CONCAT", _) if args.contains(&Value::Null) => Ok(Value::Null),
+            ("CONCAT", _) => Ok(Value::Text(
+                args.iter().map(|value| value.to_string()).collect(),
+            )),
+            ("IFNULL" | "NULLIF" | "ABS" | "UPPER" | "LOWER" | "LENGTH" | "CHAR_LENGTH", _) => {
+                Err(format!("Invalid arguments for {}", func).into())
+            }
+            _ => Err(format!("Unsupported function '{}'", func.name).into()),
+        }
+    }
+
+    // Evaluate an aggregate function over a group of rows
+    fn evaluate_aggregate(&self, func: &Function, rows: &[Row], scope: &Scope) -> DbResult<Value> {
+        let name = func.name.to_string().to_uppercase();
+
+        // COUNT(*) counts rows, every other form works on the non-NULL values of its argument
+        let arg = match func.args.as_slice() {
+            [FunctionArg::Unnamed(FunctionArgExpr::Wildcard)] if name == "COUNT" => {
+                return Ok(Value::Integer(rows.len() as i64));
+            }
+            [FunctionArg::Unnamed(FunctionArgExpr::Expr(expr))] => expr,
+            _ => return Err(format!("Invalid arguments for {}", func).into()),
+        };
+        let mut values = Vec::new();
+        let mut seen = HashSet::new();
+        for row in rows {
+            let value = self.evaluate_expr(arg, row, scope)?;
+            if value == Value::Null || (func.distinct && !seen.insert(value.clone())) {
+                continue;
+            }
+            values.push(value);
+        }
+
+        match name.as_str() {
+            "COUNT" => Ok(Value::Integer(values.len() as i64)),
+            "SUM" | "AVG" => {
+                if values.is_empty() {
+                    return Ok(Value::Null);
+                }
+                let mut sum = Value::Integer(0);
+                for value in &values {
+                    if !value.is_numeric() {
+                        return Err(format!("{} expects numbers, found {}", name, value).into());
+                    }
+                    sum = sum.arithmetic(Arithmetic::Add, value)?;
+                }
+                if name == "SUM" {
+                    return Ok(sum);
+                }
+                // Exact averages are decimals, like `/` on exact numbers
+                sum.arithmetic(Arithmetic::Divide, &Value::Integer(values.len() as i64))
+            }
+            "MIN" => Ok(values
+                .into_iter()
+                .min_by(|a, b| a.total_cmp(b))
+                .unwrap_or(Value::Null)),
+            "MAX" => Ok(values
+                .into_iter()
+                .max_by(|a, b| a.total_cmp(b))
+                .unwrap_or(Value::Null)),
+            _ => Err(format!("Unsupported function '{}'", func.name).into()),
+        }
+    }
+}
+
+// Column of an intermediate relation (a table scan or the result of a join)
+#[derive(Debug, Clone)]
+struct RelationColumn {
+    // Table the column came from, None for columns merged by USING / NATURAL joins
+    table: Option<String>,
+    name: String,
+    // Hidden columns are only reachable through a table qualifier
+    hidden: bool,
+}
+
+impl RelationColumn {
+    // Whether a qualifier such as `u` or `public.users` refers to this column's table
+    fn matches_qualifier(&self, qualifier: &[Ident]) -> bool {
+        let Some(table) = &self.table else {
+            return false;
+        };
+        let qualifier = qualifier
+            .iter()
+            .map(|ident| ident.value.as_str())
+            .collect::<Vec<_>>()
+            .join(".");
+        // A schema-qualified table can also be referenced by its bare name
+        table == &qualifier || table.ends_with(&format!(".{}", qualifier))
+    }
+}
+
+// Expression context: the columns of the relation being evaluated, inside a correlated
+// subquery the current row of the enclosing query and, while aggregating, the rows of the group
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    columns: &'a [RelationColumn],
+    outer: Option<&'a OuterRow<'a>>,
+    group: Option<&'a [Row]>,
+}
+
+// Row of an enclosing query, visible to the subqueries evaluated for it
+struct OuterRow<'a> {
+    row: &'a Row,
+    scope: Scope<'a>,
+}
+
+// One row to insert, as written in VALUES or as returned by INSERT ... SELECT
+enum Tuple<'a> {
+    Exprs(&'a [Expr]),
+    Values(Vec<Value>),
+}
+
+// What an INSERT does with a row whose unique key is already taken
+struct Upsert<'a> {
+    // Positions of the unique indexes whose keys count, among the table's indexes
+    keys: Vec<usize>,
+    action: UpsertAction<'a>,
+}
+
+enum UpsertAction<'a> {
+    Nothing,
+    // Update the row holding the key, if the condition is true for it
+    Update {
+        assignments: &'a [Assignment],
+        selection: Option<&'a Expr>,
+    },
+}
+
+// What became of one row of an INSERT
+enum Outcome {
+    Inserted,
+    Updated,
+    Skipped,
+}
+
+// A single item of the SELECT list
+#[derive(Debug, Clone)]
+enum Projection {
+    // Column of the FROM relation, already resolved to its position
+    Column(usize),
+    Function(Function),
+    Expr(Expr),
+}
+
+pub struct Table {
+    name: String,
+    columns: Vec<ColumnDef>,
+    // Table-level constraints as declared, column constraints are among the column options
+    constraints: Vec<TableConstraint>,
+    not_null: Vec<NotNull>,
+    // Positions of the primary key columns
+    primary_key: Vec<usize>,
+    foreign_keys: Vec<ForeignKey>,
+    checks: Vec<Check>,
+    // Name of the storage engine that created the store
+    engine: String,
+    store: Box<dyn TableStore>,
+    indexes: Vec<Index>,
+}
+
+impl Table {
+    fn new(
+        table_name: String,
+        columns: Vec<ColumnDef>,
+        constraints: Vec<TableConstraint>,
+        engine: String,
+        store: Box<dyn TableStore>,
+    ) -> DbResult<Self> {
+        let derived = Constraints::derive(&table_name, &columns, &constraints)?;
+        let mut table = Table {
+            name: table_name,
+            columns,
+            constraints,
+            not_null: derived.not_null,
+            primary_key: derived.primary_key,
+            foreign_keys: derived.foreign_keys,
+            checks: derived.checks,
+            engine,
+            store,
+            indexes: Vec::new(),
+        };
+        for key in derived.keys {
+            table.add_index(key)?;
+        }
+        Ok(table)
+    }
+
+    // Check a row against the constraints unique indexes do not cover
+    fn check_row(&self, row: &Row) -> DbResult<()> {
+        for not_null in &self.not_null {
+            not_null.check(&self.columns, row)?;
+        }
+        Ok(())
+    }
+
+    // Fail when `rows` would duplicate a unique key, the rows in `replaced` aside
+    fn check_unique(&self, rows: &[&Row], replaced: &HashSet<RowId>) -> DbResult<()> {
+        for index in &self.indexes {
+            index.check_unique(rows, replaced)?;
+        }
+        Ok(())
+    }
+
+    // Store a new row and add it to every index
+    fn insert_row(&mut self, row: Row) -> DbResult<RowId> {
+        self.check_unique(&[&row], &HashSet::new())?;
+        self.put_row(row)
+    }
+
+    // Replace rows by their new versions. Unique keys are checked for all of them first, so a
+    // duplicate leaves the table untouched. Returns the id each row is stored under now, with
+    // its old version.
+    fn update_rows(&mut self, changes: Vec<(RowId, Row)>) -> DbResult<Vec<(RowId, Row)>> {
+        let replaced: HashSet<RowId> = changes.iter().map(|(id, _)| *id).collect();
+        let rows: Vec<&Row> = changes.iter().map(|(_, row)| row).collect();
+        self.check_unique(&rows, &replaced)?;
+        changes
+            .into_iter()
+            .map(|(id, row)| self.replace_row(id, row))
+            .collect()
+    }
+
+    // Delete rows, returning their last versions
+    fn delete_rows(&mut self, ids: &[RowId]) -> DbResult<Vec<Row>> {
+        ids.iter().map(|&id| self.remove_row(id)).collect()
+    }
+
+    // The single row changes below keep the indexes up to date but leave the unique checks to
+    // their callers
+
+    fn put_row(&mut self, row: Row) -> DbResult<RowId> {
+        let id = self.store.insert(row.clone())?;
+        for index in &mut self.indexes {
+            index.insert(&row, id);
+        }
+        Ok(id)
+    }
+
+    fn replace_row(&mut self, id: RowId, row: Row) -> DbResult<(RowId, Row)> {
+        let old = self.store.get(id)?;
+        let new_id = self.store.update(id, row.clone())?;
+        for index in &mut self.indexes {
+            index.remove(&old, id);
+            index.insert(&row, new_id);
+        }
+        Ok((new_id, old))
+    }
+
+    fn remove_row(&mut self, id: RowId) -> DbResult<Row> {
+        let old = self.store.get(id)?;
+        for index in &mut self.indexes {
+            index.remove(&old, id);
+        }
+        self.store.delete(id)?;
+        Ok(old)
+    }
+
+    fn clear(&mut self) -> DbResult<()> {
+        self.store.clear()?;
+        for index in &mut self.indexes {
+            index.clear();
+        }
+        Ok(())
+    }
+
+    // Index to look up values of the column at `position`, hash indexes first for equality
+    fn index_on(&self, position: usize, equality: bool) -> Option<&Index> {
+        self.indexes
+            .iter()
+            .filter(|index| index.serves(position, equality))
+            .min_by_key(|index| index.def.kind != IndexKind::Hash)
+    }
+
+    // `value` converted to the type of `column` for an index lookup, None when the conversion
+    // would change it (1.5 for an INT column) so that the index cannot answer the comparison
+    fn exact_key(value: Value, column: &ColumnDef) -> Option<Value> {
+        let key = value.clone().coerce(&column.data_type)?;
+        (key.compare(&value) == Some(Ordering::Equal)).then_some(key)
+    }
+
+    // Build an index over the rows already in the table
+    fn add_index(&mut self, def: IndexDef) -> DbResult<()> {
+        let positions = def
+            .columns
+            .iter()
+            .map(|name| {
+                self.columns
+                    .iter()
+                    .position(|column| column.name.value == *name)
+                    .ok_or_else(|| format!("Column '{}' not found", name).into())
+            })
+            .collect::<DbResult<Vec<usize>>>()?;
+        let mut index = Index::new(def, positions);
+        for entry in self.store.scan() {
+            let (id, row) = entry?;
+            index.check_unique(&[&row], &HashSet::new())?;
+            index.insert(&row, id);
+        }
+        self.indexes.push(index);
+        Ok(())
+    }
+
+    // Positions of the columns an INSERT column list names, in its order. Without a list the
+    // values go to every column in declaration order.
+    fn insert_targets(&self, columns: &[Ident]) -> DbResult<Vec<usize>> {
+        if columns.is_empty() {
+            return Ok((0..self.columns.len()).collect());
+        }
+        let mut targets = Vec::new();
+        for ident in columns {
+            let position = self
+                .columns
+                .iter()
+                .position(|column| column.name.value == ident.value)
+                .ok_or_else(|| format!("Unknown column '{}' in table '{}'", ident, self.name))?;
+            if targets.contains(&position) {
+                return Err(format!("Column '{}' specified twice", ident).into());
+            }
+            targets.push(position);
+        }
+        Ok(targets)
+    }
+
+    // Unique indexes an upsert looks for conflicts in: the one matching the ON CONFLICT columns
+    // or constraint name, every one without a target
+    fn conflict_keys(&self, target: Option<&ConflictTarget>) -> DbResult<Vec<usize>> {
+        let unique = self
+            .indexes
+            .iter()
+            .enumerate()
+            .filter(|(_, index)| index.def.unique);
+        let keys: Vec<usize> = match target {
+            None => unique.map(|(position, _)| position).collect(),
+            Some(ConflictTarget::Columns(columns)) => {
+                let mut names: Vec<&str> = columns.iter().map(|c| c.value.as_str()).collect();
+                names.sort_unstable();
+                unique
+                    .filter(|(_, index)| {
+                        let mut key: Vec<&str> =
+                            index.def.columns.iter().map(String::as_str).collect();
+                        key.sort_unstable();
+                        key == names
+                    })
+                    .map(|(position, _)| position)
+                    .take(1)
+                    .collect()
+            }
+            Some(ConflictTarget::OnConstraint(name)) => unique
+                .filter(|(_, index)| index.def.name == name.to_string())
+                .map(|(position, _)| position)
+                .collect(),
+        };
+        match (target, keys.is_empty()) {
+            (Some(ConflictTarget::Columns(columns)), true) => Err(format!(
+                "No PRIMARY KEY or UNIQUE key of '{}' matches the ON CONFLICT {}",
+                self.name,
+                describe_columns(
+                    &columns
+                        .iter()
+                        .map(|c| c.value.clone())
+                        .collect::<Vec<String>>()
+                )
+            )
+            .into()),
+            (Some(ConflictTarget::OnConstraint(name)), true) => Err(format!(
+                "No PRIMARY KEY or UNIQUE constraint '{}' on table '{}'",
+                name, self.name
+            )
+            .into()),
+            _ => Ok(keys),
+        }
+    }
+
+    // Stored row already holding one of the `keys` of `row`
+    fn conflicting_row(&self, keys: &[usize], row: &Row) -> Option<RowId> {
+        keys.iter()
+            .find_map(|&key| self.indexes[key].find(row).first().copied())
+    }
+
+    fn column_names(&self, positions: &[usize]) -> Vec<String> {
+        positions
+            .iter()
+            .map(|&position| self.columns[position].name.value.clone())
+            .collect()
+    }
+
+    // Columns of this table as seen by expressions evaluated against its rows. An alias
+    // replaces the table name as qualifier.
+    fn relation_columns(&self, alias: Option<&TableAlias>) -> Vec<RelationColumn> {
+        let qualifier = match alias {
+            Some(alias) => alias.name.value.clone(),
+            None => self.name.clone(),
+        };
+        self.columns
+            .iter()
+            .map(|column| RelationColumn {
+                table: Some(qualifier.clone()),
+                name: column.name.value.clone(),
+                hidden: false,
+            })
+            .collect()
+    }
+
+    // Convert a value to the declared type of a column
+    fn coerce(column: &ColumnDef, value: Value) -> DbResult<Value> {
+        value
+            .coerce(&column.data_type)
+            .ok_or_else(|| format!("Type mismatch for column '{}'", column.name).into())
+    }
 }
+
 // Represent a query result
 #[derive(Debug, Clone)]
 pub enum QueryResult {
     Success(String),
     Rows(SelectResult),
+    // Result of an INSERT with ON DUPLICATE KEY UPDATE or ON CONFLICT
+    Upserted { inserted: usize, updated: usize },
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -670,14 +2799,14 @@ pub struct SelectResultResponse {
     pub rows: Vec<Vec<String>>,
 }
 
-impl Into<SelectResultResponse> for SelectResult {
-    fn into(self) -> SelectResultResponse {
+impl From<SelectResult> for SelectResultResponse {
+    fn from(val: SelectResult) -> Self {
         let mut result = SelectResultResponse {
             rows: Vec::new(),
             columns: Vec::new(),
         };
-        result.columns = self.columns;
-        let values: Vec<Vec<String>> = self
+        result.columns = val.columns;
+        let values: Vec<Vec<String>> = val
             .rows
             .iter()
             .map(|c| c.iter().map(|v| v.to_string()).collect())
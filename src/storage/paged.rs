@@ -0,0 +1,492 @@
+// Table rows stored in fixed-size slotted pages in a file.
+//
+// A page starts with its slot count and the offset where the record area begins, followed by
+// the slot directory (offset and length of every record). Records are packed from the end of
+// the page towards the directory. Deleting a record only clears its slot, the space is
+// reclaimed by compacting the page once a later write needs it, and the slot is given to the
+// next record stored in the page. A free-space map in memory tracks how much every page could
+// still take, new rows go to the first page with room for them. Scans return rows in page and
+// slot order, which is insertion order only until rows are deleted.
+//
+// Pages are read and written through a buffer pool that keeps at most a fixed number of pages
+// in memory and writes back the least recently used one when it needs room.
+
+use super::codec::{decode_row, encode_row};
+use super::store::{self, RowId, RowIter, StorageEngine, TableStore};
+use super::{DbResult, Row};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+pub const PAGE_SIZE: usize = 4096;
+const HEADER_SIZE: usize = 4;
+const SLOT_SIZE: usize = 4;
+// Largest record that fits into an empty page next to its slot
+const MAX_RECORD_SIZE: usize = PAGE_SIZE - HEADER_SIZE - SLOT_SIZE;
+
+// Keeps every table in its own page file in `dir`
+pub struct PagedEngine {
+    dir: PathBuf,
+    buffer_pages: usize,
+}
+
+impl PagedEngine {
+    pub fn open(dir: PathBuf, buffer_pages: usize) -> DbResult<Self> {
+        store::reset_dir(&dir)?;
+        Ok(PagedEngine { dir, buffer_pages })
+    }
+}
+
+impl StorageEngine for PagedEngine {
+    fn create_store(&self, table: &str) -> DbResult<Box<dyn TableStore>> {
+        let path = self.dir.join(format!("{}.pages", table));
+        Ok(Box::new(PagedStore::create(&path, self.buffer_pages)?))
+    }
+}
+
+pub struct PagedStore {
+    path: PathBuf,
+    pool: Mutex<BufferPool>,
+    rows: usize,
+    // Bytes every page could still take once compacted
+    free_space: Vec<usize>,
+}
+
+impl PagedStore {
+    // Create an empty store in `path`, replacing whatever the file held before
+    pub fn create(path: &Path, buffer_pages: usize) -> DbResult<Self> {
+        if buffer_pages == 0 {
+            return Err("The buffer pool needs room for at least one page".into());
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(PagedStore {
+            path: path.to_path_buf(),
+            pool: Mutex::new(BufferPool {
+                file,
+                capacity: buffer_pages,
+                frames: HashMap::new(),
+                clock: 0,
+                page_count: 0,
+            }),
+            rows: 0,
+            free_space: Vec::new(),
+        })
+    }
+
+    fn pool(&self) -> DbResult<MutexGuard<'_, BufferPool>> {
+        self.pool
+            .lock()
+            .map_err(|_| "Buffer pool lock poisoned".into())
+    }
+
+    fn pool_mut(&mut self) -> DbResult<&mut BufferPool> {
+        self.pool
+            .get_mut()
+            .map_err(|_| "Buffer pool lock poisoned".into())
+    }
+
+    // Decode the live rows of one page
+    fn read_page(&self, page_no: u32) -> DbResult<Vec<(RowId, Row)>> {
+        let mut pool = self.pool()?;
+        let page = pool.page(page_no)?;
+        (0..page.slot_count())
+            .filter_map(|slot| page.record(slot).map(|record| (slot, record)))
+            .map(|(slot, record)| Ok((row_id(page_no, slot), decode_row(record)?)))
+            .collect()
+    }
+
+    // Decode the rows in some slots of one page
+    fn read_slots(&self, page_no: u32, slots: &[u16]) -> DbResult<Vec<(RowId, Row)>> {
+        let mut pool = self.pool()?;
+        let page = pool.page(page_no)?;
+        slots
+            .iter()
+            .map(|&slot| {
+                let record = page.record(slot).ok_or("Row not found")?;
+                Ok((row_id(page_no, slot), decode_row(record)?))
+            })
+            .collect()
+    }
+
+    // Store a record in the first page with room for it, starting a new page when none has
+    fn store(&mut self, record: &[u8]) -> DbResult<RowId> {
+        if record.len() > MAX_RECORD_SIZE {
+            return Err(format!(
+                "Row of {} bytes does not fit into a {} byte page",
+                record.len(),
+                PAGE_SIZE
+            )
+            .into());
+        }
+        // Enough for the record and a new slot, whether the page has a free one or not
+        let needed = record.len() + SLOT_SIZE;
+        let candidates: Vec<u32> = (0..)
+            .zip(&self.free_space)
+            .filter(|(_, free)| **free >= needed)
+            .map(|(page_no, _)| page_no)
+            .collect();
+        for page_no in candidates {
+            if let Some(slot) = self.pool_mut()?.page_mut(page_no)?.insert(record) {
+                self.update_free_space(page_no)?;
+                return Ok(row_id(page_no, slot));
+            }
+        }
+        let pool = self.pool_mut()?;
+        let page_no = pool.allocate()?;
+        let slot = pool
+            .page_mut(page_no)?
+            .insert(record)
+            .ok_or("Record does not fit into an empty page")?;
+        self.free_space.push(0);
+        self.update_free_space(page_no)?;
+        Ok(row_id(page_no, slot))
+    }
+
+    fn update_free_space(&mut self, page_no: u32) -> DbResult<()> {
+        let free = self.pool_mut()?.page(page_no)?.reclaimable(None);
+        self.free_space[page_no as usize] = free;
+        Ok(())
+    }
+}
+
+impl TableStore for PagedStore {
+    fn len(&self) -> usize {
+        self.rows
+    }
+
+    fn scan(&self) -> RowIter<'_> {
+        let page_count = match self.pool() {
+            Ok(pool) => pool.page_count,
+            Err(e) => return Box::new(std::iter::once(Err(e))),
+        };
+        // Only one page worth of rows is decoded at a time
+        Box::new(
+            (0..page_count).flat_map(move |page_no| match self.read_page(page_no) {
+                Ok(rows) => rows.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            }),
+        )
+    }
+
+    fn get(&self, id: RowId) -> DbResult<Row> {
+        let (page_no, slot) = split_row_id(id);
+        let mut pool = self.pool()?;
+        decode_row(pool.page(page_no)?.record(slot).ok_or("Row not found")?)
+    }
+
+    // Every page is read once for all the rows it holds
+    fn lookup(&self, ids: Vec<RowId>) -> RowIter<'_> {
+        let mut pages: Vec<(u32, Vec<u16>)> = Vec::new();
+        for id in store::sorted_ids(ids) {
+            let (page_no, slot) = split_row_id(id);
+            match pages.last_mut() {
+                Some((last, slots)) if *last == page_no => slots.push(slot),
+                _ => pages.push((page_no, vec![slot])),
+            }
+        }
+        Box::new(pages.into_iter().flat_map(move |(page_no, slots)| {
+            match self.read_slots(page_no, &slots) {
+                Ok(rows) => rows.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            }
+        }))
+    }
+
+    fn insert(&mut self, row: Row) -> DbResult<RowId> {
+        let id = self.store(&encode_row(&row)?)?;
+        self.rows += 1;
+        Ok(id)
+    }
+
+    fn update(&mut self, id: RowId, row: Row) -> DbResult<RowId> {
+        let record = encode_row(&row)?;
+        let (page_no, slot) = split_row_id(id);
+        let page = self.pool_mut()?.page_mut(page_no)?;
+        if page.record(slot).is_none() {
+            return Err("Row not found".into());
+        }
+        if page.update(slot, &record) {
+            self.update_free_space(page_no)?;
+            return Ok(id);
+        }
+        // The row grew too much for its page and moves to another one
+        let moved = self.store(&record)?;
+        self.pool_mut()?.page_mut(page_no)?.delete(slot);
+        self.update_free_space(page_no)?;
+        Ok(moved)
+    }
+
+    fn delete(&mut self, id: RowId) -> DbResult<()> {
+        let (page_no, slot) = split_row_id(id);
+        let page = self.pool_mut()?.page_mut(page_no)?;
+        if page.record(slot).is_none() {
+            return Err("Row not found".into());
+        }
+        page.delete(slot);
+        self.rows -= 1;
+        self.update_free_space(page_no)
+    }
+
+    fn clear(&mut self) -> DbResult<()> {
+        self.pool_mut()?.clear()?;
+        self.rows = 0;
+        self.free_space.clear();
+        Ok(())
+    }
+}
+
+impl Drop for PagedStore {
+    // The file is only working storage, durability comes from the WAL and the snapshots
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn row_id(page_no: u32, slot: u16) -> RowId {
+    ((page_no as u64) << 16) | slot as u64
+}
+
+fn split_row_id(id: RowId) -> (u32, u16) {
+    ((id >> 16) as u32, id as u16)
+}
+
+// Keeps up to `capacity` pages of one file in memory
+struct BufferPool {
+    file: File,
+    capacity: usize,
+    frames: HashMap<u32, Frame>,
+    // Incremented on every access, frames remember when they were last used
+    clock: u64,
+    page_count: u32,
+}
+
+struct Frame {
+    page: Page,
+    dirty: bool,
+    last_used: u64,
+}
+
+impl BufferPool {
+    fn page(&mut self, page_no: u32) -> DbResult<&Page> {
+        Ok(&self.frame(page_no)?.page)
+    }
+
+    fn page_mut(&mut self, page_no: u32) -> DbResult<&mut Page> {
+        let frame = self.frame(page_no)?;
+        frame.dirty = true;
+        Ok(&mut frame.page)
+    }
+
+    // Add an empty page at the end of the file
+    fn allocate(&mut self) -> DbResult<u32> {
+        let page_no = self.page_count;
+        self.make_room()?;
+        self.frames.insert(
+            page_no,
+            Frame {
+                page: Page::new(),
+                dirty: true,
+                last_used: self.clock,
+            },
+        );
+        self.page_count += 1;
+        Ok(page_no)
+    }
+
+    fn frame(&mut self, page_no: u32) -> DbResult<&mut Frame> {
+        if page_no >= self.page_count {
+            return Err(format!("Page {} does not exist", page_no).into());
+        }
+        self.clock += 1;
+        if !self.frames.contains_key(&page_no) {
+            self.make_room()?;
+            let mut data = vec![0; PAGE_SIZE];
+            self.file.seek(SeekFrom::Start(page_offset(page_no)))?;
+            self.file.read_exact(&mut data)?;
+            self.frames.insert(
+                page_no,
+                Frame {
+                    page: Page { data },
+                    dirty: false,
+                    last_used: self.clock,
+                },
+            );
+        }
+        let frame = self.frames.get_mut(&page_no).ok_or("Page not cached")?;
+        frame.last_used = self.clock;
+        Ok(frame)
+    }
+
+    // Evict the least recently used page when the pool is full
+    fn make_room(&mut self) -> DbResult<()> {
+        if self.frames.len() < self.capacity {
+            return Ok(());
+        }
+        let Some(page_no) = self
+            .frames
+            .iter()
+            .min_by_key(|(_, frame)| frame.last_used)
+            .map(|(page_no, _)| *page_no)
+        else {
+            return Ok(());
+        };
+        let frame = self.frames.remove(&page_no).ok_or("Page not cached")?;
+        if frame.dirty {
+            self.file.seek(SeekFrom::Start(page_offset(page_no)))?;
+            self.file.write_all(&frame.page.data)?;
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) -> DbResult<()> {
+        self.frames.clear();
+        self.file.set_len(0)?;
+        self.page_count = 0;
+        Ok(())
+    }
+}
+
+fn page_offset(page_no: u32) -> u64 {
+    page_no as u64 * PAGE_SIZE as u64
+}
+
+struct Page {
+    data: Vec<u8>,
+}
+
+impl Page {
+    fn new() -> Self {
+        let mut page = Page {
+            data: vec![0; PAGE_SIZE],
+        };
+        page.set_u16(2, PAGE_SIZE as u16);
+        page
+    }
+
+    fn u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]])
+    }
+
+    fn set_u16(&mut self, offset: usize, value: u16) {
+        self.data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn slot_count(&self) -> u16 {
+        self.u16(0)
+    }
+
+    // Start of the record area
+    fn free_end(&self) -> usize {
+        // An empty page stores PAGE_SIZE, which only fits a u16 for pages up to 64 KiB
+        self.u16(2) as usize
+    }
+
+    fn slot(&self, slot: u16) -> (usize, usize) {
+        let position = HEADER_SIZE + slot as usize * SLOT_SIZE;
+        (self.u16(position) as usize, self.u16(position + 2) as usize)
+    }
+
+    fn set_slot(&mut self, slot: u16, offset: usize, length: usize) {
+        let position = HEADER_SIZE + slot as usize * SLOT_SIZE;
+        self.set_u16(position, offset as u16);
+        self.set_u16(position + 2, length as u16);
+    }
+
+    // Record stored in `slot`, None once it is deleted. Offset 0 marks a deleted slot, no
+    // record can start inside the header.
+    fn record(&self, slot: u16) -> Option<&[u8]> {
+        if slot >= self.slot_count() {
+            return None;
+        }
+        match self.slot(slot) {
+            (0, _) => None,
+            (offset, length) => Some(&self.data[offset..offset + length]),
+        }
+    }
+
+    fn directory_end(&self) -> usize {
+        HEADER_SIZE + self.slot_count() as usize * SLOT_SIZE
+    }
+
+    // Bytes between the slot directory and the records, plus the space of deleted records
+    // that compaction would win back
+    fn reclaimable(&self, ignoring: Option<u16>) -> usize {
+        let live: usize = (0..self.slot_count())
+            .filter(|slot| Some(*slot) != ignoring)
+            .filter_map(|slot| self.record(slot))
+            .map(|record| record.len())
+            .sum();
+        PAGE_SIZE - self.directory_end() - live
+    }
+
+    fn insert(&mut self, record: &[u8]) -> Option<u16> {
+        // A deleted slot is reused before the directory grows
+        let free_slot = (0..self.slot_count()).find(|slot| self.record(*slot).is_none());
+        let needed = record.len() + if free_slot.is_some() { 0 } else { SLOT_SIZE };
+        if self.free_end() - self.directory_end() < needed {
+            if self.reclaimable(None) < needed {
+                return None;
+            }
+            self.compact();
+        }
+        let slot = match free_slot {
+            Some(slot) => slot,
+            None => {
+                let slot = self.slot_count();
+                self.set_u16(0, slot + 1);
+                slot
+            }
+        };
+        self.write_record(slot, record);
+        Some(slot)
+    }
+
+    // Replace the record in `slot`, false when the new one does not fit into this page
+    fn update(&mut self, slot: u16, record: &[u8]) -> bool {
+        let (offset, length) = self.slot(slot);
+        if record.len() <= length {
+            self.data[offset..offset + record.len()].copy_from_slice(record);
+            self.set_slot(slot, offset, record.len());
+            return true;
+        }
+        if self.reclaimable(Some(slot)) < record.len() {
+            return false;
+        }
+        self.delete(slot);
+        if self.free_end() - self.directory_end() < record.len() {
+            self.compact();
+        }
+        self.write_record(slot, record);
+        true
+    }
+
+    fn delete(&mut self, slot: u16) {
+        self.set_slot(slot, 0, 0);
+    }
+
+    // Place a record right below the record area, there must be room for it
+    fn write_record(&mut self, slot: u16, record: &[u8]) {
+        let offset = self.free_end() - record.len();
+        self.data[offset..offset + record.len()].copy_from_slice(record);
+        self.set_u16(2, offset as u16);
+        self.set_slot(slot, offset, record.len());
+    }
+
+    // Move the live records to the end of the page, closing the holes left by deletes
+    fn compact(&mut self) {
+        let records: Vec<(u16, Vec<u8>)> = (0..self.slot_count())
+            .filter_map(|slot| self.record(slot).map(|record| (slot, record.to_vec())))
+            .collect();
+        self.set_u16(2, PAGE_SIZE as u16);
+        for (slot, record) in records {
+            self.write_record(slot, &record);
+        }
+    }
+}
@@ -0,0 +1,403 @@
+// SQL pattern matching used by LIKE, ILIKE and SIMILAR TO.
+//
+// LIKE only knows `%`, `_` and the escape character and is matched with the usual two-pointer
+// wildcard algorithm, which falls back to the last `%` on a mismatch. SIMILAR TO adds
+// alternation, grouping, bracket classes and the `* + ? {m,n}` quantifiers: its pattern is
+// compiled into a small automaton that is run on the set of all states the text can be in at
+// once. Neither recurses over the text or backtracks exponentially: LIKE takes time proportional
+// to the text length times the pattern length, SIMILAR TO to the text length times the size of
+// the automaton. Quantifiers copy what they repeat into the automaton, so nested ones multiply
+// its size, which is capped at MAX_INSTRUCTIONS.
+
+use super::DbResult;
+
+// Escape character used when the query has no ESCAPE clause
+const DEFAULT_ESCAPE: char = '\\';
+// Largest automaton a SIMILAR TO pattern may compile to
+const MAX_INSTRUCTIONS: usize = 100_000;
+
+#[derive(Debug)]
+enum Node {
+    Char(char),
+    // `_`, any single character
+    Any,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+    // Alternatives, each one a sequence of nodes
+    Group(Vec<Vec<Node>>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+enum LikeToken {
+    Char(char),
+    // `_`
+    Any,
+    // `%`
+    AnySequence,
+}
+
+pub fn like(
+    text: &str,
+    pattern: &str,
+    escape: Option<char>,
+    case_insensitive: bool,
+) -> DbResult<bool> {
+    let escape = escape.unwrap_or(DEFAULT_ESCAPE);
+    // The escape character is found in the pattern as written, ILIKE only folds the case of
+    // the characters to match
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        let literal = match c {
+            c if c == escape => chars
+                .next()
+                .ok_or("LIKE pattern must not end with the escape character")?,
+            '%' => {
+                tokens.push(LikeToken::AnySequence);
+                continue;
+            }
+            '_' => {
+                tokens.push(LikeToken::Any);
+                continue;
+            }
+            c => c,
+        };
+        if case_insensitive {
+            tokens.extend(literal.to_lowercase().map(LikeToken::Char));
+        } else {
+            tokens.push(LikeToken::Char(literal));
+        }
+    }
+    let text: Vec<char> = if case_insensitive {
+        text.to_lowercase().chars().collect()
+    } else {
+        text.chars().collect()
+    };
+    Ok(like_matches(&tokens, &text))
+}
+
+// The whole text has to match. On a mismatch the last `%` takes one more character and the
+// pattern after it is tried again from there; earlier `%`s never need to, whatever they could
+// take the last one can take as well.
+fn like_matches(tokens: &[LikeToken], text: &[char]) -> bool {
+    let (mut token, mut position) = (0, 0);
+    // Token after the last `%` seen, and where in the text the part after it is tried
+    let mut retry: Option<(usize, usize)> = None;
+    while position < text.len() {
+        match tokens.get(token) {
+            Some(LikeToken::AnySequence) => {
+                token += 1;
+                retry = Some((token, position));
+                continue;
+            }
+            Some(LikeToken::Any) => {
+                token += 1;
+                position += 1;
+                continue;
+            }
+            Some(LikeToken::Char(c)) if *c == text[position] => {
+                token += 1;
+                position += 1;
+                continue;
+            }
+            _ => {}
+        }
+        let Some((after, start)) = retry else {
+            return false;
+        };
+        token = after;
+        position = start + 1;
+        retry = Some((after, position));
+    }
+    tokens[token..]
+        .iter()
+        .all(|token| matches!(token, LikeToken::AnySequence))
+}
+
+pub fn similar_to(text: &str, pattern: &str, escape: Option<char>) -> DbResult<bool> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut parser = SimilarParser {
+        chars: &chars,
+        position: 0,
+        escape: escape.unwrap_or(DEFAULT_ESCAPE),
+    };
+    let alternatives = parser.parse_alternatives()?;
+    if parser.position < chars.len() {
+        return Err(format!("Unbalanced ')' in SIMILAR TO pattern '{}'", pattern).into());
+    }
+    let pattern = Node::Group(alternatives);
+    let mut program = Vec::new();
+    compile(&pattern, &mut program)?;
+    program.push(Inst::Match);
+    let text: Vec<char> = text.chars().collect();
+    Ok(run(&program, &text))
+}
+
+fn any_sequence() -> Node {
+    Node::Repeat {
+        node: Box::new(Node::Any),
+        min: 0,
+        max: None,
+    }
+}
+
+// Instruction of the automaton a SIMILAR TO pattern compiles to. Unless it jumps, an
+// instruction continues with the next one.
+enum Inst<'a> {
+    // Consume one character the node accepts
+    Step(&'a Node),
+    // Continue with both instructions
+    Split(usize, usize),
+    Jump(usize),
+    // The pattern matched, if the text is over
+    Match,
+}
+
+fn compile<'a>(node: &'a Node, program: &mut Vec<Inst<'a>>) -> DbResult<()> {
+    // Checked before every node, each one adds at most a few instructions of its own
+    if program.len() > MAX_INSTRUCTIONS {
+        return Err(format!(
+            "SIMILAR TO pattern needs more than {} instructions, repeat less",
+            MAX_INSTRUCTIONS
+        )
+        .into());
+    }
+    match node {
+        Node::Group(alternatives) => {
+            // Every alternative but the last splits off the next one and jumps to the end
+            let mut jumps = Vec::new();
+            for (index, sequence) in alternatives.iter().enumerate() {
+                let last = index + 1 == alternatives.len();
+                let split = program.len();
+                if !last {
+                    program.push(Inst::Split(split + 1, 0));
+                }
+                for node in sequence {
+                    compile(node, program)?;
+                }
+                if !last {
+                    jumps.push(program.len());
+                    program.push(Inst::Jump(0));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min {
+                compile(node, program)?;
+            }
+            match max {
+                // A loop: another occurrence or done
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program)?;
+                    program.push(Inst::Jump(split));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+                // Up to `max - min` optional occurrences, each one may end the repetition
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(0, 0));
+                        compile(node, program)?;
+                    }
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+        node => program.push(Inst::Step(node)),
+    }
+    Ok(())
+}
+
+// Run the automaton over the whole text, tracking every instruction it can be at
+fn run(program: &[Inst], text: &[char]) -> bool {
+    // Step of the text each instruction was last added at, so none is added twice
+    let mut added = vec![usize::MAX; program.len()];
+    let mut current = Vec::new();
+    add_state(program, 0, 0, &mut current, &mut added);
+    for (step, c) in text.iter().enumerate() {
+        let mut next = Vec::new();
+        for &state in &current {
+            if let Inst::Step(node) = program[state] {
+                if accepts(node, *c) {
+                    add_state(program, state + 1, step + 1, &mut next, &mut added);
+                }
+            }
+        }
+        if next.is_empty() {
+            return false;
+        }
+        current = next;
+    }
+    current
+        .iter()
+        .any(|&state| matches!(program[state], Inst::Match))
+}
+
+// Add `state` and every instruction it reaches without consuming a character
+fn add_state(
+    program: &[Inst],
+    state: usize,
+    step: usize,
+    states: &mut Vec<usize>,
+    added: &mut [usize],
+) {
+    let mut pending = vec![state];
+    while let Some(state) = pending.pop() {
+        if added[state] == step {
+            continue;
+        }
+        added[state] = step;
+        match program[state] {
+            Inst::Split(first, second) => {
+                pending.push(second);
+                pending.push(first);
+            }
+            Inst::Jump(target) => pending.push(target),
+            Inst::Step(_) | Inst::Match => states.push(state),
+        }
+    }
+}
+
+fn accepts(node: &Node, c: char) -> bool {
+    match node {
+        Node::Char(expected) => *expected == c,
+        Node::Any => true,
+        Node::Class { negated, ranges } => {
+            ranges.iter().any(|(low, high)| (*low..=*high).contains(&c)) != *negated
+        }
+        Node::Group(_) | Node::Repeat { .. } => false,
+    }
+}
+
+struct SimilarParser<'a> {
+    chars: &'a [char],
+    position: usize,
+    escape: char,
+}
+
+impl SimilarParser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next_char(&mut self) -> DbResult<char> {
+        let c = self.peek().ok_or("Unexpected end of SIMILAR TO pattern")?;
+        self.position += 1;
+        Ok(c)
+    }
+
+    // Parse `a|b|c` until the end of the pattern or a closing parenthesis
+    fn parse_alternatives(&mut self) -> DbResult<Vec<Vec<Node>>> {
+        let mut alternatives = Vec::new();
+        let mut sequence: Vec<Node> = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == ')' {
+                break;
+            }
+            self.position += 1;
+            match c {
+                '|' => alternatives.push(std::mem::take(&mut sequence)),
+                '(' => {
+                    let group = self.parse_alternatives()?;
+                    if self.next_char()? != ')' {
+                        return Err("Missing ')' in SIMILAR TO pattern".into());
+                    }
+                    sequence.push(Node::Group(group));
+                }
+                '*' | '+' | '?' | '{' => {
+                    let (min, max) = match c {
+                        '*' => (0, None),
+                        '+' => (1, None),
+                        '?' => (0, Some(1)),
+                        _ => self.parse_bounds()?,
+                    };
+                    let node = sequence
+                        .pop()
+                        .ok_or_else(|| format!("Nothing to repeat before '{}'", c))?;
+                    sequence.push(Node::Repeat {
+                        node: Box::new(node),
+                        min,
+                        max,
+                    });
+                }
+                '[' => sequence.push(self.parse_class()?),
+                '%' => sequence.push(any_sequence()),
+                '_' => sequence.push(Node::Any),
+                c if c == self.escape => sequence.push(Node::Char(self.next_char()?)),
+                c => sequence.push(Node::Char(c)),
+            }
+        }
+        alternatives.push(sequence);
+        Ok(alternatives)
+    }
+
+    // Parse the inside of `{m}`, `{m,}` or `{m,n}`, the opening brace is already consumed
+    fn parse_bounds(&mut self) -> DbResult<(usize, Option<usize>)> {
+        let mut body = String::new();
+        loop {
+            match self.next_char()? {
+                '}' => break,
+                c => body.push(c),
+            }
+        }
+        let parse = |s: &str| {
+            s.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid repetition bounds '{{{}}}'", body))
+        };
+        let bounds = match body.split_once(',') {
+            None => {
+                let count = parse(&body)?;
+                (count, Some(count))
+            }
+            Some((min, max)) if max.trim().is_empty() => (parse(min)?, None),
+            Some((min, max)) => (parse(min)?, Some(parse(max)?)),
+        };
+        if bounds.1.is_some_and(|max| max < bounds.0) {
+            return Err(format!("Invalid repetition bounds '{{{}}}'", body).into());
+        }
+        Ok(bounds)
+    }
+
+    // Parse a bracket expression such as `[a-z_]` or `[^0-9]`, the `[` is already consumed
+    fn parse_class(&mut self) -> DbResult<Node> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.position += 1;
+        }
+        let mut ranges = Vec::new();
+        loop {
+            let low = match self.next_char()? {
+                ']' if !ranges.is_empty() => break,
+                c if c == self.escape => self.next_char()?,
+                c => c,
+            };
+            let high =
+                if self.peek() == Some('-') && self.chars.get(self.position + 1) != Some(&']') {
+                    self.position += 1;
+                    self.next_char()?
+                } else {
+                    low
+                };
+            ranges.push((low, high));
+        }
+        Ok(Node::Class { negated, ranges })
+    }
+}
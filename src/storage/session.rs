@@ -0,0 +1,61 @@
+// Sessions: one client's transaction state, kept apart from the database.
+//
+// Several sessions share one database, each of them running one statement at a time on it. A
+// session with an open transaction keeps the statements it executed in that transaction, not
+// their changes: the database is left without them after every statement, so other sessions
+// neither see nor wait for uncommitted work. Each statement of the transaction runs after the
+// earlier ones are applied again on top of the latest committed state, and COMMIT applies
+// them one last time and logs them. A statement that no longer succeeds, because another
+// session committed a conflicting change meanwhile, rolls the transaction back.
+
+use super::{Database, DbResult, QueryResult};
+
+#[derive(Default)]
+pub struct Session {
+    // Statements of the open transaction, in order, the ones that failed left out
+    transaction: Option<Vec<String>>,
+}
+
+impl Session {
+    // Execute a statement in this session. The database must not be in a transaction, and is
+    // not in one afterwards.
+    pub fn execute(&mut self, db: &mut Database, sql: &str) -> DbResult<QueryResult> {
+        if let Some(statements) = &self.transaction {
+            db.begin()?;
+            for (index, statement) in statements.iter().enumerate() {
+                if let Err(e) = db.execute(statement) {
+                    db.rollback()?;
+                    self.transaction = None;
+                    return Err(format!(
+                        "Transaction rolled back: statement {} of it fails after changes \
+                         committed meanwhile: {}",
+                        index + 1,
+                        e
+                    )
+                    .into());
+                }
+            }
+        }
+        let started = db.transaction_number();
+        let result = db.execute(sql);
+        match db.transaction_number() {
+            // COMMIT, ROLLBACK or a schema change ended the transaction
+            None => self.transaction = None,
+            Some(number) => {
+                let statements = self.transaction.get_or_insert_with(Vec::new);
+                if Some(number) != started {
+                    // BEGIN or AND CHAIN, the transaction before is committed or rolled back
+                    statements.clear();
+                } else if matches!(
+                    result,
+                    Ok(QueryResult::Success(_) | QueryResult::Upserted { .. })
+                ) {
+                    // Queries change nothing and need not run again
+                    statements.push(sql.to_string());
+                }
+                db.rollback()?;
+            }
+        }
+        result
+    }
+}
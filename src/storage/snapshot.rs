@@ -0,0 +1,180 @@
+// Checkpoint snapshots: the whole database serialized into a single binary file.
+//
+// Layout: magic, the LSN of the last WAL record the snapshot includes, the table count, then per
+// table its schema as length-prefixed JSON (small, and it grows with the table features) followed
+// by the row count and the rows in a compact tagged encoding. A CRC-32 of everything before it
+// ends the file. Snapshots are written to a temporary file that is renamed over the previous one,
+// so a crash leaves either the old or the new snapshot, never a mix. Both directions stream one
+// row at a time through a buffer, a snapshot is never held in memory as a whole.
+
+use super::codec::{self, Reader};
+use super::index::IndexDef;
+use super::wal::extend_checksum;
+use super::{DbResult, Row, Table, Value};
+use serde::{Deserialize, Serialize};
+use sqlparser::ast::{ColumnDef, TableConstraint};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"RDBSNAP1";
+const SNAPSHOT_FILE: &str = "snapshot.db";
+const TEMPORARY_FILE: &str = "snapshot.db.tmp";
+
+#[derive(Serialize, Deserialize)]
+struct TableSchema {
+    name: String,
+    columns: Vec<ColumnDef>,
+    // Table-level constraints, column constraints are part of the columns
+    #[serde(default)]
+    constraints: Vec<TableConstraint>,
+    // Missing in snapshots taken before tables had storage engines
+    #[serde(default)]
+    engine: Option<String>,
+    // Indexes are stored as definitions and rebuilt from the rows
+    #[serde(default)]
+    indexes: Vec<IndexDef>,
+}
+
+// Write a snapshot of `tables` covering the WAL up to `lsn`
+pub fn write(data_dir: &Path, lsn: u64, tables: &HashMap<String, Table>) -> DbResult<()> {
+    let temporary = data_dir.join(TEMPORARY_FILE);
+    let mut file = Checksummed::new(BufWriter::new(File::create(&temporary)?));
+    // Encoding buffer, reused for every row
+    let mut data = Vec::new();
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&lsn.to_le_bytes());
+    codec::put_u32(&mut data, tables.len())?;
+    file.write_all(&data)?;
+
+    // Sorted, so the same database always produces the same file
+    let mut names: Vec<&String> = tables.keys().collect();
+    names.sort();
+    for name in names {
+        let table = &tables[name];
+        let schema = serde_json::to_vec(&TableSchema {
+            name: table.name.clone(),
+            columns: table.columns.clone(),
+            constraints: table.constraints.clone(),
+            engine: Some(table.engine.clone()),
+            indexes: table
+                .indexes
+                .iter()
+                .filter(|index| index.def.constraint.is_none())
+                .map(|index| index.def.clone())
+                .collect(),
+        })?;
+        data.clear();
+        codec::put_bytes(&mut data, &schema)?;
+        data.extend_from_slice(&(table.store.len() as u64).to_le_bytes());
+        file.write_all(&data)?;
+        for row in table.store.scan() {
+            let (_, row) = row?;
+            data.clear();
+            for value in &row.data {
+                codec::put_value(&mut data, value)?;
+            }
+            file.write_all(&data)?;
+        }
+    }
+    let crc = file.checksum();
+    let mut file = file.inner;
+    file.write_all(&crc.to_le_bytes())?;
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&temporary, data_dir.join(SNAPSHOT_FILE))?;
+    // Make the rename itself durable
+    #[cfg(unix)]
+    File::open(data_dir)?.sync_all()?;
+    Ok(())
+}
+
+// Read the snapshot in `data_dir`, returning the LSN it covers and its tables. `create_table`
+// builds each empty table from its name, columns, constraints and storage engine, the rows are
+// inserted afterwards. The checksum can only be verified at the end, a corrupt snapshot fails
+// after some tables have been built.
+pub fn read(
+    data_dir: &Path,
+    mut create_table: impl FnMut(
+        String,
+        Vec<ColumnDef>,
+        Vec<TableConstraint>,
+        Option<String>,
+    ) -> DbResult<Table>,
+) -> DbResult<Option<(u64, HashMap<String, Table>)>> {
+    let path = data_dir.join(SNAPSHOT_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let corrupt = || format!("Snapshot {} is corrupt", path.display());
+    let mut reader = Reader::new(Checksummed::new(BufReader::new(File::open(&path)?)));
+    if reader.take::<8>().ok().as_ref() != Some(MAGIC) {
+        return Err(corrupt().into());
+    }
+    let lsn = reader.u64()?;
+    let mut tables = HashMap::new();
+    for _ in 0..reader.u32()? {
+        let schema: TableSchema = serde_json::from_slice(&reader.bytes()?)?;
+        let mut table = create_table(
+            schema.name,
+            schema.columns,
+            schema.constraints,
+            schema.engine,
+        )?;
+        for _ in 0..reader.u64()? {
+            let data = (0..table.columns.len())
+                .map(|_| reader.value())
+                .collect::<DbResult<Vec<Value>>>()?;
+            table.insert_row(Row::new(data))?;
+        }
+        for index in schema.indexes {
+            table.add_index(index)?;
+        }
+        tables.insert(table.name.clone(), table);
+    }
+    let body = reader.into_inner();
+    let expected = body.checksum();
+    // Only the checksum may be left
+    let mut rest = Vec::new();
+    body.inner.take(5).read_to_end(&mut rest)?;
+    if rest != expected.to_le_bytes() {
+        return Err(corrupt().into());
+    }
+    Ok(Some((lsn, tables)))
+}
+
+// Passes bytes through, keeping a CRC-32 of them
+struct Checksummed<T> {
+    inner: T,
+    crc: u32,
+}
+
+impl<T> Checksummed<T> {
+    fn new(inner: T) -> Self {
+        Checksummed { inner, crc: !0 }
+    }
+
+    fn checksum(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.crc = extend_checksum(self.crc, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.crc = extend_checksum(self.crc, &buf[..read]);
+        Ok(read)
+    }
+}
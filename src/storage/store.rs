@@ -0,0 +1,116 @@
+// Row storage behind a table.
+//
+// A table only talks to its rows through `TableStore`, so the query code works the same
+// whether they live in memory, in pages on disk or in an append-only log. A `StorageEngine`
+// creates the store of every table that is declared with it (`CREATE TABLE ... ENGINE = name`).
+// Indexes are kept by the table above the store, the same for every engine, and map keys to
+// row ids; the store answers an index lookup by fetching the rows for those ids.
+
+use super::{DbResult, Row};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub trait StorageEngine: Send + Sync {
+    fn create_store(&self, table: &str) -> DbResult<Box<dyn TableStore>>;
+}
+
+// Identifies a row inside its store until the row is deleted or moved by an update
+pub type RowId = u64;
+
+pub type RowIter<'a> = Box<dyn Iterator<Item = DbResult<(RowId, Row)>> + 'a>;
+
+pub trait TableStore: Send + Sync {
+    fn len(&self) -> usize;
+
+    // Every row in insertion order, as far as the store preserves it
+    fn scan(&self) -> RowIter<'_>;
+
+    // Fetch a single row, e.g. one found through an index
+    fn get(&self, id: RowId) -> DbResult<Row>;
+
+    // Fetch the rows an index lookup found, in scan order and each once
+    fn lookup(&self, ids: Vec<RowId>) -> RowIter<'_>;
+
+    fn insert(&mut self, row: Row) -> DbResult<RowId>;
+
+    // Replace a row, returning its id afterwards
+    fn update(&mut self, id: RowId, row: Row) -> DbResult<RowId>;
+
+    fn delete(&mut self, id: RowId) -> DbResult<()>;
+
+    fn clear(&mut self) -> DbResult<()>;
+}
+
+pub struct MemoryEngine;
+
+impl StorageEngine for MemoryEngine {
+    fn create_store(&self, _table: &str) -> DbResult<Box<dyn TableStore>> {
+        Ok(Box::<MemoryStore>::default())
+    }
+}
+
+// Rows kept in memory, keyed by an ever increasing id so scans return them in insertion order
+#[derive(Default)]
+pub struct MemoryStore {
+    rows: BTreeMap<RowId, Row>,
+    next_id: RowId,
+}
+
+impl TableStore for MemoryStore {
+    fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn scan(&self) -> RowIter<'_> {
+        Box::new(self.rows.iter().map(|(id, row)| Ok((*id, row.clone()))))
+    }
+
+    fn get(&self, id: RowId) -> DbResult<Row> {
+        Ok(self.rows.get(&id).ok_or("Row not found")?.clone())
+    }
+
+    fn lookup(&self, ids: Vec<RowId>) -> RowIter<'_> {
+        Box::new(sorted_ids(ids).map(|id| Ok((id, self.get(id)?))))
+    }
+
+    fn insert(&mut self, row: Row) -> DbResult<RowId> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.rows.insert(id, row);
+        Ok(id)
+    }
+
+    fn update(&mut self, id: RowId, row: Row) -> DbResult<RowId> {
+        let slot = self.rows.get_mut(&id).ok_or("Row not found")?;
+        *slot = row;
+        Ok(id)
+    }
+
+    fn delete(&mut self, id: RowId) -> DbResult<()> {
+        self.rows.remove(&id).ok_or("Row not found")?;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> DbResult<()> {
+        self.rows.clear();
+        Ok(())
+    }
+}
+
+// Ids in scan order, ids being assigned in storage order by every engine, without duplicates
+pub fn sorted_ids(mut ids: Vec<RowId>) -> impl Iterator<Item = RowId> {
+    ids.sort_unstable();
+    ids.dedup();
+    ids.into_iter()
+}
+
+// Start a disk engine's working directory empty. Its files are rebuilt from the snapshot and
+// the WAL on every start, leftovers from an earlier run are useless.
+pub fn reset_dir(dir: &Path) -> DbResult<()> {
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    fs::create_dir_all(dir)?;
+    Ok(())
+}
@@ -0,0 +1,190 @@
+// Explicit transactions: BEGIN / START TRANSACTION, COMMIT, ROLLBACK and savepoints.
+//
+// Outside a transaction every statement commits on its own. Inside one, the undo log keeps the
+// row changes of all its statements, so ROLLBACK can take them back, and the statements are only
+// written to the WAL on COMMIT. A savepoint remembers how far both had got. Clients sharing a
+// database keep their transactions in a `Session` instead, which only opens one here for the
+// duration of a statement.
+
+use super::{Database, DbResult, QueryResult};
+
+#[derive(Default)]
+pub struct Transaction {
+    // Changing statements executed so far, logged on COMMIT
+    statements: Vec<String>,
+    savepoints: Vec<Savepoint>,
+}
+
+struct Savepoint {
+    name: String,
+    // Lengths of the undo log and of the statement list when it was set
+    undo: usize,
+    statements: usize,
+}
+
+// Statements about transactions the SQL parser does not know
+pub enum Command {
+    RollbackTo(String),
+    Release(String),
+}
+
+impl Command {
+    // `ROLLBACK [WORK | TRANSACTION] TO [SAVEPOINT] name` or `RELEASE SAVEPOINT name`
+    pub fn parse(sql: &str) -> Option<Command> {
+        let words: Vec<&str> = sql
+            .trim()
+            .trim_end_matches(';')
+            .split_whitespace()
+            .collect();
+        let is = |word: &&str, keyword: &str| word.eq_ignore_ascii_case(keyword);
+        match words.as_slice() {
+            [rollback, rest @ ..] if is(rollback, "ROLLBACK") => {
+                let rest = match rest {
+                    [work, rest @ ..] if is(work, "WORK") || is(work, "TRANSACTION") => rest,
+                    rest => rest,
+                };
+                match rest {
+                    [to, savepoint, name] if is(to, "TO") && is(savepoint, "SAVEPOINT") => {
+                        Some(Command::RollbackTo(unquote(name)))
+                    }
+                    [to, name] if is(to, "TO") => Some(Command::RollbackTo(unquote(name))),
+                    _ => None,
+                }
+            }
+            [release, savepoint, name] if is(release, "RELEASE") && is(savepoint, "SAVEPOINT") => {
+                Some(Command::Release(unquote(name)))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Savepoint names can be quoted with backticks
+fn unquote(name: &str) -> String {
+    name.trim_matches('`').to_string()
+}
+
+impl Database {
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    // Number of the open transaction, which tells a new one from the one before
+    pub(super) fn transaction_number(&self) -> Option<u64> {
+        self.transaction.as_ref().map(|_| self.transactions)
+    }
+
+    // Starting a transaction inside another one commits the first, as in MySQL
+    pub(super) fn begin(&mut self) -> DbResult<QueryResult> {
+        if self.transaction.is_some() {
+            self.commit()?;
+        }
+        self.transaction = Some(Transaction::default());
+        self.transactions += 1;
+        Ok(QueryResult::Success("Transaction started".to_string()))
+    }
+
+    // Make the changes of the transaction permanent: log its statements and forget how to undo
+    // them. If they cannot be logged the transaction is rolled back. Without a transaction
+    // there is nothing to do.
+    pub(super) fn commit(&mut self) -> DbResult<QueryResult> {
+        let Some(transaction) = self.transaction.take() else {
+            return Ok(QueryResult::Success("No transaction to commit".to_string()));
+        };
+        if !transaction.statements.is_empty() {
+            let mut records = vec!["BEGIN"];
+            records.extend(transaction.statements.iter().map(String::as_str));
+            records.push("COMMIT");
+            // Changes that are not in the log would be lost on restart, so they are taken back
+            if let Err(e) = self.log(&records) {
+                self.rollback_to(0)?;
+                return Err(
+                    format!("COMMIT failed, the transaction was rolled back: {}", e).into(),
+                );
+            }
+        }
+        self.undo.clear();
+        Ok(QueryResult::Success("Transaction committed".to_string()))
+    }
+
+    pub(super) fn rollback(&mut self) -> DbResult<QueryResult> {
+        if self.transaction.take().is_none() {
+            return Ok(QueryResult::Success(
+                "No transaction to roll back".to_string(),
+            ));
+        }
+        self.rollback_to(0)?;
+        Ok(QueryResult::Success("Transaction rolled back".to_string()))
+    }
+
+    // Setting a savepoint again moves it
+    pub(super) fn savepoint(&mut self, name: &str) -> DbResult<QueryResult> {
+        let undo = self.undo.len();
+        let transaction = self
+            .transaction
+            .as_mut()
+            .ok_or("SAVEPOINT can only be used in a transaction")?;
+        transaction
+            .savepoints
+            .retain(|savepoint| !savepoint.name.eq_ignore_ascii_case(name));
+        transaction.savepoints.push(Savepoint {
+            name: name.to_string(),
+            undo,
+            statements: transaction.statements.len(),
+        });
+        Ok(QueryResult::Success(format!("Savepoint '{}' set", name)))
+    }
+
+    // Take back what the transaction did since the savepoint, which stays set, and drop the
+    // savepoints set after it
+    pub(super) fn rollback_to_savepoint(&mut self, name: &str) -> DbResult<QueryResult> {
+        let transaction = self
+            .transaction
+            .as_mut()
+            .ok_or("ROLLBACK TO SAVEPOINT can only be used in a transaction")?;
+        let position = Self::find_savepoint(transaction, name)?;
+        transaction.savepoints.truncate(position + 1);
+        let savepoint = &transaction.savepoints[position];
+        transaction.statements.truncate(savepoint.statements);
+        let undo = savepoint.undo;
+        self.rollback_to(undo)?;
+        Ok(QueryResult::Success(format!(
+            "Rolled back to savepoint '{}'",
+            name
+        )))
+    }
+
+    // Forget the savepoint and the ones set after it, keeping the changes
+    pub(super) fn release_savepoint(&mut self, name: &str) -> DbResult<QueryResult> {
+        let transaction = self
+            .transaction
+            .as_mut()
+            .ok_or("RELEASE SAVEPOINT can only be used in a transaction")?;
+        let position = Self::find_savepoint(transaction, name)?;
+        transaction.savepoints.truncate(position);
+        Ok(QueryResult::Success(format!(
+            "Savepoint '{}' released",
+            name
+        )))
+    }
+
+    // Savepoint names compare case-insensitively, like other identifiers in MySQL
+    fn find_savepoint(transaction: &Transaction, name: &str) -> DbResult<usize> {
+        transaction
+            .savepoints
+            .iter()
+            .position(|savepoint| savepoint.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Savepoint '{}' does not exist", name).into())
+    }
+
+    // Keep a changing statement for the WAL: right away outside a transaction, on COMMIT inside
+    pub(super) fn record(&mut self, sql: &str) -> DbResult<()> {
+        match &mut self.transaction {
+            Some(transaction) => {
+                transaction.statements.push(sql.to_string());
+                Ok(())
+            }
+            None => self.log(&[sql]),
+        }
+    }
+}
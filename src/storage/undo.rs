@@ -0,0 +1,120 @@
+// Undo log: every row change, with what it takes to reverse it.
+//
+// Statements record the rows they insert, update and delete here, so one that fails halfway,
+// e.g. on the third row of an upsert after updating the first two, is taken back as a whole,
+// along with whatever its foreign keys did to other tables. Schema changes are recorded too, so
+// one that cannot be written to the WAL is taken back like any other statement. An open
+// transaction keeps the log until it ends, to roll back to its start or a savepoint. Reversing
+// runs the log backwards. Stores may give a row a new id when it is written again, later
+// entries are followed to it.
+
+use super::index::Index;
+use super::store::RowId;
+use super::{Database, DbResult, Row, Table};
+use std::collections::HashMap;
+
+pub enum Undo {
+    // A row was stored as `id`
+    Inserted {
+        table: String,
+        id: RowId,
+    },
+    // The row `id`, holding `row` before, is now stored as `new_id`
+    Updated {
+        table: String,
+        id: RowId,
+        new_id: RowId,
+        row: Row,
+    },
+    Deleted {
+        table: String,
+        id: RowId,
+        row: Row,
+    },
+    CreatedTable {
+        table: String,
+    },
+    // Kept whole, rows and store included, until the log is cleared
+    DroppedTable {
+        table: Box<Table>,
+    },
+    CreatedIndex {
+        table: String,
+        name: String,
+    },
+    // The index was at `position` among the indexes of the table
+    DroppedIndex {
+        table: String,
+        position: usize,
+        index: Index,
+    },
+}
+
+impl Database {
+    // Reverse the changes logged after the first `mark` entries of the undo log
+    pub(super) fn rollback_to(&mut self, mark: usize) -> DbResult<()> {
+        // Where rows written again by the rollback are stored now
+        let mut moved: HashMap<(String, RowId), RowId> = HashMap::new();
+        // A failed COMMIT has already rolled back the whole log
+        let mark = mark.min(self.undo.len());
+        for entry in self.undo.split_off(mark).into_iter().rev() {
+            match entry {
+                Undo::Inserted { table, id } => {
+                    let id = moved.get(&(table.clone(), id)).copied().unwrap_or(id);
+                    self.tables
+                        .get_mut(&table)
+                        .ok_or("Table not found")?
+                        .remove_row(id)?;
+                }
+                Undo::Updated {
+                    table,
+                    id,
+                    new_id,
+                    row,
+                } => {
+                    let current = moved.get(&(table.clone(), new_id)).copied();
+                    let stored = self
+                        .tables
+                        .get_mut(&table)
+                        .ok_or("Table not found")?
+                        .replace_row(current.unwrap_or(new_id), row)?
+                        .0;
+                    moved.insert((table, id), stored);
+                }
+                Undo::Deleted { table, id, row } => {
+                    let stored = self
+                        .tables
+                        .get_mut(&table)
+                        .ok_or("Table not found")?
+                        .put_row(row)?;
+                    moved.insert((table, id), stored);
+                }
+                Undo::CreatedTable { table } => {
+                    self.tables.remove(&table);
+                }
+                Undo::DroppedTable { table } => {
+                    self.tables.insert(table.name.clone(), *table);
+                }
+                Undo::CreatedIndex { table, name } => {
+                    self.tables
+                        .get_mut(&table)
+                        .ok_or("Table not found")?
+                        .indexes
+                        .retain(|index| !index.is_named(&name));
+                }
+                Undo::DroppedIndex {
+                    table,
+                    position,
+                    index,
+                } => {
+                    self.tables
+                        .get_mut(&table)
+                        .ok_or("Table not found")?
+                        .indexes
+                        .insert(position, index);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,790 @@
+// Values stored in rows and produced by expressions.
+//
+// SQL literals are converted into these at the parser boundary (expression evaluation, which
+// INSERT uses too), so the engine never has to re-parse numbers or guess what a string holds.
+
+use super::DbResult;
+use serde::{Deserialize, Serialize};
+use sqlparser::ast::{DataType, ExactNumberInfo, Value as SqlValue};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const MICROS_PER_DAY: i64 = 86_400 * MICROS_PER_SECOND;
+// Extra fractional digits kept by `/`, MySQL's div_precision_increment
+const DIVISION_SCALE: u32 = 4;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Value {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    Decimal(Decimal),
+    Text(String),
+    Bytes(Vec<u8>),
+    // Days since 1970-01-01
+    Date(i32),
+    // Microseconds since midnight
+    Time(i64),
+    // Microseconds since 1970-01-01 00:00:00
+    Timestamp(i64),
+}
+
+impl Value {
+    // Convert a literal from the parsed statement
+    pub fn from_literal(literal: &SqlValue) -> DbResult<Value> {
+        Ok(match literal {
+            SqlValue::Null => Value::Null,
+            SqlValue::Boolean(b) => Value::Boolean(*b),
+            SqlValue::Number(n, _) => {
+                Self::parse_number(n).ok_or_else(|| format!("Invalid number '{}'", n))?
+            }
+            SqlValue::SingleQuotedString(s)
+            | SqlValue::DoubleQuotedString(s)
+            | SqlValue::EscapedStringLiteral(s)
+            | SqlValue::NationalStringLiteral(s)
+            | SqlValue::RawStringLiteral(s) => Value::Text(s.clone()),
+            SqlValue::DollarQuotedString(s) => Value::Text(s.value.clone()),
+            SqlValue::SingleQuotedByteStringLiteral(s)
+            | SqlValue::DoubleQuotedByteStringLiteral(s) => Value::Bytes(s.as_bytes().to_vec()),
+            SqlValue::HexStringLiteral(s) => {
+                Value::Bytes(decode_hex(s).ok_or_else(|| format!("Invalid hex literal X'{}'", s))?)
+            }
+            other => return Err(format!("Unsupported literal {}", other).into()),
+        })
+    }
+
+    // Integers that fit in i64 stay exact, numbers with a fraction become decimals and only
+    // exponent notation falls back to floating point
+    fn parse_number(n: &str) -> Option<Value> {
+        if let Ok(i) = n.parse::<i64>() {
+            return Some(Value::Integer(i));
+        }
+        if !n.contains(['e', 'E']) {
+            if let Ok(d) = n.parse::<Decimal>() {
+                return Some(Value::Decimal(d));
+            }
+        }
+        n.parse::<f64>().ok().map(Value::Float)
+    }
+
+    // Explicit conversion for CAST: whatever `coerce` accepts, text holding a number to a
+    // numeric type and any value to text
+    pub fn cast(self, data_type: &DataType) -> Option<Value> {
+        if let Some(value) = self.clone().coerce(data_type) {
+            return Some(value);
+        }
+        match self {
+            Value::Text(s) => Self::parse_number(s.trim())?.coerce(data_type),
+            value => Value::Text(value.to_string()).coerce(data_type),
+        }
+    }
+
+    // Convert the value to the type of a column, None when it cannot be stored there
+    pub fn coerce(self, data_type: &DataType) -> Option<Value> {
+        if let Value::Null = self {
+            return Some(Value::Null);
+        }
+        match data_type {
+            DataType::TinyInt(_)
+            | DataType::UnsignedTinyInt(_)
+            | DataType::SmallInt(_)
+            | DataType::UnsignedSmallInt(_)
+            | DataType::MediumInt(_)
+            | DataType::UnsignedMediumInt(_)
+            | DataType::Int(_)
+            | DataType::Integer(_)
+            | DataType::UnsignedInt(_)
+            | DataType::UnsignedInteger(_)
+            | DataType::BigInt(_)
+            | DataType::UnsignedBigInt(_) => match self {
+                Value::Integer(i) => Some(Value::Integer(i)),
+                // Only numbers without a fractional part fit an integer column
+                Value::Decimal(d) => d.to_i64().map(Value::Integer),
+                Value::Float(f) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => {
+                    Some(Value::Integer(f as i64))
+                }
+                _ => None,
+            },
+            DataType::Float(_) | DataType::Real | DataType::Double | DataType::DoublePrecision => {
+                self.to_f64().map(Value::Float)
+            }
+            DataType::Decimal(info)
+            | DataType::Numeric(info)
+            | DataType::Dec(info)
+            | DataType::BigNumeric(info)
+            | DataType::BigDecimal(info) => {
+                let decimal = match self {
+                    Value::Integer(i) => Decimal::from(i),
+                    Value::Decimal(d) => d,
+                    Value::Float(f) => Decimal::from_f64(f)?,
+                    _ => return None,
+                };
+                match info {
+                    ExactNumberInfo::None => Some(Value::Decimal(decimal)),
+                    ExactNumberInfo::Precision(precision) => {
+                        decimal.constrain(*precision, 0).map(Value::Decimal)
+                    }
+                    ExactNumberInfo::PrecisionAndScale(precision, scale) => {
+                        decimal.constrain(*precision, *scale).map(Value::Decimal)
+                    }
+                }
+            }
+            DataType::Text
+            | DataType::String
+            | DataType::Varchar(_)
+            | DataType::Nvarchar(_)
+            | DataType::Char(_)
+            | DataType::Character(_)
+            | DataType::CharVarying(_)
+            | DataType::CharacterVarying(_)
+            | DataType::Clob(_)
+            | DataType::CharLargeObject(_)
+            | DataType::CharacterLargeObject(_) => match self {
+                Value::Text(s) => Some(Value::Text(s)),
+                _ => None,
+            },
+            DataType::Boolean => match self {
+                Value::Boolean(b) => Some(Value::Boolean(b)),
+                _ => None,
+            },
+            DataType::Binary(_) | DataType::Varbinary(_) | DataType::Blob(_) | DataType::Bytea => {
+                match self {
+                    Value::Bytes(b) => Some(Value::Bytes(b)),
+                    Value::Text(s) => Some(Value::Bytes(s.into_bytes())),
+                    _ => None,
+                }
+            }
+            DataType::Date => match self {
+                Value::Date(d) => Some(Value::Date(d)),
+                Value::Timestamp(t) => Some(Value::Date(t.div_euclid(MICROS_PER_DAY) as i32)),
+                Value::Text(s) => parse_date(&s).map(Value::Date),
+                _ => None,
+            },
+            DataType::Time(..) => match self {
+                Value::Time(t) => Some(Value::Time(t)),
+                Value::Text(s) => parse_time(&s).map(Value::Time),
+                _ => None,
+            },
+            DataType::Timestamp(..) | DataType::Datetime(_) => match self {
+                Value::Timestamp(t) => Some(Value::Timestamp(t)),
+                Value::Date(d) => Some(Value::Timestamp(d as i64 * MICROS_PER_DAY)),
+                Value::Text(s) => parse_timestamp(&s).map(Value::Timestamp),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Value::Integer(_) | Value::Float(_) | Value::Decimal(_)
+        )
+    }
+
+    pub fn to_f64(&self) -> Option<f64> {
+        match self {
+            Value::Integer(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            Value::Decimal(d) => Some(d.to_f64()),
+            _ => None,
+        }
+    }
+
+    fn to_decimal(&self) -> Option<Decimal> {
+        match self {
+            Value::Integer(i) => Some(Decimal::from(*i)),
+            Value::Decimal(d) => Some(*d),
+            _ => None,
+        }
+    }
+
+    // SQL comparison, None when the values are not comparable (including NULL)
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Null, _) | (_, Value::Null) => None,
+            (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+            (Value::Float(_), _) | (_, Value::Float(_))
+                if self.is_numeric() && other.is_numeric() =>
+            {
+                self.to_f64()?.partial_cmp(&other.to_f64()?)
+            }
+            (a, b) if a.is_numeric() && b.is_numeric() => {
+                Some(a.to_decimal()?.cmp(&b.to_decimal()?))
+            }
+            (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+            (Value::Boolean(a), Value::Boolean(b)) => Some(a.cmp(b)),
+            (Value::Bytes(a), Value::Bytes(b)) => Some(a.cmp(b)),
+            (Value::Date(a), Value::Date(b)) => Some(a.cmp(b)),
+            (Value::Time(a), Value::Time(b)) => Some(a.cmp(b)),
+            (Value::Timestamp(a), Value::Timestamp(b)) => Some(a.cmp(b)),
+            (Value::Date(a), Value::Timestamp(b)) => Some((*a as i64 * MICROS_PER_DAY).cmp(b)),
+            (Value::Timestamp(a), Value::Date(b)) => Some(a.cmp(&(*b as i64 * MICROS_PER_DAY))),
+            // A string compared with a date or time is read as a value of that type
+            (
+                Value::Text(s),
+                temporal @ (Value::Date(_) | Value::Time(_) | Value::Timestamp(_)),
+            ) => Self::parse_as(s, temporal)?.compare(temporal),
+            (
+                temporal @ (Value::Date(_) | Value::Time(_) | Value::Timestamp(_)),
+                Value::Text(s),
+            ) => temporal.compare(&Self::parse_as(s, temporal)?),
+            _ => None,
+        }
+    }
+
+    fn parse_as(text: &str, like: &Value) -> Option<Value> {
+        match like {
+            Value::Date(_) => parse_date(text).map(Value::Date),
+            Value::Time(_) => parse_time(text).map(Value::Time),
+            Value::Timestamp(_) => parse_timestamp(text).map(Value::Timestamp),
+            _ => None,
+        }
+    }
+
+    // Total ordering used for sorting and indexes. Values are ordered by kind first: NULL <
+    // booleans < numbers < strings < bytes < dates and times, and by `compare` within a kind.
+    // Comparing across kinds first, e.g. a date with a string holding one, would not be
+    // transitive.
+    pub fn total_cmp(&self, other: &Value) -> Ordering {
+        let rank = |value: &Value| match value {
+            Value::Null => 0,
+            Value::Boolean(_) => 1,
+            Value::Integer(_) | Value::Float(_) | Value::Decimal(_) => 2,
+            Value::Text(_) => 3,
+            Value::Bytes(_) => 4,
+            Value::Date(_) | Value::Timestamp(_) => 5,
+            Value::Time(_) => 6,
+        };
+        rank(self).cmp(&rank(other)).then_with(|| {
+            self.compare(other)
+                .unwrap_or_else(|| self.to_string().cmp(&other.to_string()))
+        })
+    }
+
+    // Exact arithmetic: integers stay integers and decimals stay decimals, a float operand
+    // makes the result a float. Any NULL operand gives NULL.
+    pub fn arithmetic(&self, op: Arithmetic, other: &Value) -> DbResult<Value> {
+        use Arithmetic::*;
+        let out_of_range = || format!("Result of {} {} {} is out of range", self, op, other);
+        match (self, other) {
+            (Value::Null, _) | (_, Value::Null) => return Ok(Value::Null),
+            // Dates move by whole days
+            (Value::Date(date), Value::Integer(days)) if matches!(op, Add | Subtract) => {
+                let days = if op == Add {
+                    Some(*days)
+                } else {
+                    days.checked_neg()
+                };
+                return days
+                    .and_then(|days| (*date as i64).checked_add(days))
+                    .and_then(|date| i32::try_from(date).ok())
+                    .map(Value::Date)
+                    .ok_or_else(|| out_of_range().into());
+            }
+            (Value::Date(a), Value::Date(b)) if op == Subtract => {
+                return Ok(Value::Integer(*a as i64 - *b as i64))
+            }
+            _ => {}
+        }
+        if !self.is_numeric() || !other.is_numeric() {
+            return Err(format!("Cannot apply {} to {} and {}", op, self, other).into());
+        }
+        if matches!(op, Divide | IntegerDivide | Modulo) && other.to_f64() == Some(0.0) {
+            return Err("Division by zero".into());
+        }
+
+        let result = match (self, other) {
+            (Value::Float(_), _) | (_, Value::Float(_)) => {
+                let (a, b) = (
+                    self.to_f64().unwrap_or_default(),
+                    other.to_f64().unwrap_or_default(),
+                );
+                match op {
+                    Add => Some(Value::Float(a + b)),
+                    Subtract => Some(Value::Float(a - b)),
+                    Multiply => Some(Value::Float(a * b)),
+                    Divide => Some(Value::Float(a / b)),
+                    Modulo => Some(Value::Float(a % b)),
+                    IntegerDivide => Value::Float((a / b).trunc()).coerce(&DataType::BigInt(None)),
+                }
+            }
+            (Value::Integer(a), Value::Integer(b)) => match op {
+                Add => a.checked_add(*b).map(Value::Integer),
+                Subtract => a.checked_sub(*b).map(Value::Integer),
+                Multiply => a.checked_mul(*b).map(Value::Integer),
+                IntegerDivide => a.checked_div(*b).map(Value::Integer),
+                Modulo => a.checked_rem(*b).map(Value::Integer),
+                // Like MySQL, `/` on integers does not truncate
+                Divide => Decimal::from(*a)
+                    .checked_div(&Decimal::from(*b), DIVISION_SCALE)
+                    .map(Value::Decimal),
+            },
+            _ => {
+                let (a, b) = (
+                    self.to_decimal().unwrap_or_default(),
+                    other.to_decimal().unwrap_or_default(),
+                );
+                match op {
+                    Add => a.checked_add(&b).map(Value::Decimal),
+                    Subtract => a.checked_sub(&b).map(Value::Decimal),
+                    Multiply => a.checked_mul(&b).map(Value::Decimal),
+                    Divide => a
+                        .checked_div(&b, a.scale + DIVISION_SCALE)
+                        .map(Value::Decimal),
+                    Modulo => a.checked_rem(&b).map(Value::Decimal),
+                    // The dividend minus the remainder divides exactly
+                    IntegerDivide => a
+                        .checked_rem(&b)
+                        .and_then(|remainder| a.checked_sub(&remainder))
+                        .and_then(|exact| exact.checked_div(&b, 0))
+                        .and_then(Decimal::to_i64)
+                        .map(Value::Integer),
+                }
+            }
+        };
+        result.ok_or_else(|| out_of_range().into())
+    }
+
+    pub fn negate(&self) -> DbResult<Value> {
+        match self {
+            Value::Null => Ok(Value::Null),
+            Value::Integer(i) => i
+                .checked_neg()
+                .map(Value::Integer)
+                .ok_or_else(|| format!("Result of -{} is out of range", i).into()),
+            Value::Float(f) => Ok(Value::Float(-f)),
+            Value::Decimal(d) => Ok(Value::Decimal(d.neg())),
+            other => Err(format!("Cannot negate {}", other).into()),
+        }
+    }
+}
+
+// Binary arithmetic operators
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    // MySQL's DIV, division truncated to an integer
+    IntegerDivide,
+    Modulo,
+}
+
+impl fmt::Display for Arithmetic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Arithmetic::Add => "+",
+            Arithmetic::Subtract => "-",
+            Arithmetic::Multiply => "*",
+            Arithmetic::Divide => "/",
+            Arithmetic::IntegerDivide => "DIV",
+            Arithmetic::Modulo => "%",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+// Structural equality, used for grouping and DISTINCT. SQL comparisons go through compare.
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+            (Value::Decimal(a), Value::Decimal(b)) => a == b,
+            (Value::Text(a), Value::Text(b)) => a == b,
+            (Value::Bytes(a), Value::Bytes(b)) => a == b,
+            (Value::Date(a), Value::Date(b)) => a == b,
+            (Value::Time(a), Value::Time(b)) => a == b,
+            (Value::Timestamp(a), Value::Timestamp(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Null => {}
+            Value::Boolean(b) => b.hash(state),
+            Value::Integer(i) => i.hash(state),
+            Value::Float(f) => f.to_bits().hash(state),
+            Value::Decimal(d) => d.hash(state),
+            Value::Text(s) => s.hash(state),
+            Value::Bytes(b) => b.hash(state),
+            Value::Date(d) => d.hash(state),
+            Value::Time(t) | Value::Timestamp(t) => t.hash(state),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Decimal(d) => write!(f, "{}", d),
+            Value::Text(s) => write!(f, "{}", s),
+            Value::Bytes(bytes) => {
+                write!(f, "0x")?;
+                bytes.iter().try_for_each(|b| write!(f, "{:02X}", b))
+            }
+            Value::Date(days) => {
+                let (year, month, day) = civil_from_days(*days as i64);
+                write!(f, "{:04}-{:02}-{:02}", year, month, day)
+            }
+            Value::Time(micros) => format_time(f, *micros),
+            Value::Timestamp(micros) => {
+                let days = micros.div_euclid(MICROS_PER_DAY);
+                write!(f, "{} ", Value::Date(days as i32))?;
+                format_time(f, micros.rem_euclid(MICROS_PER_DAY))
+            }
+        }
+    }
+}
+
+fn format_time(f: &mut fmt::Formatter, micros: i64) -> fmt::Result {
+    let seconds = micros / MICROS_PER_SECOND;
+    write!(
+        f,
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )?;
+    let fraction = micros % MICROS_PER_SECOND;
+    if fraction != 0 {
+        write!(f, ".{}", format!("{:06}", fraction).trim_end_matches('0'))?;
+    }
+    Ok(())
+}
+
+// Fixed point number: `mantissa / 10^scale`
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+impl Decimal {
+    pub fn new(mantissa: i128, scale: u32) -> Self {
+        Decimal { mantissa, scale }
+    }
+
+    pub fn mantissa(&self) -> i128 {
+        self.mantissa
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    fn from_f64(f: f64) -> Option<Decimal> {
+        // Display of f64 never uses exponent notation, so this is exact for what it prints
+        if f.is_finite() {
+            f.to_string().parse().ok()
+        } else {
+            None
+        }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.mantissa as f64 / 10f64.powi(self.scale as i32)
+    }
+
+    pub fn to_i64(self) -> Option<i64> {
+        let normalized = self.normalized();
+        if normalized.scale == 0 {
+            i64::try_from(normalized.mantissa).ok()
+        } else {
+            None
+        }
+    }
+
+    // The same number with `scale` fractional digits, rounding half away from zero
+    pub fn rescale(&self, scale: u32) -> Option<Decimal> {
+        let mantissa = if scale >= self.scale {
+            self.mantissa
+                .checked_mul(10i128.checked_pow(scale - self.scale)?)?
+        } else {
+            let divisor = 10i128.checked_pow(self.scale - scale)?;
+            let (quotient, remainder) = (self.mantissa / divisor, self.mantissa % divisor);
+            if remainder.unsigned_abs() * 2 >= divisor.unsigned_abs() {
+                quotient + self.mantissa.signum()
+            } else {
+                quotient
+            }
+        };
+        Some(Decimal { mantissa, scale })
+    }
+
+    // Round to the scale of a DECIMAL(precision, scale) column, None if it does not fit
+    fn constrain(&self, precision: u64, scale: u64) -> Option<Decimal> {
+        let rescaled = self.rescale(u32::try_from(scale).ok()?)?;
+        let digits = rescaled
+            .mantissa
+            .unsigned_abs()
+            .checked_ilog10()
+            .unwrap_or(0)
+            + 1;
+        if digits as u64 > precision.max(1) {
+            return None;
+        }
+        Some(rescaled)
+    }
+
+    // Strip trailing fractional zeros, so 1.50 and 1.5 hash and compare alike
+    fn normalized(&self) -> Decimal {
+        let mut result = *self;
+        while result.scale > 0 && result.mantissa % 10 == 0 {
+            result.mantissa /= 10;
+            result.scale -= 1;
+        }
+        result
+    }
+
+    pub fn checked_add(&self, other: &Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        let mantissa = self
+            .rescale(scale)?
+            .mantissa
+            .checked_add(other.rescale(scale)?.mantissa)?;
+        Some(Decimal { mantissa, scale })
+    }
+
+    pub fn checked_sub(&self, other: &Decimal) -> Option<Decimal> {
+        self.checked_add(&Decimal::new(other.mantissa.checked_neg()?, other.scale))
+    }
+
+    pub fn checked_mul(&self, other: &Decimal) -> Option<Decimal> {
+        Some(Decimal {
+            mantissa: self.mantissa.checked_mul(other.mantissa)?,
+            scale: self.scale.checked_add(other.scale)?,
+        })
+    }
+
+    // Remainder with the sign of the dividend, None on division by zero
+    pub fn checked_rem(&self, other: &Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        let mantissa = self
+            .rescale(scale)?
+            .mantissa
+            .checked_rem(other.rescale(scale)?.mantissa)?;
+        Some(Decimal { mantissa, scale })
+    }
+
+    fn neg(&self) -> Decimal {
+        Decimal::new(-self.mantissa, self.scale)
+    }
+
+    // Division rounded to `scale` fractional digits, None on division by zero or overflow
+    pub fn checked_div(&self, other: &Decimal, scale: u32) -> Option<Decimal> {
+        if other.mantissa == 0 {
+            return None;
+        }
+        // self / other = (a / 10^sa) / (b / 10^sb), scaled up by 10^scale
+        let numerator = self
+            .mantissa
+            .checked_mul(10i128.checked_pow(scale + other.scale)?)?;
+        let denominator = other
+            .mantissa
+            .checked_mul(10i128.checked_pow(self.scale)?)?;
+        let (quotient, remainder) = (numerator / denominator, numerator % denominator);
+        let mantissa = if remainder.unsigned_abs() * 2 >= denominator.unsigned_abs() {
+            quotient + numerator.signum() * denominator.signum()
+        } else {
+            quotient
+        };
+        Some(Decimal { mantissa, scale })
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(i: i64) -> Self {
+        Decimal::new(i as i128, 0)
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        let scale = self.scale.max(other.scale);
+        match (self.rescale(scale), other.rescale(scale)) {
+            (Some(a), Some(b)) => a.mantissa.cmp(&b.mantissa),
+            // Too large to align exactly, compare approximately
+            _ => self.to_f64().total_cmp(&other.to_f64()),
+        }
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl Hash for Decimal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let normalized = self.normalized();
+        normalized.mantissa.hash(state);
+        normalized.scale.hash(state);
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid decimal '{}'", s);
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if integer.is_empty() && fraction.is_empty()
+            || !integer
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let mut mantissa: i128 = 0;
+        for c in integer.chars().chain(fraction.chars()) {
+            mantissa = mantissa
+                .checked_mul(10)
+                .and_then(|m| m.checked_add(c.to_digit(10).unwrap_or(0) as i128))
+                .ok_or_else(invalid)?;
+        }
+        Ok(Decimal {
+            mantissa: if negative { -mantissa } else { mantissa },
+            scale: fraction.len() as u32,
+        })
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = format!(
+            "{:0width$}",
+            self.mantissa.unsigned_abs(),
+            width = self.scale as usize + 1
+        );
+        let (integer, fraction) = digits.split_at(digits.len() - self.scale as usize);
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        if fraction.is_empty() {
+            write!(f, "{}{}", sign, integer)
+        } else {
+            write!(f, "{}{}.{}", sign, integer, fraction)
+        }
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Parse `YYYY-MM-DD` into days since the epoch
+fn parse_date(s: &str) -> Option<i32> {
+    let mut parts = s.trim().splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    i32::try_from(days_from_civil(year, month, day)).ok()
+}
+
+// Parse `HH:MM[:SS[.ffffff]]` into microseconds since midnight
+fn parse_time(s: &str) -> Option<i64> {
+    let mut parts = s.trim().splitn(3, ':');
+    let hours: i64 = parts.next()?.parse().ok()?;
+    let minutes: i64 = parts.next()?.parse().ok()?;
+    let (seconds, fraction) = match parts.next() {
+        Some(seconds) => seconds.split_once('.').unwrap_or((seconds, "")),
+        None => ("0", ""),
+    };
+    let seconds: i64 = seconds.parse().ok()?;
+    if hours > 23 || minutes > 59 || seconds > 59 || fraction.len() > 6 {
+        return None;
+    }
+    let fraction = if fraction.is_empty() {
+        0
+    } else {
+        format!("{:0<6}", fraction).parse::<i64>().ok()?
+    };
+    Some(((hours * 60 + minutes) * 60 + seconds) * MICROS_PER_SECOND + fraction)
+}
+
+// Parse `YYYY-MM-DD[ HH:MM:SS[.ffffff]]`, `T` is accepted as separator too
+fn parse_timestamp(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let (date, time) = match s.split_once([' ', 'T']) {
+        Some((date, time)) => (date, parse_time(time)?),
+        None => (s, 0),
+    };
+    Some(parse_date(date)? as i64 * MICROS_PER_DAY + time)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
@@ -0,0 +1,173 @@
+// Write-ahead log of the statements that changed the database.
+//
+// Every record holds the SQL text of one successful mutating statement together with its log
+// sequence number (LSN), framed as `[length: u32][crc32: u32][lsn: u64][sql bytes]` in little
+// endian; the checksum covers the LSN and the SQL. Records are fsynced before the statement is
+// acknowledged and replayed in order on startup. A torn record at the end of the file, left
+// behind by a crash in the middle of a write, is cut off when the log is opened; one left by a
+// write that failed is cut off right away, and the statement is taken back.
+//
+// A transaction is logged when it commits, as its statements between a BEGIN and a COMMIT
+// record written together. If a crash cuts it short, replay ends inside the transaction and
+// rolls it back.
+
+use super::DbResult;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+
+const HEADER_SIZE: usize = 8;
+const LSN_SIZE: usize = 8;
+
+pub struct Wal {
+    file: File,
+    next_lsn: u64,
+    // Records currently in the file, i.e. written since the last checkpoint
+    records: u64,
+    // A failed append could not be cut off, nothing can be appended after it
+    failed: bool,
+    // Bytes of incomplete records cut off when the log was opened
+    discarded: usize,
+}
+
+impl Wal {
+    // Open or create the log. Returns it together with the statements logged after LSN `after`,
+    // earlier ones are already part of the snapshot.
+    pub fn open(path: &Path, after: u64) -> DbResult<(Wal, Vec<String>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        let mut statements = Vec::new();
+        let mut last_lsn = after;
+        let mut records = 0;
+        let mut offset = 0;
+        while let Some((lsn, sql, next)) = Self::read_record(&data, offset) {
+            if lsn > after {
+                statements.push(sql);
+            }
+            last_lsn = last_lsn.max(lsn);
+            records += 1;
+            offset = next;
+        }
+        if offset < data.len() {
+            file.set_len(offset as u64)?;
+            file.sync_all()?;
+        }
+        let wal = Wal {
+            file,
+            next_lsn: last_lsn + 1,
+            records,
+            failed: false,
+            discarded: data.len() - offset,
+        };
+        Ok((wal, statements))
+    }
+
+    fn read_record(data: &[u8], offset: usize) -> Option<(u64, String, usize)> {
+        let header = data.get(offset..offset + HEADER_SIZE)?;
+        let length = u32::from_le_bytes(header[..4].try_into().ok()?) as usize;
+        let expected = u32::from_le_bytes(header[4..].try_into().ok()?);
+        let end = offset + HEADER_SIZE + length;
+        let body = data.get(offset + HEADER_SIZE..end)?;
+        if length < LSN_SIZE || checksum(body) != expected {
+            return None;
+        }
+        let lsn = u64::from_le_bytes(body[..LSN_SIZE].try_into().ok()?);
+        let sql = String::from_utf8(body[LSN_SIZE..].to_vec()).ok()?;
+        Some((lsn, sql, end))
+    }
+
+    // Append statements, one record each, and wait until they are all on disk
+    pub fn append(&mut self, statements: &[&str]) -> DbResult<()> {
+        let mut records = Vec::new();
+        for (lsn, sql) in (self.next_lsn..).zip(statements) {
+            let mut body = Vec::with_capacity(LSN_SIZE + sql.len());
+            body.extend_from_slice(&lsn.to_le_bytes());
+            body.extend_from_slice(sql.as_bytes());
+            let length =
+                u32::try_from(body.len()).map_err(|_| "Statement too large for the WAL")?;
+
+            records.extend_from_slice(&length.to_le_bytes());
+            records.extend_from_slice(&checksum(&body).to_le_bytes());
+            records.extend_from_slice(&body);
+        }
+        if self.failed {
+            return Err("The WAL could not be repaired after a failed write".into());
+        }
+        // A write that fails part of the way is cut off again, so later records are not
+        // appended after a torn one and lost on replay
+        let length = self.file.metadata()?.len();
+        let written = self
+            .file
+            .write_all(&records)
+            .and_then(|_| self.file.sync_data());
+        if let Err(e) = written {
+            if self
+                .file
+                .set_len(length)
+                .and_then(|_| self.file.sync_all())
+                .is_err()
+            {
+                self.failed = true;
+            }
+            return Err(e.into());
+        }
+        self.next_lsn += statements.len() as u64;
+        self.records += statements.len() as u64;
+        Ok(())
+    }
+
+    // Swap the file for one that cannot be written
+    #[cfg(test)]
+    pub fn break_writes(&mut self) -> DbResult<()> {
+        self.file = File::open("/dev/null")?;
+        Ok(())
+    }
+
+    // LSN of the last record ever appended, 0 if there is none
+    pub fn last_lsn(&self) -> u64 {
+        self.next_lsn - 1
+    }
+
+    pub fn discarded(&self) -> usize {
+        self.discarded
+    }
+
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    // Drop every record, once a snapshot covers them. LSNs keep counting up.
+    pub fn truncate(&mut self) -> DbResult<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.records = 0;
+        self.failed = false;
+        Ok(())
+    }
+}
+
+// CRC-32 (IEEE), enough to tell a complete record from a torn one
+pub fn checksum(data: &[u8]) -> u32 {
+    !extend_checksum(!0, data)
+}
+
+// Feed more data into a running CRC-32, which starts at `!0` and is inverted at the end
+pub fn extend_checksum(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
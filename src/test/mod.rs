@@ -1,7 +1,6 @@
 #[cfg(test)]
 mod tests {
     use crate::storage::*;
-    use sqlparser::ast::Value;
 
     #[test]
     fn test_database_creation() {
@@ -100,10 +99,12 @@ mod tests {
         db.execute("CREATE TABLE users (id INT, name STRING)")
             .unwrap();
 
-        let insert_result = db.execute("INSERT INTO users (id) VALUES (1)");
-        // The behavior here depends on how you want to handle missing columns.
-        // This test assumes it's a failure, but you might choose to allow NULL values.
-        assert!(matches!(insert_result, Err(..)));
+        // Columns missing from the list get their default, NULL without one
+        db.execute("INSERT INTO users (id) VALUES (1)").unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT * FROM users"),
+            vec![vec![Value::Integer(1), Value::Null]]
+        );
     }
 
     #[test]
@@ -242,6 +243,12 @@ mod tests {
 
         let result = db.execute("SELECT * FROM users ORDER BY id");
         assert!(result.is_ok());
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows[0][1], Value::Text("Alice".to_string()));
+            assert_eq!(data.rows[1][1], Value::Text("Bob".to_string()));
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
     }
 
     #[test]
@@ -440,10 +447,7 @@ mod tests {
             );
             assert_eq!(
                 data.rows[0],
-                vec![
-                    Value::SingleQuotedString("Alice".to_string()),
-                    Value::Number("400".to_string(), false)
-                ]
+                vec![Value::Text("Alice".to_string()), Value::Integer(400)]
             );
         } else {
             panic!("Expected Success QueryResult");
@@ -526,7 +530,7 @@ mod tests {
                 .rows
                 .iter()
                 .map(|row| match row.get(1).unwrap() {
-                    Value::SingleQuotedString(s) => s.clone(),
+                    Value::Text(s) => s.clone(),
                     _ => panic!("Expected string value"),
                 })
                 .collect();
@@ -604,14 +608,14 @@ mod tests {
             );
             assert_eq!(
                 data.columns,
-                vec!["category".to_string(), "SUM(amount)".to_string()]
+                vec!["category".to_string(), "total_sales".to_string()]
             );
 
             // Check the result row
             if !data.rows.is_empty() {
                 let row = &data.rows[0];
-                assert_eq!(row[0], Value::SingleQuotedString("Fruit".to_string()));
-                assert_eq!(row[1], Value::Number("570".to_string(), false)); // Total sales for Fruit
+                assert_eq!(row[0], Value::Text("Fruit".to_string()));
+                assert_eq!(row[1], Value::Integer(570)); // Total sales for Fruit
             }
         } else {
             panic!("Expected Rows QueryResult");
@@ -630,7 +634,7 @@ mod tests {
             assert_eq!(data.rows.len(), 2);
             assert_eq!(
                 data.columns,
-                vec!["category".to_string(), "SUM(amount)".to_string()]
+                vec!["category".to_string(), "total_sales".to_string()]
             );
 
             // Sort the results for consistent ordering
@@ -638,14 +642,11 @@ mod tests {
             rows.sort_by(|a, b| a[0].to_string().cmp(&b[0].to_string()));
 
             // Check the result rows
-            assert_eq!(rows[0][0], Value::SingleQuotedString("Fruit".to_string()));
-            assert_eq!(rows[0][1], Value::Number("570".to_string(), false));
+            assert_eq!(rows[0][0], Value::Text("Fruit".to_string()));
+            assert_eq!(rows[0][1], Value::Integer(570));
 
-            assert_eq!(
-                rows[1][0],
-                Value::SingleQuotedString("Vegetable".to_string())
-            );
-            assert_eq!(rows[1][1], Value::Number("170".to_string(), false));
+            assert_eq!(rows[1][0], Value::Text("Vegetable".to_string()));
+            assert_eq!(rows[1][1], Value::Integer(170));
         } else {
             panic!("Expected Rows QueryResult");
         }
@@ -675,7 +676,7 @@ mod tests {
                 .rows
                 .iter()
                 .map(|row| match row.get(1).unwrap() {
-                    Value::SingleQuotedString(s) => s.clone(),
+                    Value::Text(s) => s.clone(),
                     _ => panic!("Expected string value"),
                 })
                 .collect();
@@ -696,7 +697,7 @@ mod tests {
                 .rows
                 .iter()
                 .map(|row| match row.get(1).unwrap() {
-                    Value::SingleQuotedString(s) => s.clone(),
+                    Value::Text(s) => s.clone(),
                     _ => panic!("Expected string value"),
                 })
                 .collect();
@@ -732,7 +733,7 @@ mod tests {
                 .rows
                 .iter()
                 .map(|row| match row.get(1).unwrap() {
-                    Value::SingleQuotedString(s) => s.clone(),
+                    Value::Text(s) => s.clone(),
                     _ => panic!("Expected string value"),
                 })
                 .collect();
@@ -752,7 +753,7 @@ mod tests {
                 .rows
                 .iter()
                 .map(|row| match row.get(1).unwrap() {
-                    Value::SingleQuotedString(s) => s.clone(),
+                    Value::Text(s) => s.clone(),
                     _ => panic!("Expected string value"),
                 })
                 .collect();
@@ -764,4 +765,2952 @@ mod tests {
             panic!("Expected Select QueryResult");
         }
     }
+
+    #[test]
+    fn test_update_with_where_clause() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT, name STRING, age INT)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name, age) VALUES (1, 'Alice', 30)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name, age) VALUES (2, 'Bob', 25)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name, age) VALUES (3, 'Charlie', 35)")
+            .unwrap();
+
+        let result = db.execute("UPDATE users SET age = 40, name = 'Old' WHERE age > 28");
+        if let QueryResult::Success(message) = result.unwrap() {
+            assert_eq!(message, "Updated 2 row(s)");
+        } else {
+            panic!("Expected Success QueryResult");
+        }
+
+        let result = db.execute("SELECT * FROM users WHERE age = 40");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows.len(), 2);
+            for row in &data.rows {
+                assert_eq!(row[1], Value::Text("Old".to_string()));
+            }
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+    }
+
+    #[test]
+    fn test_update_without_where_clause() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT, active BOOLEAN)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, active) VALUES (1, true)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, active) VALUES (2, true)")
+            .unwrap();
+
+        let result = db.execute("UPDATE users SET active = false");
+        if let QueryResult::Success(message) = result.unwrap() {
+            assert_eq!(message, "Updated 2 row(s)");
+        } else {
+            panic!("Expected Success QueryResult");
+        }
+
+        let result = db.execute("SELECT * FROM users WHERE active = true");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows.len(), 0);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+    }
+
+    #[test]
+    fn test_update_with_type_mismatch() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT, name STRING)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name) VALUES (1, 'Alice')")
+            .unwrap();
+
+        let result = db.execute("UPDATE users SET id = 'one'");
+        assert!(result.is_err());
+
+        let result = db.execute("UPDATE users SET missing = 1");
+        assert!(result.is_err());
+
+        // The failed updates must not have touched the row
+        let result = db.execute("SELECT * FROM users WHERE id = 1");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows.len(), 1);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+    }
+
+    #[test]
+    fn test_delete_with_where_clause() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT, name STRING, age INT)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name, age) VALUES (1, 'Alice', 30)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name, age) VALUES (2, 'Bob', 25)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name, age) VALUES (3, 'Charlie', 35)")
+            .unwrap();
+
+        let result = db.execute("DELETE FROM users WHERE age >= 30");
+        if let QueryResult::Success(message) = result.unwrap() {
+            assert_eq!(message, "Deleted 2 row(s)");
+        } else {
+            panic!("Expected Success QueryResult");
+        }
+
+        let result = db.execute("SELECT * FROM users");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows.len(), 1);
+            assert_eq!(data.rows[0][1], Value::Text("Bob".to_string()));
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+    }
+
+    #[test]
+    fn test_delete_all_rows() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT, name STRING)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name) VALUES (1, 'Alice')")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name) VALUES (2, 'Bob')")
+            .unwrap();
+
+        let result = db.execute("DELETE FROM users");
+        if let QueryResult::Success(message) = result.unwrap() {
+            assert_eq!(message, "Deleted 2 row(s)");
+        } else {
+            panic!("Expected Success QueryResult");
+        }
+
+        let result = db.execute("SELECT * FROM users");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows.len(), 0);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        let result = db.execute("DELETE FROM nonexistent");
+        assert!(result.is_err());
+    }
+
+    fn setup_employees_and_departments(db: &mut Database) {
+        db.execute("CREATE TABLE employees (id INT, name STRING, department_id INT)")
+            .unwrap();
+        db.execute("CREATE TABLE departments (id INT, name STRING)")
+            .unwrap();
+        db.execute("INSERT INTO employees (id, name, department_id) VALUES (1, 'Alice', 1)")
+            .unwrap();
+        db.execute("INSERT INTO employees (id, name, department_id) VALUES (2, 'Bob', 2)")
+            .unwrap();
+        db.execute("INSERT INTO employees (id, name, department_id) VALUES (3, 'Carol', NULL)")
+            .unwrap();
+        db.execute("INSERT INTO departments (id, name) VALUES (1, 'Sales')")
+            .unwrap();
+        db.execute("INSERT INTO departments (id, name) VALUES (2, 'HR')")
+            .unwrap();
+        db.execute("INSERT INTO departments (id, name) VALUES (3, 'IT')")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_select_with_outer_joins() {
+        let mut db = Database::new();
+        setup_employees_and_departments(&mut db);
+
+        let result = db.execute("SELECT employees.name, departments.name FROM employees LEFT JOIN departments ON employees.department_id = departments.id");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.columns, vec!["employees.name", "departments.name"]);
+            assert_eq!(data.rows.len(), 3);
+            assert!(data
+                .rows
+                .contains(&vec![Value::Text("Carol".to_string()), Value::Null]));
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        let result = db.execute("SELECT employees.name, departments.name FROM employees RIGHT JOIN departments ON employees.department_id = departments.id");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows.len(), 3);
+            assert!(data
+                .rows
+                .contains(&vec![Value::Null, Value::Text("IT".to_string())]));
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        let result = db.execute("SELECT * FROM employees FULL OUTER JOIN departments ON employees.department_id = departments.id");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.columns.len(), 5);
+            assert_eq!(data.rows.len(), 4);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+    }
+
+    #[test]
+    fn test_select_with_cross_join() {
+        let mut db = Database::new();
+        setup_employees_and_departments(&mut db);
+
+        let result = db.execute("SELECT * FROM employees CROSS JOIN departments");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows.len(), 9);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        let result = db.execute(
+            "SELECT departments.* FROM employees, departments WHERE employees.department_id = departments.id",
+        );
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.columns, vec!["id", "name"]);
+            assert_eq!(data.rows.len(), 2);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+    }
+
+    #[test]
+    fn test_select_with_join_using() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT, name STRING)")
+            .unwrap();
+        db.execute("CREATE TABLE emails (id INT, email STRING)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name) VALUES (1, 'Alice')")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name) VALUES (2, 'Bob')")
+            .unwrap();
+        db.execute("INSERT INTO emails (id, email) VALUES (2, 'bob@example.com')")
+            .unwrap();
+        db.execute("INSERT INTO emails (id, email) VALUES (3, 'eve@example.com')")
+            .unwrap();
+
+        let result = db.execute("SELECT * FROM users JOIN emails USING (id)");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.columns, vec!["id", "name", "email"]);
+            assert_eq!(data.rows.len(), 1);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        // The merged USING column takes whichever side is present
+        let result = db.execute("SELECT id, users.id FROM users FULL JOIN emails USING (id)");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows.len(), 3);
+            assert!(data.rows.contains(&vec![Value::Integer(3), Value::Null]));
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+    }
+
+    #[test]
+    fn test_select_with_table_aliases() {
+        let mut db = Database::new();
+        setup_employees_and_departments(&mut db);
+
+        let result = db.execute(
+            "SELECT e.name, d.name FROM employees AS e JOIN departments d ON e.department_id = d.id WHERE d.name = 'HR'",
+        );
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows.len(), 1);
+            assert_eq!(
+                data.rows[0],
+                vec![
+                    Value::Text("Bob".to_string()),
+                    Value::Text("HR".to_string())
+                ]
+            );
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        // Self join is possible once both sides have their own alias
+        let result = db.execute(
+            "SELECT a.name, b.name FROM employees a JOIN employees b ON a.department_id = b.department_id WHERE a.id < b.id",
+        );
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows.len(), 0);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        // An aliased table is no longer visible under its own name
+        let result = db.execute("SELECT employees.name FROM employees e");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_select_with_schema_qualified_column() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE shop.items (id INT, name STRING)")
+            .unwrap();
+        db.execute("INSERT INTO shop.items (id, name) VALUES (1, 'Pen')")
+            .unwrap();
+
+        let result =
+            db.execute("SELECT shop.items.name, items.id FROM shop.items WHERE shop.items.id = 1");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows.len(), 1);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+    }
+
+    #[test]
+    fn test_select_with_ambiguous_or_unknown_columns() {
+        let mut db = Database::new();
+        setup_employees_and_departments(&mut db);
+
+        let result = db.execute(
+            "SELECT name FROM employees JOIN departments ON employees.department_id = departments.id",
+        );
+        assert!(result.unwrap_err().to_string().contains("ambiguous"));
+
+        let result = db.execute("SELECT * FROM employees JOIN employees ON id = id");
+        assert!(result.is_err());
+
+        // Unknown names are reported as errors instead of panicking
+        let result = db.execute("SELECT * FROM employees WHERE missing = 1");
+        assert!(result.unwrap_err().to_string().contains("not found"));
+        let result = db.execute("SELECT * FROM employees WHERE unknown.id = 1");
+        assert!(result.is_err());
+        let result = db.execute("UPDATE employees SET name = 'X' WHERE missing = 1");
+        assert!(result.is_err());
+    }
+
+    fn column_values(data: &SelectResult, index: usize) -> Vec<Value> {
+        data.rows.iter().map(|row| row[index].clone()).collect()
+    }
+
+    #[test]
+    fn test_select_with_order_by_multiple_keys() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT, name STRING, age INT)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name, age) VALUES (1, 'Carol', 30)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name, age) VALUES (2, 'Alice', 25)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name, age) VALUES (3, 'Bob', 30)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name, age) VALUES (4, 'Dave', 9)")
+            .unwrap();
+
+        let result = db.execute("SELECT id FROM users ORDER BY age DESC, name ASC");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(
+                column_values(&data, 0),
+                vec![
+                    Value::Integer(3),
+                    Value::Integer(1),
+                    Value::Integer(2),
+                    Value::Integer(4),
+                ]
+            );
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        // Ordinals and output aliases refer to the select list
+        let result = db.execute("SELECT name AS n, id FROM users ORDER BY 2 DESC LIMIT 1");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows[0][0], Value::Text("Dave".to_string()));
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+        let result = db.execute("SELECT name AS n FROM users ORDER BY n LIMIT 1");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows[0][0], Value::Text("Alice".to_string()));
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        let result = db.execute("SELECT name FROM users ORDER BY 5");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_order_by_mixed_types_ignores_insertion_order() {
+        let orders = [
+            [1, 2, 3],
+            [1, 3, 2],
+            [2, 1, 3],
+            [2, 3, 1],
+            [3, 1, 2],
+            [3, 2, 1],
+        ];
+        for order in orders {
+            let mut db = Database::new();
+            db.execute("CREATE TABLE t (id INT)").unwrap();
+            for id in order {
+                db.execute(&format!("INSERT INTO t VALUES ({})", id))
+                    .unwrap();
+            }
+            // Strings sort before dates, whatever the strings hold
+            assert_eq!(
+                select_rows(
+                    &mut db,
+                    "SELECT id FROM t ORDER BY CASE id WHEN 1 THEN CAST('2020-01-01' AS DATE) \
+                     WHEN 2 THEN '2020-01-02' ELSE 'abc' END"
+                ),
+                ids(&[2, 3, 1]),
+                "insertion order {:?}",
+                order
+            );
+        }
+    }
+
+    #[test]
+    fn test_select_with_order_by_nulls() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT, score INT)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, score) VALUES (1, NULL)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, score) VALUES (2, 10)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, score) VALUES (3, 5)")
+            .unwrap();
+
+        let ids = |db: &mut Database, sql: &str| match db.execute(sql).unwrap() {
+            QueryResult::Rows(data) => column_values(&data, 0),
+            _ => panic!("Expected Rows QueryResult"),
+        };
+        let number = |n: &str| Value::Integer(n.parse().unwrap());
+
+        assert_eq!(
+            ids(&mut db, "SELECT id FROM users ORDER BY score"),
+            vec![number("1"), number("3"), number("2")]
+        );
+        assert_eq!(
+            ids(&mut db, "SELECT id FROM users ORDER BY score DESC"),
+            vec![number("2"), number("3"), number("1")]
+        );
+        assert_eq!(
+            ids(&mut db, "SELECT id FROM users ORDER BY score NULLS LAST"),
+            vec![number("3"), number("2"), number("1")]
+        );
+        assert_eq!(
+            ids(&mut db, "SELECT id FROM users ORDER BY score NULLS FIRST"),
+            vec![number("1"), number("3"), number("2")]
+        );
+        assert_eq!(
+            ids(
+                &mut db,
+                "SELECT id FROM users ORDER BY score DESC NULLS LAST"
+            ),
+            vec![number("2"), number("3"), number("1")]
+        );
+    }
+
+    #[test]
+    fn test_select_with_order_by_and_group_by() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE orders (id INT, customer STRING, amount INT)")
+            .unwrap();
+        db.execute("INSERT INTO orders (id, customer, amount) VALUES (1, 'Alice', 100)")
+            .unwrap();
+        db.execute("INSERT INTO orders (id, customer, amount) VALUES (2, 'Bob', 500)")
+            .unwrap();
+        db.execute("INSERT INTO orders (id, customer, amount) VALUES (3, 'Alice', 300)")
+            .unwrap();
+        db.execute("INSERT INTO orders (id, customer, amount) VALUES (4, 'Carol', 50)")
+            .unwrap();
+
+        let result = db.execute(
+            "SELECT customer, SUM(amount) FROM orders GROUP BY customer ORDER BY SUM(amount) DESC",
+        );
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(
+                column_values(&data, 0),
+                vec![
+                    Value::Text("Bob".to_string()),
+                    Value::Text("Alice".to_string()),
+                    Value::Text("Carol".to_string()),
+                ]
+            );
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+    }
+
+    #[test]
+    fn test_order_by_aggregates_not_in_select_list() {
+        let mut db = Database::new();
+        setup_orders(&mut db);
+
+        let result = db.execute(
+            "SELECT customer FROM orders GROUP BY customer ORDER BY COUNT(*) DESC, SUM(amount) + 1",
+        );
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.columns, vec!["customer".to_string()]);
+            assert!(data.rows.iter().all(|row| row.len() == 1));
+            assert_eq!(
+                column_values(&data, 0),
+                vec![
+                    Value::Text("Alice".to_string()),
+                    Value::Text("Carol".to_string()),
+                    Value::Text("Bob".to_string()),
+                ]
+            );
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        // Without GROUP BY the aggregate collapses the table into one row
+        let result = db.execute("SELECT COUNT(id) FROM orders ORDER BY COUNT(*)");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows, vec![vec![Value::Integer(4)]]);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+        let result = db.execute("SELECT id FROM orders ORDER BY COUNT(*)");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.columns.len(), 1);
+            assert_eq!(data.rows.len(), 1);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+        // The hidden sort key is no position of the select list
+        assert!(db
+            .execute("SELECT customer FROM orders GROUP BY customer ORDER BY COUNT(*), 2")
+            .is_err());
+    }
+
+    fn setup_orders(db: &mut Database) {
+        db.execute("CREATE TABLE orders (id INT, customer STRING, amount INT)")
+            .unwrap();
+        db.execute("INSERT INTO orders (id, customer, amount) VALUES (1, 'Alice', 100)")
+            .unwrap();
+        db.execute("INSERT INTO orders (id, customer, amount) VALUES (2, 'Bob', 200)")
+            .unwrap();
+        db.execute("INSERT INTO orders (id, customer, amount) VALUES (3, 'Alice', 300)")
+            .unwrap();
+        db.execute("INSERT INTO orders (id, customer, amount) VALUES (4, 'Carol', NULL)")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_select_with_aggregates_without_group_by() {
+        let mut db = Database::new();
+        setup_orders(&mut db);
+
+        let result = db.execute(
+            "SELECT COUNT(*), COUNT(amount), COUNT(DISTINCT customer), AVG(amount), MIN(amount), MAX(customer) FROM orders",
+        );
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(
+                data.columns,
+                vec![
+                    "COUNT(*)",
+                    "COUNT(amount)",
+                    "COUNT(DISTINCT customer)",
+                    "AVG(amount)",
+                    "MIN(amount)",
+                    "MAX(customer)"
+                ]
+            );
+            assert_eq!(data.rows.len(), 1);
+            assert_eq!(
+                data.rows[0],
+                vec![
+                    Value::Integer(4),
+                    Value::Integer(3),
+                    Value::Integer(3),
+                    Value::Decimal("200.0000".parse().unwrap()),
+                    Value::Integer(100),
+                    Value::Text("Carol".to_string()),
+                ]
+            );
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        // Aggregating an empty input still returns one row
+        let result = db.execute("SELECT COUNT(*), SUM(amount) FROM orders WHERE amount > 1000");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows, vec![vec![Value::Integer(0), Value::Null]]);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+    }
+
+    #[test]
+    fn test_select_with_aggregates_per_group() {
+        let mut db = Database::new();
+        setup_orders(&mut db);
+
+        let result = db.execute(
+            "SELECT customer, COUNT(*), MIN(amount), MAX(amount) FROM orders GROUP BY customer HAVING COUNT(amount) > 0 ORDER BY customer",
+        );
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(
+                data.rows,
+                vec![
+                    vec![
+                        Value::Text("Alice".to_string()),
+                        Value::Integer(2),
+                        Value::Integer(100),
+                        Value::Integer(300),
+                    ],
+                    vec![
+                        Value::Text("Bob".to_string()),
+                        Value::Integer(1),
+                        Value::Integer(200),
+                        Value::Integer(200),
+                    ],
+                ]
+            );
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        let result = db.execute("SELECT UNKNOWN_FUNC(amount) FROM orders");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_aggregate_alias_names_output_column() {
+        let mut db = Database::new();
+        setup_orders(&mut db);
+
+        let result = db.execute("SELECT customer, COUNT(*) AS c FROM orders GROUP BY customer");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.columns, vec!["customer", "c"]);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        // The alias is what a derived table exposes
+        assert_eq!(
+            select_rows(
+                &mut db,
+                "SELECT d.customer FROM (SELECT customer, COUNT(*) AS c FROM orders GROUP BY customer) d WHERE d.c >= 2 ORDER BY d.customer",
+            ),
+            vec![vec![Value::Text("Alice".to_string())]]
+        );
+    }
+
+    #[test]
+    fn test_select_with_in_and_exists_subqueries() {
+        let mut db = Database::new();
+        setup_employees_and_departments(&mut db);
+
+        let result = db.execute(
+            "SELECT name FROM departments WHERE id IN (SELECT department_id FROM employees)",
+        );
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows.len(), 2);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        // Correlated EXISTS refers to the outer row through its table name
+        let result = db.execute(
+            "SELECT name FROM departments d WHERE NOT EXISTS (SELECT * FROM employees e WHERE e.department_id = d.id)",
+        );
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows, vec![vec![Value::Text("IT".to_string())]]);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+    }
+
+    #[test]
+    fn test_select_with_correlated_scalar_subquery() {
+        let mut db = Database::new();
+        setup_employees_and_departments(&mut db);
+
+        let result = db.execute(
+            "SELECT name, (SELECT COUNT(*) FROM employees WHERE department_id = d.id) AS headcount FROM departments d ORDER BY name",
+        );
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.columns, vec!["name", "headcount"]);
+            assert_eq!(
+                data.rows,
+                vec![
+                    vec![Value::Text("HR".to_string()), Value::Integer(1)],
+                    vec![Value::Text("IT".to_string()), Value::Integer(0)],
+                    vec![Value::Text("Sales".to_string()), Value::Integer(1)],
+                ]
+            );
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        // A scalar subquery must not produce more than one row
+        let result = db.execute("SELECT * FROM employees WHERE id = (SELECT id FROM departments)");
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("more than one row"));
+        let result = db.execute("SELECT * FROM employees WHERE id IN (SELECT * FROM departments)");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_select_from_derived_table() {
+        let mut db = Database::new();
+        setup_orders(&mut db);
+
+        let result = db.execute(
+            "SELECT t.customer FROM (SELECT customer, SUM(amount) AS total FROM orders GROUP BY customer) AS t (customer, total) WHERE t.total > 250",
+        );
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows, vec![vec![Value::Text("Alice".to_string())]]);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+    }
+
+    fn matching_names(db: &mut Database, condition: &str) -> Vec<Value> {
+        let result = db.execute(&format!("SELECT name FROM users WHERE {}", condition));
+        if let QueryResult::Rows(data) = result.unwrap() {
+            column_values(&data, 0)
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+    }
+
+    #[test]
+    fn test_select_with_like_variants() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT, name STRING)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name) VALUES (1, 'Alice')")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name) VALUES (2, 'Bob')")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name) VALUES (3, '50%_off')")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name) VALUES (4, NULL)")
+            .unwrap();
+        let names = |names: &[&str]| -> Vec<Value> {
+            names
+                .iter()
+                .map(|name| Value::Text(name.to_string()))
+                .collect()
+        };
+
+        assert_eq!(matching_names(&mut db, "name LIKE '_ob'"), names(&["Bob"]));
+        assert_eq!(matching_names(&mut db, "name LIKE 'a%'"), names(&[]));
+        assert_eq!(
+            matching_names(&mut db, "name NOT LIKE 'A%'"),
+            names(&["Bob", "50%_off"])
+        );
+        assert_eq!(
+            matching_names(&mut db, "name ILIKE 'a%'"),
+            names(&["Alice"])
+        );
+        assert_eq!(
+            matching_names(&mut db, "name NOT ILIKE '%B'"),
+            names(&["Alice", "50%_off"])
+        );
+        assert_eq!(
+            matching_names(&mut db, "name LIKE '%#%#_%' ESCAPE '#'"),
+            names(&["50%_off"])
+        );
+        assert_eq!(
+            matching_names(&mut db, "name LIKE '50!%%' ESCAPE '!'"),
+            names(&["50%_off"])
+        );
+        assert_eq!(
+            matching_names(&mut db, "name LIKE '50!%' ESCAPE '!'"),
+            names(&[])
+        );
+        // The escape character is taken as written, not case folded with the pattern
+        assert_eq!(
+            matching_names(&mut db, "name ILIKE '50A%A_OFF' ESCAPE 'A'"),
+            names(&["50%_off"])
+        );
+    }
+
+    #[test]
+    fn test_pattern_matching_on_long_texts() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT, name STRING)")
+            .unwrap();
+        let long = format!("{}b", "a".repeat(20_000));
+        db.execute(&format!("INSERT INTO users VALUES (1, '{}')", long))
+            .unwrap();
+        db.execute(&format!(
+            "INSERT INTO users VALUES (2, '{}')",
+            "a".repeat(36)
+        ))
+        .unwrap();
+        let ids = |db: &mut Database, condition: &str| -> Vec<Value> {
+            let result = db.execute(&format!("SELECT id FROM users WHERE {}", condition));
+            if let QueryResult::Rows(data) = result.unwrap() {
+                column_values(&data, 0)
+            } else {
+                panic!("Expected Rows QueryResult");
+            }
+        };
+
+        assert_eq!(ids(&mut db, "name LIKE '%b'"), vec![Value::Integer(1)]);
+        assert_eq!(
+            ids(&mut db, "name ILIKE '%A_'"),
+            vec![Value::Integer(1), Value::Integer(2)]
+        );
+        // Every `%` could take any part of the text, none of which ends the match
+        assert_eq!(
+            ids(&mut db, "name LIKE '%a%a%a%a%a%a%a%a%b'"),
+            vec![Value::Integer(1)]
+        );
+        assert_eq!(ids(&mut db, "name LIKE '%a%a%a%a%a%a%a%a%c'"), vec![]);
+        assert_eq!(
+            ids(&mut db, "name SIMILAR TO '%b'"),
+            vec![Value::Integer(1)]
+        );
+        assert_eq!(
+            ids(&mut db, "name SIMILAR TO '(a|aa)*'"),
+            vec![Value::Integer(2)]
+        );
+        assert_eq!(ids(&mut db, "name SIMILAR TO '(a*)*c'"), vec![]);
+        assert_eq!(
+            ids(&mut db, "name SIMILAR TO '(a{1,3}){12}'"),
+            vec![Value::Integer(2)]
+        );
+        assert_eq!(ids(&mut db, "name SIMILAR TO 'a{5000}'"), vec![]);
+        // Nested quantifiers multiply the size of the automaton
+        for pattern in [
+            "((a{100}){100}){100}",
+            "(((a{1000}){1000}){1000})",
+            "a{1000000000}",
+        ] {
+            let result = db.execute(&format!(
+                "SELECT id FROM users WHERE name SIMILAR TO '{}'",
+                pattern
+            ));
+            assert!(result
+                .unwrap_err()
+                .to_string()
+                .starts_with("SIMILAR TO pattern needs more than 100000 instructions"));
+        }
+    }
+
+    #[test]
+    fn test_select_with_similar_to() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT, name STRING)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name) VALUES (1, 'Alice')")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name) VALUES (2, 'Bob')")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name) VALUES (3, 'Bobby')")
+            .unwrap();
+        let names = |names: &[&str]| -> Vec<Value> {
+            names
+                .iter()
+                .map(|name| Value::Text(name.to_string()))
+                .collect()
+        };
+
+        assert_eq!(
+            matching_names(&mut db, "name SIMILAR TO '(Alice|Bob)'"),
+            names(&["Alice", "Bob"])
+        );
+        assert_eq!(
+            matching_names(&mut db, "name SIMILAR TO 'Bob(by)?'"),
+            names(&["Bob", "Bobby"])
+        );
+        assert_eq!(
+            matching_names(&mut db, "name SIMILAR TO '[A-B][a-z]{2,3}'"),
+            names(&["Bob"])
+        );
+        assert_eq!(
+            matching_names(&mut db, "name NOT SIMILAR TO '%b+y'"),
+            names(&["Alice", "Bob"])
+        );
+        assert!(db
+            .execute("SELECT name FROM users WHERE name SIMILAR TO '(Bob'")
+            .is_err());
+    }
+
+    #[test]
+    fn test_typed_values() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE payments (id INT, amount DECIMAL(10, 2), rate FLOAT, paid DATE)")
+            .unwrap();
+        db.execute("INSERT INTO payments VALUES (1, 0.1, 1.5, '2024-01-31')")
+            .unwrap();
+        db.execute("INSERT INTO payments VALUES (2, 0.2, 2, '2024-02-29')")
+            .unwrap();
+        db.execute("INSERT INTO payments VALUES (3, 10, 0.25, '2023-12-01')")
+            .unwrap();
+
+        // Decimal sums are exact and keep the column scale, floats stay floats
+        let result = db.execute("SELECT SUM(amount), SUM(rate), SUM(id) FROM payments");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(
+                data.rows,
+                vec![vec![
+                    Value::Decimal("10.30".parse().unwrap()),
+                    Value::Float(3.75),
+                    Value::Integer(6),
+                ]]
+            );
+            assert_eq!(data.rows[0][0].to_string(), "10.30");
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        // Dates compare as dates, string literals are read as dates when compared with them
+        let result =
+            db.execute("SELECT id, paid FROM payments WHERE paid >= '2024-01-01' ORDER BY paid");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(
+                column_values(&data, 0),
+                vec![Value::Integer(1), Value::Integer(2)]
+            );
+            assert_eq!(data.rows[1][1].to_string(), "2024-02-29");
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        // Values that cannot be represented in the column type are rejected
+        assert!(db
+            .execute("INSERT INTO payments VALUES (1.5, 1, 1, '2024-01-01')")
+            .is_err());
+        assert!(db
+            .execute("INSERT INTO payments VALUES (4, 1, 1, '2023-02-29')")
+            .is_err());
+        assert!(db
+            .execute("INSERT INTO payments VALUES (4, 123456789.5, 1, '2024-01-01')")
+            .is_err());
+    }
+
+    #[test]
+    fn test_select_arithmetic_expressions() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE items (id INT, name STRING, price DECIMAL(10, 2), qty INT)")
+            .unwrap();
+        db.execute("INSERT INTO items VALUES (1, 'pen', 1.25, 4)")
+            .unwrap();
+        db.execute("INSERT INTO items VALUES (2, 'book', 12.5, 2)")
+            .unwrap();
+        db.execute("INSERT INTO items VALUES (3, 'bag', 30, NULL)")
+            .unwrap();
+
+        let result = db.execute(
+            "SELECT name || '!', price * qty AS total, -id, (id + 1) * 2, qty / 3, 7 DIV 2, id % 2 FROM items",
+        );
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(
+                data.columns,
+                vec![
+                    "name || '!'",
+                    "total",
+                    "-id",
+                    "(id + 1) * 2",
+                    "qty / 3",
+                    "7 DIV 2",
+                    "id % 2"
+                ]
+            );
+            assert_eq!(
+                data.rows[0],
+                vec![
+                    Value::Text("pen!".to_string()),
+                    Value::Decimal("5.00".parse().unwrap()),
+                    Value::Integer(-1),
+                    Value::Integer(4),
+                    Value::Decimal("1.3333".parse().unwrap()),
+                    Value::Integer(3),
+                    Value::Integer(1),
+                ]
+            );
+            // Arithmetic with NULL yields NULL
+            assert_eq!(data.rows[2][1], Value::Null);
+            assert_eq!(data.rows[2][4], Value::Null);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        let result = db.execute("SELECT name FROM items WHERE price * qty > 10 AND -qty < -1");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(
+                column_values(&data, 0),
+                vec![Value::Text("book".to_string())]
+            );
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        assert!(db
+            .execute("SELECT id / 0 FROM items")
+            .unwrap_err()
+            .to_string()
+            .contains("Division by zero"));
+        assert!(db.execute("SELECT name + 1 FROM items").is_err());
+    }
+
+    #[test]
+    fn test_select_case_and_scalar_functions() {
+        let mut db = Database::new();
+        setup_orders(&mut db);
+
+        let result = db.execute(
+            "SELECT CASE WHEN amount >= 200 THEN 'big' WHEN amount IS NULL THEN 'none' ELSE 'small' END, \
+             CASE customer WHEN 'Bob' THEN 1 ELSE 0 END, \
+             COALESCE(amount, 0), UPPER(customer), CONCAT(customer, ':', amount) \
+             FROM orders",
+        );
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(
+                column_values(&data, 0),
+                vec![
+                    Value::Text("small".to_string()),
+                    Value::Text("big".to_string()),
+                    Value::Text("big".to_string()),
+                    Value::Text("none".to_string()),
+                ]
+            );
+            assert_eq!(
+                column_values(&data, 1),
+                vec![
+                    Value::Integer(0),
+                    Value::Integer(1),
+                    Value::Integer(0),
+                    Value::Integer(0)
+                ]
+            );
+            assert_eq!(data.rows[3][2], Value::Integer(0));
+            assert_eq!(data.rows[0][3], Value::Text("ALICE".to_string()));
+            assert_eq!(data.rows[1][4], Value::Text("Bob:200".to_string()));
+            assert_eq!(data.rows[3][4], Value::Null);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        // Aggregates can be part of larger expressions
+        let result = db.execute(
+            "SELECT customer, SUM(amount) * 2 AS doubled, COUNT(*) + 1 FROM orders GROUP BY customer HAVING SUM(amount) * 2 > 300",
+        );
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.columns, vec!["customer", "doubled", "COUNT(*) + 1"]);
+            assert_eq!(
+                data.rows,
+                vec![
+                    vec![
+                        Value::Text("Alice".to_string()),
+                        Value::Integer(800),
+                        Value::Integer(3)
+                    ],
+                    vec![
+                        Value::Text("Bob".to_string()),
+                        Value::Integer(400),
+                        Value::Integer(2)
+                    ],
+                ]
+            );
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        assert!(db
+            .execute("SELECT customer FROM orders WHERE SUM(amount) > 1")
+            .is_err());
+    }
+
+    #[test]
+    fn test_three_valued_null_logic() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE t (id INT, x INT, flag BOOLEAN)")
+            .unwrap();
+        db.execute("INSERT INTO t VALUES (1, 5, true)").unwrap();
+        db.execute("INSERT INTO t VALUES (2, 7, false)").unwrap();
+        db.execute("INSERT INTO t VALUES (3, NULL, NULL)").unwrap();
+        let ids = |db: &mut Database, condition: &str| -> Vec<Value> {
+            let result = db.execute(&format!("SELECT id FROM t WHERE {}", condition));
+            match result.unwrap() {
+                QueryResult::Rows(data) => column_values(&data, 0),
+                _ => panic!("Expected Rows QueryResult"),
+            }
+        };
+        let expected =
+            |ids: &[i64]| -> Vec<Value> { ids.iter().map(|i| Value::Integer(*i)).collect() };
+
+        // Comparisons with NULL are UNKNOWN, and so is their negation
+        assert_eq!(ids(&mut db, "x <> 5"), expected(&[2]));
+        assert_eq!(ids(&mut db, "NOT (x = 5)"), expected(&[2]));
+        assert_eq!(ids(&mut db, "NOT x BETWEEN 1 AND 6"), expected(&[2]));
+        // UNKNOWN OR TRUE is TRUE, UNKNOWN AND FALSE is FALSE
+        assert_eq!(ids(&mut db, "x = 5 OR id = 3"), expected(&[1, 3]));
+        assert_eq!(ids(&mut db, "NOT (x = 5 AND id = 2)"), expected(&[1, 2, 3]));
+        // NOT IN with a NULL in the list never matches
+        assert_eq!(ids(&mut db, "x IN (5, NULL)"), expected(&[1]));
+        assert_eq!(ids(&mut db, "x NOT IN (5, NULL)"), expected(&[]));
+        assert_eq!(ids(&mut db, "x NOT IN (5)"), expected(&[2]));
+        assert_eq!(
+            ids(&mut db, "x NOT IN (SELECT x FROM t WHERE id <> 2)"),
+            expected(&[])
+        );
+        // IS [NOT] DISTINCT FROM treats NULL as a value
+        assert_eq!(ids(&mut db, "x IS DISTINCT FROM 5"), expected(&[2, 3]));
+        assert_eq!(ids(&mut db, "x IS NOT DISTINCT FROM NULL"), expected(&[3]));
+        // Boolean tests
+        assert_eq!(ids(&mut db, "flag"), expected(&[1]));
+        assert_eq!(ids(&mut db, "NOT flag"), expected(&[2]));
+        assert_eq!(ids(&mut db, "flag IS NOT TRUE"), expected(&[2, 3]));
+        assert_eq!(ids(&mut db, "(x > 6) IS UNKNOWN"), expected(&[3]));
+
+        // UNKNOWN is NULL when used as a value
+        let result = db.execute("SELECT x > 6, x IS NULL FROM t WHERE id = 3");
+        if let QueryResult::Rows(data) = result.unwrap() {
+            assert_eq!(data.rows, vec![vec![Value::Null, Value::Boolean(true)]]);
+        } else {
+            panic!("Expected Rows QueryResult");
+        }
+
+        // Comparing unrelated types is an error rather than a silent mismatch
+        assert!(db.execute("SELECT id FROM t WHERE flag = 1").is_err());
+    }
+
+    // Empty data directory for a durability test
+    fn temp_data_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rust-db-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn select_rows(db: &mut Database, sql: &str) -> Vec<Vec<Value>> {
+        match db.execute(sql).unwrap() {
+            QueryResult::Rows(data) => data.rows,
+            _ => panic!("Expected Rows QueryResult"),
+        }
+    }
+
+    #[test]
+    fn test_wal_replay_after_restart() {
+        let dir = temp_data_dir("wal-replay");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE users (id INT, name STRING)")
+                .unwrap();
+            db.execute("INSERT INTO users VALUES (1, 'Alice'), (2, 'Bob'), (3, 'Carol')")
+                .unwrap();
+            db.execute("UPDATE users SET name = 'Robert' WHERE id = 2")
+                .unwrap();
+            db.execute("DELETE FROM users WHERE id = 3").unwrap();
+            // Failed statements are not logged
+            assert!(db.execute("INSERT INTO users VALUES ('x', 'y')").is_err());
+            db.execute("CREATE TABLE scratch (id INT)").unwrap();
+            db.execute("DROP TABLE scratch").unwrap();
+        }
+
+        let mut db = Database::open(&dir).unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT * FROM users"),
+            vec![
+                vec![Value::Integer(1), Value::Text("Alice".to_string())],
+                vec![Value::Integer(2), Value::Text("Robert".to_string())],
+            ]
+        );
+        assert!(db.execute("SELECT * FROM scratch").is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_wal_discards_torn_record() {
+        use std::io::Write;
+
+        let dir = temp_data_dir("wal-torn");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE t (id INT)").unwrap();
+            db.execute("INSERT INTO t VALUES (1)").unwrap();
+        }
+        // Simulate a crash halfway through writing a record
+        let mut wal = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("wal.log"))
+            .unwrap();
+        wal.write_all(&[40, 0, 0, 0, 1, 2, 3, 4, b'I', b'N'])
+            .unwrap();
+        drop(wal);
+
+        {
+            let mut db = Database::open(&dir).unwrap();
+            let warnings = db.take_warnings();
+            assert_eq!(warnings.len(), 1);
+            assert!(warnings[0].starts_with("Discarded 10 bytes of incomplete records"));
+            assert_eq!(
+                select_rows(&mut db, "SELECT * FROM t"),
+                vec![vec![Value::Integer(1)]]
+            );
+            db.execute("INSERT INTO t VALUES (2)").unwrap();
+        }
+        let mut db = Database::open(&dir).unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT * FROM t"),
+            vec![vec![Value::Integer(1)], vec![Value::Integer(2)]]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_statement_that_cannot_be_logged_is_taken_back() {
+        let dir = temp_data_dir("wal-failure");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE t (id INT)").unwrap();
+            db.execute("INSERT INTO t VALUES (1)").unwrap();
+            db.execute("CREATE INDEX t_id ON t (id)").unwrap();
+            db.break_wal().unwrap();
+            assert!(db.execute("INSERT INTO t VALUES (2)").is_err());
+            assert!(db.execute("UPDATE t SET id = 3").is_err());
+            assert_eq!(
+                select_rows(&mut db, "SELECT * FROM t"),
+                vec![vec![Value::Integer(1)]]
+            );
+
+            // Schema changes are taken back as well
+            assert!(db.execute("CREATE TABLE u (id INT)").is_err());
+            assert!(db.execute("SELECT * FROM u").is_err());
+            assert!(db.execute("DROP TABLE t").is_err());
+            assert_eq!(
+                select_rows(&mut db, "SELECT * FROM t WHERE id = 1"),
+                vec![vec![Value::Integer(1)]]
+            );
+            assert!(db.execute("CREATE INDEX t_id_2 ON t (id)").is_err());
+            assert_eq!(
+                db.execute("DROP INDEX t_id_2").unwrap_err().to_string(),
+                "Index t_id_2 does not exist"
+            );
+            assert!(db.execute("DROP INDEX t_id").is_err());
+            assert_eq!(
+                db.execute("CREATE INDEX t_id ON t (id)")
+                    .unwrap_err()
+                    .to_string(),
+                "Index t_id already exists"
+            );
+        }
+        let mut db = Database::open(&dir).unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT * FROM t"),
+            vec![vec![Value::Integer(1)]]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_delete_all_that_cannot_be_logged_is_taken_back() {
+        let dir = temp_data_dir("wal-failure-delete");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE t (id INT)").unwrap();
+            db.execute("INSERT INTO t VALUES (1), (2)").unwrap();
+            db.break_wal().unwrap();
+            assert!(db.execute("DELETE FROM t").is_err());
+            assert_eq!(
+                select_rows(&mut db, "SELECT * FROM t ORDER BY id"),
+                vec![vec![Value::Integer(1)], vec![Value::Integer(2)]]
+            );
+        }
+        let mut db = Database::open(&dir).unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT * FROM t ORDER BY id"),
+            vec![vec![Value::Integer(1)], vec![Value::Integer(2)]]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_checkpoint_snapshot_and_wal() {
+        let dir = temp_data_dir("checkpoint");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE t (id INT, name VARCHAR(10), price DECIMAL(6,2))")
+                .unwrap();
+            db.execute("INSERT INTO t VALUES (1, 'one', 1.50), (2, NULL, 20)")
+                .unwrap();
+            assert!(matches!(
+                db.execute("checkpoint;").unwrap(),
+                QueryResult::Success(_)
+            ));
+            assert_eq!(std::fs::metadata(dir.join("wal.log")).unwrap().len(), 0);
+            // Logged after the snapshot, replayed on top of it
+            db.execute("DELETE FROM t WHERE id = 1").unwrap();
+            db.execute("INSERT INTO t VALUES (3, 'three', 0.25)")
+                .unwrap();
+        }
+        let mut db = Database::open(&dir).unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT * FROM t"),
+            vec![
+                vec![
+                    Value::Integer(2),
+                    Value::Null,
+                    Value::Decimal("20.00".parse().unwrap())
+                ],
+                vec![
+                    Value::Integer(3),
+                    Value::Text("three".to_string()),
+                    Value::Decimal("0.25".parse().unwrap())
+                ],
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_damaged_snapshot_is_rejected() {
+        let dir = temp_data_dir("snapshot-damaged");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE t (id INT, name VARCHAR(10))")
+                .unwrap();
+            db.execute("INSERT INTO t VALUES (1, 'one'), (2, 'two')")
+                .unwrap();
+            db.execute("CHECKPOINT").unwrap();
+        }
+        let snapshot = std::fs::read(dir.join("snapshot.db")).unwrap();
+        // A flipped bit in a row, a missing checksum and trailing garbage
+        let mut flipped = snapshot.clone();
+        let position = flipped.len() - 6;
+        flipped[position] ^= 1;
+        let mut trailing = snapshot.clone();
+        trailing.push(0);
+        for data in [flipped, snapshot[..snapshot.len() - 4].to_vec(), trailing] {
+            std::fs::write(dir.join("snapshot.db"), data).unwrap();
+            assert!(Database::open(&dir).is_err());
+        }
+        std::fs::write(dir.join("snapshot.db"), snapshot).unwrap();
+        let mut db = Database::open(&dir).unwrap();
+        assert_eq!(select_rows(&mut db, "SELECT id FROM t").len(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_automatic_checkpoint() {
+        let dir = temp_data_dir("auto-checkpoint");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.set_checkpoint_interval(3);
+            db.execute("CREATE TABLE t (id INT)").unwrap();
+            db.execute("INSERT INTO t VALUES (1)").unwrap();
+            assert!(!dir.join("snapshot.db").exists());
+            db.execute("INSERT INTO t VALUES (2)").unwrap();
+            assert!(dir.join("snapshot.db").exists());
+            assert_eq!(std::fs::metadata(dir.join("wal.log")).unwrap().len(), 0);
+            db.execute("INSERT INTO t VALUES (3)").unwrap();
+        }
+        let mut db = Database::open(&dir).unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT * FROM t"),
+            vec![
+                vec![Value::Integer(1)],
+                vec![Value::Integer(2)],
+                vec![Value::Integer(3)]
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_failed_automatic_checkpoint_is_retried() {
+        let dir = temp_data_dir("checkpoint-retry");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.set_checkpoint_interval(2);
+            // The snapshot cannot be written while a directory is in the way
+            std::fs::create_dir_all(dir.join("snapshot.db.tmp")).unwrap();
+            db.execute("CREATE TABLE t (id INT)").unwrap();
+            assert!(db.take_warnings().is_empty());
+            db.execute("INSERT INTO t VALUES (1)").unwrap();
+            assert!(!dir.join("snapshot.db").exists());
+            let warnings = db.take_warnings();
+            assert_eq!(warnings.len(), 1);
+            assert!(warnings[0].starts_with("Automatic checkpoint failed: "));
+            std::fs::remove_dir(dir.join("snapshot.db.tmp")).unwrap();
+            db.execute("INSERT INTO t VALUES (2)").unwrap();
+            assert!(!dir.join("snapshot.db").exists());
+            db.execute("INSERT INTO t VALUES (3)").unwrap();
+            assert!(dir.join("snapshot.db").exists());
+            assert!(db.take_warnings().is_empty());
+            assert_eq!(std::fs::metadata(dir.join("wal.log")).unwrap().len(), 0);
+        }
+        let mut db = Database::open(&dir).unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT * FROM t"),
+            vec![
+                vec![Value::Integer(1)],
+                vec![Value::Integer(2)],
+                vec![Value::Integer(3)]
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_checkpoint_requires_data_directory() {
+        let mut db = Database::new();
+        assert!(db.execute("CHECKPOINT").is_err());
+    }
+
+    #[test]
+    fn test_paged_storage() {
+        let dir = temp_data_dir("paged");
+        let storage = Storage::Paged { buffer_pages: 2 };
+        let padding = "x".repeat(60);
+        {
+            let mut db = Database::open_with(&dir, storage).unwrap();
+            db.execute("CREATE TABLE t (id INT, name VARCHAR(200))")
+                .unwrap();
+            for id in 1..=300 {
+                db.execute(&format!("INSERT INTO t VALUES ({}, '{}')", id, padding))
+                    .unwrap();
+            }
+            // The rows span more pages than the buffer pool holds, the rest went to the file
+            let pages = std::fs::metadata(dir.join("pages").join("t.pages"))
+                .unwrap()
+                .len();
+            assert!(pages >= 4 * 4096);
+
+            // Growing rows no longer fit into their page and move
+            db.execute(&format!(
+                "UPDATE t SET name = '{}' WHERE id <= 50",
+                "y".repeat(150)
+            ))
+            .unwrap();
+            db.execute("DELETE FROM t WHERE id % 3 = 0").unwrap();
+            assert_eq!(
+                select_rows(&mut db, "SELECT COUNT(*), SUM(id) FROM t"),
+                vec![vec![Value::Integer(200), Value::Integer(30000)]]
+            );
+            assert_eq!(
+                select_rows(&mut db, "SELECT LENGTH(name) FROM t WHERE id = 49"),
+                vec![vec![Value::Integer(150)]]
+            );
+            db.execute("CHECKPOINT").unwrap();
+            db.execute("DELETE FROM t WHERE id > 100").unwrap();
+        }
+        let mut db = Database::open_with(&dir, storage).unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT COUNT(*), MAX(id) FROM t"),
+            vec![vec![Value::Integer(67), Value::Integer(100)]]
+        );
+        assert_eq!(
+            select_rows(&mut db, "SELECT id FROM t ORDER BY id LIMIT 3"),
+            vec![
+                vec![Value::Integer(1)],
+                vec![Value::Integer(2)],
+                vec![Value::Integer(4)]
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_paged_storage_reuses_free_space() {
+        let dir = temp_data_dir("paged-free-space");
+        let mut db = Database::open_with(&dir, Storage::Paged { buffer_pages: 1 }).unwrap();
+        db.execute("CREATE TABLE t (id INT, name VARCHAR(200))")
+            .unwrap();
+        let padding = "x".repeat(60);
+        let insert = |db: &mut Database, ids: std::ops::RangeInclusive<i64>| {
+            for id in ids {
+                db.execute(&format!("INSERT INTO t VALUES ({}, '{}')", id, padding))
+                    .unwrap();
+            }
+        };
+        let file_size = || {
+            std::fs::metadata(dir.join("pages").join("t.pages"))
+                .unwrap()
+                .len()
+        };
+        insert(&mut db, 1..=300);
+        let size = file_size();
+        // The new rows go into the pages the deleted ones left room in, and their slots
+        db.execute("DELETE FROM t WHERE id % 2 = 0").unwrap();
+        insert(&mut db, 301..=450);
+        // Only the last page may not have been written out before
+        assert!(file_size() <= size + 4096);
+        assert_eq!(
+            select_rows(&mut db, "SELECT COUNT(*), MAX(id) FROM t"),
+            vec![vec![Value::Integer(300), Value::Integer(450)]]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_paged_storage_rejects_oversized_rows() {
+        let dir = temp_data_dir("paged-large");
+        let mut db = Database::open_with(&dir, Storage::Paged { buffer_pages: 1 }).unwrap();
+        db.execute("CREATE TABLE t (name TEXT)").unwrap();
+        let result = db.execute(&format!("INSERT INTO t VALUES ('{}')", "z".repeat(5000)));
+        assert!(result.is_err());
+        assert_eq!(
+            select_rows(&mut db, "SELECT * FROM t"),
+            Vec::<Vec<Value>>::new()
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_storage_engine_per_table() {
+        let dir = temp_data_dir("engines");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            for engine in ["memory", "PAGED", "log"] {
+                let table = format!("t_{}", engine.to_lowercase());
+                db.execute(&format!(
+                    "CREATE TABLE {} (id INT, name TEXT) ENGINE = {}",
+                    table, engine
+                ))
+                .unwrap();
+                db.execute(&format!(
+                    "INSERT INTO {} VALUES (1, 'a'), (2, 'b'), (3, 'c')",
+                    table
+                ))
+                .unwrap();
+                db.execute(&format!("UPDATE {} SET name = 'bb' WHERE id = 2", table))
+                    .unwrap();
+                db.execute(&format!("DELETE FROM {} WHERE id = 1", table))
+                    .unwrap();
+            }
+            assert!(dir.join("pages").join("t_paged.pages").exists());
+            assert!(dir.join("logs").join("t_log.log").exists());
+            db.execute("CHECKPOINT").unwrap();
+        }
+        let mut db = Database::open(&dir).unwrap();
+        // The snapshot remembers the engine of every table
+        assert!(dir.join("logs").join("t_log.log").exists());
+        for table in ["t_memory", "t_paged", "t_log"] {
+            assert_eq!(
+                select_rows(&mut db, &format!("SELECT * FROM {}", table)),
+                vec![
+                    vec![Value::Integer(2), Value::Text("bb".to_string())],
+                    vec![Value::Integer(3), Value::Text("c".to_string())]
+                ]
+            );
+        }
+        db.execute("DROP TABLE t_log").unwrap();
+        assert!(!dir.join("logs").join("t_log.log").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_storage_engine_errors() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE m (id INT) ENGINE = memory")
+            .unwrap();
+        let result = db.execute("CREATE TABLE p (id INT) ENGINE = paged");
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("requires a data directory"));
+        let result = db.execute("CREATE TABLE i (id INT) ENGINE = InnoDB");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Unknown storage engine 'innodb'"
+        );
+    }
+
+    fn setup_indexed_items(db: &mut Database) {
+        db.execute("CREATE TABLE items (id INT, name VARCHAR(20), price DECIMAL(6,2))")
+            .unwrap();
+        for id in 1..=20 {
+            db.execute(&format!(
+                "INSERT INTO items VALUES ({}, 'item{}', {}.50)",
+                id,
+                id % 7,
+                id
+            ))
+            .unwrap();
+        }
+        db.execute("INSERT INTO items VALUES (NULL, 'none', NULL)")
+            .unwrap();
+        db.execute("CREATE INDEX items_id ON items (id)").unwrap();
+        db.execute("CREATE INDEX items_price ON items (price)")
+            .unwrap();
+    }
+
+    fn item_ids(db: &mut Database, condition: &str) -> Vec<i64> {
+        select_rows(db, &format!("SELECT id FROM items WHERE {}", condition))
+            .into_iter()
+            .map(|row| match row[0] {
+                Value::Integer(id) => id,
+                ref other => panic!("Unexpected id {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_index_lookups() {
+        let mut db = Database::new();
+        setup_indexed_items(&mut db);
+
+        assert_eq!(item_ids(&mut db, "id = 7"), vec![7]);
+        assert_eq!(item_ids(&mut db, "3 > id"), vec![1, 2]);
+        assert_eq!(item_ids(&mut db, "id <= 3"), vec![1, 2, 3]);
+        assert_eq!(item_ids(&mut db, "id > 18"), vec![19, 20]);
+        assert_eq!(item_ids(&mut db, "id >= 19"), vec![19, 20]);
+        assert_eq!(item_ids(&mut db, "id BETWEEN 4 AND 6"), vec![4, 5, 6]);
+        // Rows come back in storage order, like without the index
+        assert_eq!(item_ids(&mut db, "id IN (9, 2, 5, 2)"), vec![2, 5, 9]);
+        assert_eq!(item_ids(&mut db, "id < 10 AND name = 'item1'"), vec![1, 8]);
+        assert_eq!(item_ids(&mut db, "(id = 3 OR id = 4) AND id > 3"), vec![4]);
+        assert_eq!(item_ids(&mut db, "price = 12.5"), vec![12]);
+        assert_eq!(item_ids(&mut db, "price < 3"), vec![1, 2]);
+        // A constant that the column type cannot hold exactly falls back to a scan
+        assert_eq!(item_ids(&mut db, "id < 2.5"), vec![1, 2]);
+        assert_eq!(item_ids(&mut db, "id = 2.5"), Vec::<i64>::new());
+        assert_eq!(item_ids(&mut db, "id = NULL"), Vec::<i64>::new());
+        assert_eq!(item_ids(&mut db, "id = 1 + 1"), vec![2]);
+
+        // Changes keep the indexes up to date
+        db.execute("UPDATE items SET id = id + 100 WHERE id BETWEEN 1 AND 3")
+            .unwrap();
+        assert_eq!(item_ids(&mut db, "id <= 3"), Vec::<i64>::new());
+        assert_eq!(item_ids(&mut db, "id > 100"), vec![101, 102, 103]);
+        db.execute("DELETE FROM items WHERE id IN (101, 5)")
+            .unwrap();
+        assert_eq!(item_ids(&mut db, "id IN (101, 5, 6)"), vec![6]);
+        db.execute("DELETE FROM items").unwrap();
+        assert_eq!(item_ids(&mut db, "id > 0"), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn test_unique_index() {
+        let mut db = Database::new();
+        setup_indexed_items(&mut db);
+
+        let result = db.execute("CREATE UNIQUE INDEX items_name ON items (name)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Duplicate entry 'item1' for key 'items_name'"
+        );
+        db.execute("CREATE UNIQUE INDEX items_key ON items (id, name)")
+            .unwrap();
+        let result = db.execute("INSERT INTO items VALUES (4, 'item4', 1.00)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Duplicate entry '4-item4' for key 'items_key'"
+        );
+        // NULL never collides
+        db.execute("INSERT INTO items VALUES (NULL, 'none', NULL)")
+            .unwrap();
+
+        let result = db.execute("UPDATE items SET id = 4 WHERE id = 11");
+        assert!(result.is_err());
+        assert_eq!(item_ids(&mut db, "name = 'item4'"), vec![4, 11, 18]);
+        // Swapping keys is fine, only the final rows are checked
+        db.execute("UPDATE items SET id = 15 - id WHERE id IN (4, 11)")
+            .unwrap();
+        assert_eq!(item_ids(&mut db, "name = 'item4'"), vec![11, 4, 18]);
+    }
+
+    #[test]
+    fn test_create_and_drop_index_errors() {
+        let mut db = Database::new();
+        setup_indexed_items(&mut db);
+
+        assert!(db.execute("CREATE INDEX items_id ON items (name)").is_err());
+        db.execute("CREATE INDEX IF NOT EXISTS items_id ON items (name)")
+            .unwrap();
+        assert_eq!(
+            db.execute("CREATE INDEX bad ON items (missing)")
+                .unwrap_err()
+                .to_string(),
+            "Column 'missing' not found"
+        );
+        assert!(db.execute("CREATE INDEX bad ON missing (id)").is_err());
+        assert!(db
+            .execute("CREATE INDEX bad ON items USING gist (id)")
+            .is_err());
+
+        db.execute("DROP INDEX items_id").unwrap();
+        assert!(db.execute("DROP INDEX items_id").is_err());
+        db.execute("DROP INDEX IF EXISTS items_id").unwrap();
+        assert_eq!(item_ids(&mut db, "id = 7"), vec![7]);
+    }
+
+    #[test]
+    fn test_index_survives_restart() {
+        let dir = temp_data_dir("index");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE t (id INT) ENGINE = paged")
+                .unwrap();
+            db.execute("INSERT INTO t VALUES (1), (2), (3)").unwrap();
+            db.execute("CREATE UNIQUE INDEX t_id ON t (id)").unwrap();
+            db.execute("CHECKPOINT").unwrap();
+            db.execute("CREATE INDEX t_id_2 ON t (id)").unwrap();
+        }
+        let mut db = Database::open(&dir).unwrap();
+        assert!(db.execute("INSERT INTO t VALUES (2)").is_err());
+        assert!(db.execute("CREATE INDEX t_id_2 ON t (id)").is_err());
+        assert_eq!(
+            select_rows(&mut db, "SELECT id FROM t WHERE id >= 2"),
+            vec![vec![Value::Integer(2)], vec![Value::Integer(3)]]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_index_lookups_in_every_engine() {
+        let dir = temp_data_dir("index-engines");
+        let mut db = Database::open(&dir).unwrap();
+        let padding = "x".repeat(60);
+        for engine in ["memory", "paged", "log"] {
+            let table = format!("t_{}", engine);
+            db.execute(&format!(
+                "CREATE TABLE {} (id INT, name TEXT) ENGINE = {}",
+                table, engine
+            ))
+            .unwrap();
+            // Enough rows for several pages
+            for id in 1..=200 {
+                db.execute(&format!(
+                    "INSERT INTO {} VALUES ({}, '{}')",
+                    table, id, padding
+                ))
+                .unwrap();
+            }
+            db.execute(&format!("CREATE INDEX {0}_id ON {0} (id)", table))
+                .unwrap();
+            assert_eq!(
+                select_rows(
+                    &mut db,
+                    &format!("SELECT id FROM {} WHERE id IN (150, 3, 77, 3)", table)
+                ),
+                ids(&[3, 77, 150])
+            );
+            assert_eq!(
+                select_rows(
+                    &mut db,
+                    &format!(
+                        "SELECT a.id FROM t_memory a JOIN {} b ON b.id = a.id \
+                         WHERE a.id <= 2",
+                        table
+                    )
+                ),
+                ids(&[1, 2])
+            );
+        }
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_hash_index_lookups() {
+        let mut db = Database::new();
+        setup_indexed_items(&mut db);
+        db.execute("CREATE INDEX items_name ON items USING HASH (name)")
+            .unwrap();
+        db.execute("CREATE INDEX items_id_hash ON items USING HASH (id)")
+            .unwrap();
+
+        assert_eq!(item_ids(&mut db, "id = 7"), vec![7]);
+        assert_eq!(item_ids(&mut db, "id IN (9, 2, 40)"), vec![2, 9]);
+        assert_eq!(item_ids(&mut db, "name = 'item3'"), vec![3, 10, 17]);
+        assert_eq!(
+            item_ids(&mut db, "name IN ('item1', 'item6')"),
+            vec![1, 6, 8, 13, 15, 20]
+        );
+        // Ranges cannot use a hash index but still find the rows
+        assert_eq!(item_ids(&mut db, "name < 'item1'"), vec![7, 14]);
+        assert_eq!(item_ids(&mut db, "id BETWEEN 4 AND 6"), vec![4, 5, 6]);
+
+        db.execute("UPDATE items SET name = 'renamed' WHERE id = 3")
+            .unwrap();
+        db.execute("DELETE FROM items WHERE id = 10").unwrap();
+        assert_eq!(item_ids(&mut db, "name = 'item3'"), vec![17]);
+        assert_eq!(item_ids(&mut db, "name = 'renamed'"), vec![3]);
+
+        let result = db.execute("CREATE INDEX bad ON items USING RTREE (id)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Unsupported index type 'rtree'"
+        );
+    }
+
+    #[test]
+    fn test_unique_hash_index() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE t (code VARCHAR(10))").unwrap();
+        db.execute("CREATE UNIQUE INDEX t_code ON t USING HASH (code)")
+            .unwrap();
+        db.execute("INSERT INTO t VALUES ('a'), ('b'), (NULL), (NULL)")
+            .unwrap();
+        let result = db.execute("INSERT INTO t VALUES ('a')");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Duplicate entry 'a' for key 't_code'"
+        );
+        db.execute("UPDATE t SET code = 'c' WHERE code = 'a'")
+            .unwrap();
+        db.execute("INSERT INTO t VALUES ('a')").unwrap();
+    }
+
+    #[test]
+    fn test_index_join() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE customers (id INT, name VARCHAR(20))")
+            .unwrap();
+        db.execute("CREATE TABLE orders (id INT, customer_id INT, total INT)")
+            .unwrap();
+        db.execute(
+            "INSERT INTO customers VALUES (1, 'Alice'), (2, 'Bob'), (3, 'Carol'), (NULL, 'Nobody')",
+        )
+        .unwrap();
+        db.execute("INSERT INTO orders VALUES (10, 2, 5), (11, 1, 7), (12, 2, 9), (13, NULL, 1)")
+            .unwrap();
+        let inner = "SELECT c.name, o.id FROM customers c JOIN orders o ON c.id = o.customer_id AND o.total > 5";
+        let left =
+            "SELECT c.name, o.id FROM customers c LEFT JOIN orders o ON o.customer_id = c.id";
+        let expected_inner = select_rows(&mut db, inner);
+        let expected_left = select_rows(&mut db, left);
+
+        db.execute("CREATE INDEX orders_customer ON orders USING HASH (customer_id)")
+            .unwrap();
+        assert_eq!(select_rows(&mut db, inner), expected_inner);
+        assert_eq!(
+            expected_inner,
+            vec![
+                vec![Value::Text("Alice".to_string()), Value::Integer(11)],
+                vec![Value::Text("Bob".to_string()), Value::Integer(12)],
+            ]
+        );
+        assert_eq!(select_rows(&mut db, left), expected_left);
+        assert_eq!(
+            expected_left,
+            vec![
+                vec![Value::Text("Alice".to_string()), Value::Integer(11)],
+                vec![Value::Text("Bob".to_string()), Value::Integer(10)],
+                vec![Value::Text("Bob".to_string()), Value::Integer(12)],
+                vec![Value::Text("Carol".to_string()), Value::Null],
+                vec![Value::Text("Nobody".to_string()), Value::Null],
+            ]
+        );
+
+        // The join uses the index as rows change
+        db.execute("INSERT INTO orders VALUES (14, 3, 8)").unwrap();
+        db.execute("DELETE FROM orders WHERE id = 11").unwrap();
+        assert_eq!(
+            select_rows(&mut db, inner),
+            vec![
+                vec![Value::Text("Bob".to_string()), Value::Integer(12)],
+                vec![Value::Text("Carol".to_string()), Value::Integer(14)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hash_index_survives_restart() {
+        let dir = temp_data_dir("hash_index");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE t (id INT)").unwrap();
+            db.execute("INSERT INTO t VALUES (1), (2), (3)").unwrap();
+            db.execute("CREATE UNIQUE INDEX t_id ON t USING HASH (id)")
+                .unwrap();
+            db.execute("CHECKPOINT").unwrap();
+        }
+        let mut db = Database::open(&dir).unwrap();
+        assert!(db.execute("INSERT INTO t VALUES (2)").is_err());
+        assert_eq!(
+            select_rows(&mut db, "SELECT id FROM t WHERE id = 3 OR id > 1"),
+            vec![vec![Value::Integer(2)], vec![Value::Integer(3)]]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_column_constraints() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT PRIMARY KEY, name VARCHAR(20) NOT NULL, email VARCHAR(50) UNIQUE)")
+            .unwrap();
+        db.execute("INSERT INTO users VALUES (1, 'Alice', 'alice@example.com')")
+            .unwrap();
+        db.execute("INSERT INTO users VALUES (2, 'Bob', NULL), (3, 'Carol', NULL)")
+            .unwrap();
+
+        let result = db.execute("INSERT INTO users VALUES (1, 'Dave', NULL)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "PRIMARY KEY constraint 'PRIMARY' violated: duplicate entry '1' for column 'id'"
+        );
+        let result = db.execute("INSERT INTO users VALUES (NULL, 'Dave', NULL)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "PRIMARY KEY constraint 'PRIMARY' violated: column 'id' cannot be null"
+        );
+        let result = db.execute("INSERT INTO users VALUES (4, NULL, NULL)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "NOT NULL constraint violated: column 'name' cannot be null"
+        );
+        let result = db.execute("INSERT INTO users VALUES (4, 'Dave', 'alice@example.com')");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "UNIQUE constraint 'email' violated: duplicate entry 'alice@example.com' for column 'email'"
+        );
+
+        // Updates are checked too and leave the table untouched when they fail
+        assert!(db.execute("UPDATE users SET id = 1 WHERE id = 2").is_err());
+        assert!(db.execute("UPDATE users SET name = NULL").is_err());
+        db.execute("UPDATE users SET id = id + 10").unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT id, name FROM users WHERE id = 12"),
+            vec![vec![Value::Integer(12), Value::Text("Bob".to_string())]]
+        );
+        assert_eq!(select_rows(&mut db, "SELECT * FROM users").len(), 3);
+    }
+
+    #[test]
+    fn test_table_constraints() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE enrollments (student INT, course INT, seat INT, CONSTRAINT pk_enrollment PRIMARY KEY (student, course), CONSTRAINT uq_seat UNIQUE (course, seat))")
+            .unwrap();
+        db.execute("INSERT INTO enrollments VALUES (1, 10, 1), (1, 11, 1), (2, 10, 2)")
+            .unwrap();
+        let result = db.execute("INSERT INTO enrollments VALUES (1, 10, 5)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "PRIMARY KEY constraint 'pk_enrollment' violated: duplicate entry '1-10' for columns 'student, course'"
+        );
+        let result = db.execute("INSERT INTO enrollments VALUES (3, 10, 2)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "UNIQUE constraint 'uq_seat' violated: duplicate entry '10-2' for columns 'course, seat'"
+        );
+        let result = db.execute("INSERT INTO enrollments VALUES (3, NULL, 2)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "PRIMARY KEY constraint 'pk_enrollment' violated: column 'course' cannot be null"
+        );
+
+        let result = db.execute("CREATE TABLE t (a INT PRIMARY KEY, b INT, PRIMARY KEY (b))");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Multiple primary keys defined"
+        );
+        let result = db.execute("CREATE TABLE t (a INT, PRIMARY KEY (missing))");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Column 'missing' not found"
+        );
+        let result = db.execute("CREATE TABLE t (a INT CONSTRAINT a_required NOT NULL)");
+        assert!(result.is_ok());
+        let result = db.execute("INSERT INTO t VALUES (NULL)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "NOT NULL constraint 'a_required' violated: column 'a' cannot be null"
+        );
+    }
+
+    #[test]
+    fn test_constraints_survive_restart() {
+        let dir = temp_data_dir("constraints");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE t (id INT, code VARCHAR(5) NOT NULL, PRIMARY KEY (id))")
+                .unwrap();
+            db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+                .unwrap();
+            db.execute("CHECKPOINT").unwrap();
+            db.execute("INSERT INTO t VALUES (3, 'c')").unwrap();
+        }
+        let mut db = Database::open(&dir).unwrap();
+        assert!(db.execute("INSERT INTO t VALUES (1, 'x')").is_err());
+        assert!(db.execute("INSERT INTO t VALUES (3, 'x')").is_err());
+        assert!(db.execute("INSERT INTO t VALUES (4, NULL)").is_err());
+        // Constraint indexes are not visible to DROP INDEX
+        let result = db.execute("DROP INDEX PRIMARY");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Index PRIMARY does not exist"
+        );
+        db.execute("INSERT INTO t VALUES (4, 'd')").unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn setup_departments(db: &mut Database, references: &str) {
+        db.execute("CREATE TABLE departments (id INT PRIMARY KEY, name VARCHAR(20))")
+            .unwrap();
+        db.execute(&format!(
+            "CREATE TABLE employees (id INT PRIMARY KEY, name VARCHAR(20), department_id INT {})",
+            references
+        ))
+        .unwrap();
+        db.execute("INSERT INTO departments VALUES (0, 'Unassigned'), (1, 'Engineering'), (2, 'Sales'), (3, 'HR')")
+            .unwrap();
+        db.execute("INSERT INTO employees VALUES (1, 'Alice', 1), (2, 'Bob', 2), (3, 'Carol', 1), (4, 'Dave', NULL)")
+            .unwrap();
+    }
+
+    fn employee_departments(db: &mut Database) -> Vec<Vec<Value>> {
+        select_rows(db, "SELECT id, department_id FROM employees")
+    }
+
+    #[test]
+    fn test_foreign_key_restrict() {
+        let mut db = Database::new();
+        setup_departments(&mut db, "REFERENCES departments (id)");
+
+        let result = db.execute("INSERT INTO employees VALUES (5, 'Eve', 9)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "FOREIGN KEY constraint 'employees_ibfk_1' violated: no row in 'departments' matches '9' for column 'department_id'"
+        );
+        let result = db.execute("UPDATE employees SET department_id = 9 WHERE id = 1");
+        assert!(result.is_err());
+
+        let result = db.execute("DELETE FROM departments WHERE id = 2");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "FOREIGN KEY constraint 'employees_ibfk_1' violated: rows in 'employees' still reference '2' through column 'department_id'"
+        );
+        assert!(db
+            .execute("UPDATE departments SET id = 20 WHERE id = 2")
+            .is_err());
+        assert!(db.execute("DELETE FROM departments").is_err());
+        let result = db.execute("DROP TABLE departments");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Cannot drop table 'departments' referenced by FOREIGN KEY constraint 'employees_ibfk_1' of 'employees'"
+        );
+        assert_eq!(select_rows(&mut db, "SELECT * FROM departments").len(), 4);
+
+        // Rows nobody references can go, and a referencing row can move to another parent
+        db.execute("DELETE FROM departments WHERE id = 3").unwrap();
+        db.execute("UPDATE employees SET department_id = 1 WHERE id = 2")
+            .unwrap();
+        db.execute("DELETE FROM departments WHERE id = 2").unwrap();
+        db.execute("DELETE FROM employees").unwrap();
+        db.execute("DROP TABLE employees").unwrap();
+        db.execute("DROP TABLE departments").unwrap();
+    }
+
+    #[test]
+    fn test_foreign_key_cascade() {
+        let mut db = Database::new();
+        setup_departments(
+            &mut db,
+            ", CONSTRAINT fk_department FOREIGN KEY (department_id) REFERENCES departments (id) ON DELETE CASCADE ON UPDATE CASCADE",
+        );
+        db.execute("CREATE TABLE badges (employee_id INT REFERENCES employees ON DELETE CASCADE, code VARCHAR(5))")
+            .unwrap();
+        db.execute("INSERT INTO badges VALUES (1, 'A1'), (2, 'B1'), (3, 'C1')")
+            .unwrap();
+
+        db.execute("UPDATE departments SET id = 10 WHERE id = 1")
+            .unwrap();
+        assert_eq!(
+            employee_departments(&mut db),
+            vec![
+                vec![Value::Integer(1), Value::Integer(10)],
+                vec![Value::Integer(2), Value::Integer(2)],
+                vec![Value::Integer(3), Value::Integer(10)],
+                vec![Value::Integer(4), Value::Null],
+            ]
+        );
+
+        // Deleting a department deletes its employees and, through them, their badges
+        let result = db.execute("DELETE FROM departments WHERE id = 10").unwrap();
+        assert!(matches!(result, QueryResult::Success(message) if message == "Deleted 1 row(s)"));
+        assert_eq!(
+            employee_departments(&mut db),
+            vec![
+                vec![Value::Integer(2), Value::Integer(2)],
+                vec![Value::Integer(4), Value::Null],
+            ]
+        );
+        assert_eq!(
+            select_rows(&mut db, "SELECT code FROM badges"),
+            vec![vec![Value::Text("B1".to_string())]]
+        );
+    }
+
+    #[test]
+    fn test_foreign_key_set_null_and_default() {
+        let mut db = Database::new();
+        setup_departments(
+            &mut db,
+            "DEFAULT 0 REFERENCES departments (id) ON DELETE SET NULL ON UPDATE SET DEFAULT",
+        );
+        db.execute("UPDATE departments SET id = 20 WHERE id = 2")
+            .unwrap();
+        db.execute("DELETE FROM departments WHERE id = 1").unwrap();
+        assert_eq!(
+            employee_departments(&mut db),
+            vec![
+                vec![Value::Integer(1), Value::Null],
+                vec![Value::Integer(2), Value::Integer(0)],
+                vec![Value::Integer(3), Value::Null],
+                vec![Value::Integer(4), Value::Null],
+            ]
+        );
+        // The default has to reference a row too
+        let result = db.execute("UPDATE departments SET id = 30 WHERE id = 0");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "FOREIGN KEY constraint 'employees_ibfk_1' violated: no row in 'departments' matches '0' for column 'department_id'"
+        );
+
+        // SET NULL cannot break a NOT NULL column, and a failed action changes nothing
+        db.execute("CREATE TABLE managers (department_id INT NOT NULL REFERENCES departments (id) ON DELETE SET NULL)")
+            .unwrap();
+        db.execute("INSERT INTO managers VALUES (3)").unwrap();
+        let result = db.execute("DELETE FROM departments WHERE id = 3");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "NOT NULL constraint violated: column 'department_id' cannot be null"
+        );
+        assert_eq!(select_rows(&mut db, "SELECT * FROM departments").len(), 3);
+    }
+
+    #[test]
+    fn test_self_referencing_foreign_key() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE nodes (id INT PRIMARY KEY, parent_id INT, FOREIGN KEY (parent_id) REFERENCES nodes (id) ON DELETE CASCADE)")
+            .unwrap();
+        db.execute("INSERT INTO nodes VALUES (1, NULL), (2, 1), (3, 2), (4, 4), (5, NULL)")
+            .unwrap();
+        assert!(db.execute("INSERT INTO nodes VALUES (6, 7)").is_err());
+        db.execute("DELETE FROM nodes WHERE id = 1").unwrap();
+        db.execute("DELETE FROM nodes WHERE id = 4").unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT id FROM nodes"),
+            vec![vec![Value::Integer(5)]]
+        );
+    }
+
+    #[test]
+    fn test_foreign_key_definition_errors() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE parents (id INT, code INT UNIQUE)")
+            .unwrap();
+        let result = db.execute("CREATE TABLE c (p INT REFERENCES missing (id))");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "FOREIGN KEY constraint 'c_ibfk_1' references unknown table 'missing'"
+        );
+        let result = db.execute("CREATE TABLE c (p INT REFERENCES parents (id))");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "FOREIGN KEY constraint 'c_ibfk_1' must reference a PRIMARY KEY or UNIQUE key of 'parents'"
+        );
+        let result = db.execute("CREATE TABLE c (p INT REFERENCES parents)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "FOREIGN KEY constraint 'c_ibfk_1' references table 'parents' which has no primary key"
+        );
+        db.execute(
+            "CREATE TABLE c (p INT, CONSTRAINT fk_code FOREIGN KEY (p) REFERENCES parents (code))",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_check_constraints() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE products (id INT, price DECIMAL(6,2) CHECK (price > 0), stock INT, low INT, high INT, CONSTRAINT valid_range CHECK (low <= high))")
+            .unwrap();
+        db.execute("INSERT INTO products VALUES (1, 9.99, 5, 1, 10)")
+            .unwrap();
+        // An unknown result lets the row in
+        db.execute("INSERT INTO products VALUES (2, NULL, 5, NULL, 10)")
+            .unwrap();
+
+        let result = db.execute("INSERT INTO products VALUES (3, 0, 5, 1, 10)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "CHECK constraint 'products_chk_1' violated: price > 0 is false for column 'price' value '0.00'"
+        );
+        let result = db.execute("INSERT INTO products VALUES (3, 1.50, 5, 10, 1)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "CHECK constraint 'valid_range' violated: low <= high is false"
+        );
+        let result = db.execute("UPDATE products SET high = 0 WHERE id = 1");
+        assert!(result.is_err());
+        db.execute("UPDATE products SET low = 20, high = 30 WHERE id = 1")
+            .unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT low, high FROM products WHERE id = 1"),
+            vec![vec![Value::Integer(20), Value::Integer(30)]]
+        );
+
+        let result = db.execute("CREATE TABLE t (a INT CHECK (b > 0))");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Invalid CHECK constraint 't_chk_1': Column 'b' not found"
+        );
+    }
+
+    #[test]
+    fn test_default_values() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE tasks (id INT, title VARCHAR(20) DEFAULT 'untitled', priority INT DEFAULT 3, done BOOLEAN DEFAULT FALSE, note TEXT)")
+            .unwrap();
+        db.execute("INSERT INTO tasks VALUES (1, DEFAULT, DEFAULT, DEFAULT, DEFAULT)")
+            .unwrap();
+        db.execute("INSERT INTO tasks VALUES (2, 'Write', 1, TRUE, 'soon')")
+            .unwrap();
+        db.execute("UPDATE tasks SET priority = DEFAULT, note = DEFAULT WHERE id = 2")
+            .unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT * FROM tasks"),
+            vec![
+                vec![
+                    Value::Integer(1),
+                    Value::Text("untitled".to_string()),
+                    Value::Integer(3),
+                    Value::Boolean(false),
+                    Value::Null
+                ],
+                vec![
+                    Value::Integer(2),
+                    Value::Text("Write".to_string()),
+                    Value::Integer(3),
+                    Value::Boolean(true),
+                    Value::Null
+                ],
+            ]
+        );
+
+        let result = db.execute("CREATE TABLE t (a INT DEFAULT 'abc')");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Invalid DEFAULT value for column 'a': Type mismatch for column 'a'"
+        );
+        // Defaults have to pass the other constraints of their column
+        db.execute("CREATE TABLE t (a INT DEFAULT 0 CHECK (a > 0), b INT NOT NULL)")
+            .unwrap();
+        assert!(db.execute("INSERT INTO t VALUES (DEFAULT, 1)").is_err());
+        assert!(db.execute("INSERT INTO t VALUES (1, DEFAULT)").is_err());
+    }
+
+    #[test]
+    fn test_insert_column_list() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT, name VARCHAR(20) NOT NULL, role VARCHAR(10) DEFAULT 'member', age INT)")
+            .unwrap();
+        db.execute("INSERT INTO users (name, id) VALUES ('Alice', 1), ('Bob', 2)")
+            .unwrap();
+        db.execute("INSERT INTO users (age, role, name, id) VALUES (40, 'admin', 'Carol', 3)")
+            .unwrap();
+        db.execute("INSERT INTO users (id, name, role) VALUES (4, 'Dave', DEFAULT)")
+            .unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT * FROM users"),
+            vec![
+                vec![
+                    Value::Integer(1),
+                    Value::Text("Alice".to_string()),
+                    Value::Text("member".to_string()),
+                    Value::Null
+                ],
+                vec![
+                    Value::Integer(2),
+                    Value::Text("Bob".to_string()),
+                    Value::Text("member".to_string()),
+                    Value::Null
+                ],
+                vec![
+                    Value::Integer(3),
+                    Value::Text("Carol".to_string()),
+                    Value::Text("admin".to_string()),
+                    Value::Integer(40)
+                ],
+                vec![
+                    Value::Integer(4),
+                    Value::Text("Dave".to_string()),
+                    Value::Text("member".to_string()),
+                    Value::Null
+                ],
+            ]
+        );
+
+        let result = db.execute("INSERT INTO users (id, nickname) VALUES (5, 'E')");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Unknown column 'nickname' in table 'users'"
+        );
+        let result = db.execute("INSERT INTO users (id, name, id) VALUES (5, 'Eve', 6)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Column 'id' specified twice"
+        );
+        let result = db.execute("INSERT INTO users (id, name) VALUES (5)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Number of values doesn't match number of columns"
+        );
+        // An omitted column without a default is NULL, which NOT NULL rejects
+        let result = db.execute("INSERT INTO users (id) VALUES (5)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "NOT NULL constraint violated: column 'name' cannot be null"
+        );
+        assert_eq!(select_rows(&mut db, "SELECT * FROM users").len(), 4);
+    }
+
+    #[test]
+    fn test_insert_expression_values() {
+        let mut db = Database::new();
+        db.execute(
+            "CREATE TABLE readings (id INT, delta INT, label VARCHAR(20), ratio DECIMAL(5,2))",
+        )
+        .unwrap();
+        db.execute("INSERT INTO readings VALUES (1 + 1, -5, UPPER('low'), 1 / 4)")
+            .unwrap();
+        db.execute("INSERT INTO readings VALUES (CAST('7' AS INT), -(2 * 3), CONCAT('id-', 7), CAST('2.5' AS DECIMAL(5,2)))")
+            .unwrap();
+        db.execute("INSERT INTO readings (id, label) VALUES ((SELECT MAX(id) + 1 FROM readings), CAST(42 AS VARCHAR(10)))")
+            .unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT id, delta, label FROM readings"),
+            vec![
+                vec![
+                    Value::Integer(2),
+                    Value::Integer(-5),
+                    Value::Text("LOW".to_string())
+                ],
+                vec![
+                    Value::Integer(7),
+                    Value::Integer(-6),
+                    Value::Text("id-7".to_string())
+                ],
+                vec![
+                    Value::Integer(8),
+                    Value::Null,
+                    Value::Text("42".to_string())
+                ],
+            ]
+        );
+        assert_eq!(
+            select_rows(
+                &mut db,
+                "SELECT id FROM readings WHERE ratio = 0.25 OR ratio = 2.5"
+            ),
+            vec![vec![Value::Integer(2)], vec![Value::Integer(7)]]
+        );
+
+        let result = db.execute("INSERT INTO readings VALUES (CAST('seven' AS INT), 0, 'x', 0)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Cannot cast 'seven' to INT"
+        );
+        // Values cannot refer to columns
+        let result = db.execute("INSERT INTO readings VALUES (id, 0, 'x', 0)");
+        assert_eq!(result.unwrap_err().to_string(), "Column 'id' not found");
+    }
+
+    #[test]
+    fn test_insert_select() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT, name VARCHAR(20), age INT)")
+            .unwrap();
+        db.execute("INSERT INTO users VALUES (1, 'Alice', 30), (2, 'Bob', 17), (3, 'Carol', 45)")
+            .unwrap();
+        db.execute(
+            "CREATE TABLE adults (name VARCHAR(20), id INT, note VARCHAR(10) DEFAULT 'copied')",
+        )
+        .unwrap();
+
+        let result = db
+            .execute("INSERT INTO adults (id, name) SELECT id, UPPER(name) FROM users WHERE age >= 18 ORDER BY id DESC")
+            .unwrap();
+        assert!(matches!(result, QueryResult::Success(message) if message == "Inserted 2 row(s)"));
+        assert_eq!(
+            select_rows(&mut db, "SELECT * FROM adults"),
+            vec![
+                vec![
+                    Value::Text("CAROL".to_string()),
+                    Value::Integer(3),
+                    Value::Text("copied".to_string())
+                ],
+                vec![
+                    Value::Text("ALICE".to_string()),
+                    Value::Integer(1),
+                    Value::Text("copied".to_string())
+                ],
+            ]
+        );
+
+        // A table can be copied into itself, the query is read before anything is inserted
+        db.execute("INSERT INTO users SELECT id + 10, name, age FROM users")
+            .unwrap();
+        assert_eq!(select_rows(&mut db, "SELECT * FROM users").len(), 6);
+
+        let result = db.execute("INSERT INTO adults SELECT id, name FROM users");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Row 1 of 6: Number of values doesn't match number of columns"
+        );
+        let result = db.execute("INSERT INTO adults (id, name) SELECT name, id FROM users");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Row 1 of 6: Type mismatch for column 'id'"
+        );
+    }
+
+    #[test]
+    fn test_multi_row_insert_is_atomic() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT PRIMARY KEY, name VARCHAR(20))")
+            .unwrap();
+        db.execute("CREATE INDEX users_name ON users (name)")
+            .unwrap();
+        db.execute("INSERT INTO users VALUES (1, 'Alice')").unwrap();
+
+        let result = db.execute("INSERT INTO users VALUES (2, 'Bob'), (3, 'Carol'), ('x', 'Dave')");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Row 3 of 3: Type mismatch for column 'id'"
+        );
+        // Rows of the same statement count for unique keys
+        let result = db.execute("INSERT INTO users VALUES (2, 'Bob'), (3, 'Carol'), (2, 'Dave')");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Row 3 of 3: PRIMARY KEY constraint 'PRIMARY' violated: duplicate entry '2' for column 'id'"
+        );
+        let result = db.execute("INSERT INTO users VALUES (2, 'Bob'), (1, 'Eve')");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Row 2 of 2: PRIMARY KEY constraint 'PRIMARY' violated: duplicate entry '1' for column 'id'"
+        );
+
+        // Nothing of the failed statements is left, in the table or in its indexes
+        assert_eq!(
+            select_rows(&mut db, "SELECT * FROM users"),
+            vec![vec![Value::Integer(1), Value::Text("Alice".to_string())]]
+        );
+        assert!(select_rows(&mut db, "SELECT id FROM users WHERE name = 'Bob'").is_empty());
+        db.execute("INSERT INTO users VALUES (2, 'Bob'), (3, 'Carol')")
+            .unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT id FROM users WHERE name = 'Bob'"),
+            vec![vec![Value::Integer(2)]]
+        );
+    }
+
+    #[test]
+    fn test_failed_insert_is_not_logged() {
+        let dir = temp_data_dir("atomic_insert");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE t (id INT NOT NULL) ENGINE = log")
+                .unwrap();
+            assert!(db.execute("INSERT INTO t VALUES (1), (2), (NULL)").is_err());
+            db.execute("INSERT INTO t VALUES (3)").unwrap();
+        }
+        let mut db = Database::open(&dir).unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT id FROM t"),
+            vec![vec![Value::Integer(3)]]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn upsert_counts(db: &mut Database, sql: &str) -> (usize, usize) {
+        match db.execute(sql).unwrap() {
+            QueryResult::Upserted { inserted, updated } => (inserted, updated),
+            _ => panic!("Expected Upserted QueryResult"),
+        }
+    }
+
+    fn setup_visits(db: &mut Database) {
+        db.execute(
+            "CREATE TABLE visits (id INT PRIMARY KEY, email VARCHAR(30) UNIQUE, name VARCHAR(20), count INT DEFAULT 0 CHECK (count < 10))",
+        )
+        .unwrap();
+        db.execute("INSERT INTO visits VALUES (1, 'a@x', 'Alice', 1), (2, 'b@x', 'Bob', 1)")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_on_duplicate_key_update() {
+        let mut db = Database::new();
+        setup_visits(&mut db);
+
+        let counts = upsert_counts(
+            &mut db,
+            "INSERT INTO visits VALUES (1, 'a@x', 'Alice', 2), (3, 'c@x', 'Carol', 1) ON DUPLICATE KEY UPDATE count = count + VALUES(count)",
+        );
+        assert_eq!(counts, (1, 1));
+        // Any unique key counts, the row holding it is updated
+        let counts = upsert_counts(
+            &mut db,
+            "INSERT INTO visits (id, email, name) VALUES (9, 'b@x', 'Bobby') ON DUPLICATE KEY UPDATE name = VALUES(name)",
+        );
+        assert_eq!(counts, (0, 1));
+        // Rows of the same statement run into each other
+        let counts = upsert_counts(
+            &mut db,
+            "INSERT INTO visits VALUES (4, 'd@x', 'Dave', 1), (4, 'd@x', 'Dave', 1) ON DUPLICATE KEY UPDATE count = count + 1",
+        );
+        assert_eq!(counts, (1, 1));
+        assert_eq!(
+            select_rows(&mut db, "SELECT id, name, count FROM visits ORDER BY id"),
+            vec![
+                vec![
+                    Value::Integer(1),
+                    Value::Text("Alice".to_string()),
+                    Value::Integer(3)
+                ],
+                vec![
+                    Value::Integer(2),
+                    Value::Text("Bobby".to_string()),
+                    Value::Integer(1)
+                ],
+                vec![
+                    Value::Integer(3),
+                    Value::Text("Carol".to_string()),
+                    Value::Integer(1)
+                ],
+                vec![
+                    Value::Integer(4),
+                    Value::Text("Dave".to_string()),
+                    Value::Integer(2)
+                ],
+            ]
+        );
+
+        // The update has to keep the keys unique
+        let result = db.execute(
+            "INSERT INTO visits VALUES (1, 'x@x', 'Alice', 1) ON DUPLICATE KEY UPDATE email = 'b@x'",
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "UNIQUE constraint 'email' violated: duplicate entry 'b@x' for column 'email'"
+        );
+        let result = db.execute(
+            "INSERT INTO visits VALUES (1, 'a@x', 'Alice', 1) ON DUPLICATE KEY UPDATE missing = 1",
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Column 'missing' not found"
+        );
+        let result = db.execute("UPDATE visits SET count = VALUES(count)");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_on_conflict() {
+        let mut db = Database::new();
+        setup_visits(&mut db);
+
+        let counts = upsert_counts(
+            &mut db,
+            "INSERT INTO visits VALUES (1, 'z@x', 'Zoe', 5), (3, 'c@x', 'Carol', 1) ON CONFLICT (id) DO NOTHING",
+        );
+        assert_eq!(counts, (1, 0));
+        // Only the key named by the target counts
+        let result = db
+            .execute("INSERT INTO visits VALUES (9, 'a@x', 'Ann', 1) ON CONFLICT (id) DO NOTHING");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "UNIQUE constraint 'email' violated: duplicate entry 'a@x' for column 'email'"
+        );
+        let counts = upsert_counts(
+            &mut db,
+            "INSERT INTO visits VALUES (9, 'a@x', 'Ann', 1) ON CONFLICT ON CONSTRAINT email DO NOTHING",
+        );
+        assert_eq!(counts, (0, 0));
+
+        // DO UPDATE sees the proposed row as `excluded` and skips rows failing its WHERE
+        let counts = upsert_counts(
+            &mut db,
+            "INSERT INTO visits VALUES (1, 'a@x', 'Ann', 4), (2, 'b@x', 'Bobby', 4) ON CONFLICT (id) DO UPDATE SET name = excluded.name, count = visits.count + excluded.count WHERE visits.name <> 'Bob'",
+        );
+        assert_eq!(counts, (0, 1));
+        assert_eq!(
+            select_rows(&mut db, "SELECT id, name, count FROM visits ORDER BY id"),
+            vec![
+                vec![
+                    Value::Integer(1),
+                    Value::Text("Ann".to_string()),
+                    Value::Integer(5)
+                ],
+                vec![
+                    Value::Integer(2),
+                    Value::Text("Bob".to_string()),
+                    Value::Integer(1)
+                ],
+                vec![
+                    Value::Integer(3),
+                    Value::Text("Carol".to_string()),
+                    Value::Integer(1)
+                ],
+            ]
+        );
+
+        let result = db.execute(
+            "INSERT INTO visits VALUES (1, 'a@x', 'Ann', 1) ON CONFLICT (name) DO NOTHING",
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "No PRIMARY KEY or UNIQUE key of 'visits' matches the ON CONFLICT column 'name'"
+        );
+        let result = db.execute("INSERT INTO visits VALUES (1, 'a@x', 'Ann', 1) ON CONFLICT ON CONSTRAINT nope DO NOTHING");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "No PRIMARY KEY or UNIQUE constraint 'nope' on table 'visits'"
+        );
+    }
+
+    #[test]
+    fn test_upsert_is_atomic() {
+        let mut db = Database::new();
+        setup_departments(&mut db, "REFERENCES departments (id) ON UPDATE CASCADE");
+        db.execute("CREATE UNIQUE INDEX departments_name ON departments (name)")
+            .unwrap();
+
+        // The first row moves Engineering to id 10 and its employees with it, the second fails
+        let result = db.execute(
+            "INSERT INTO departments VALUES (5, 'Engineering'), (6, 'Sales') ON DUPLICATE KEY UPDATE id = 10",
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Row 2 of 2: PRIMARY KEY constraint 'PRIMARY' violated: duplicate entry '10' for column 'id'"
+        );
+        assert_eq!(
+            select_rows(
+                &mut db,
+                "SELECT id FROM departments WHERE name = 'Engineering'"
+            ),
+            vec![vec![Value::Integer(1)]]
+        );
+        assert_eq!(
+            employee_departments(&mut db),
+            vec![
+                vec![Value::Integer(1), Value::Integer(1)],
+                vec![Value::Integer(2), Value::Integer(2)],
+                vec![Value::Integer(3), Value::Integer(1)],
+                vec![Value::Integer(4), Value::Null],
+            ]
+        );
+
+        let counts = upsert_counts(
+            &mut db,
+            "INSERT INTO departments VALUES (5, 'Engineering'), (6, 'Support') ON DUPLICATE KEY UPDATE id = 10",
+        );
+        assert_eq!(counts, (1, 1));
+        assert_eq!(
+            select_rows(&mut db, "SELECT id FROM employees WHERE department_id = 10"),
+            vec![vec![Value::Integer(1)], vec![Value::Integer(3)]]
+        );
+    }
+
+    #[test]
+    fn test_upserts_survive_restart() {
+        let dir = temp_data_dir("upserts");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            setup_visits(&mut db);
+            db.execute("INSERT INTO visits VALUES (1, 'a@x', 'Alice', 1) ON DUPLICATE KEY UPDATE count = count + 1")
+                .unwrap();
+            assert!(db
+                .execute("INSERT INTO visits VALUES (1, 'a@x', 'Alice', 1) ON DUPLICATE KEY UPDATE count = 99")
+                .is_err());
+        }
+        let mut db = Database::open(&dir).unwrap();
+        assert_eq!(
+            select_rows(&mut db, "SELECT id, count FROM visits ORDER BY id"),
+            vec![
+                vec![Value::Integer(1), Value::Integer(2)],
+                vec![Value::Integer(2), Value::Integer(1)],
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn user_ids(db: &mut Database) -> Vec<Vec<Value>> {
+        select_rows(db, "SELECT id FROM users ORDER BY id")
+    }
+
+    fn ids(values: &[i64]) -> Vec<Vec<Value>> {
+        values.iter().map(|&id| vec![Value::Integer(id)]).collect()
+    }
+
+    #[test]
+    fn test_transaction_commit_and_rollback() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT PRIMARY KEY, name VARCHAR(20))")
+            .unwrap();
+        db.execute("INSERT INTO users VALUES (1, 'Alice'), (2, 'Bob')")
+            .unwrap();
+
+        db.execute("BEGIN").unwrap();
+        assert!(db.in_transaction());
+        db.execute("INSERT INTO users VALUES (3, 'Carol')").unwrap();
+        db.execute("UPDATE users SET id = 10 WHERE id = 1").unwrap();
+        // The transaction sees its own changes
+        assert_eq!(user_ids(&mut db), ids(&[2, 3, 10]));
+        db.execute("DELETE FROM users").unwrap();
+        assert!(user_ids(&mut db).is_empty());
+        db.execute("ROLLBACK").unwrap();
+        assert!(!db.in_transaction());
+        assert_eq!(user_ids(&mut db), ids(&[1, 2]));
+        // Indexes are rolled back with the rows
+        assert!(db.execute("INSERT INTO users VALUES (3, 'Carol')").is_ok());
+        assert!(db.execute("INSERT INTO users VALUES (1, 'Eve')").is_err());
+
+        db.execute("START TRANSACTION").unwrap();
+        db.execute("INSERT INTO users VALUES (4, 'Dave')").unwrap();
+        db.execute("COMMIT").unwrap();
+        db.execute("ROLLBACK").unwrap();
+        assert_eq!(user_ids(&mut db), ids(&[1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn test_failed_statement_in_transaction() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT PRIMARY KEY)")
+            .unwrap();
+
+        db.execute("BEGIN").unwrap();
+        db.execute("INSERT INTO users VALUES (1)").unwrap();
+        // Only the failing statement is taken back, the transaction goes on
+        let result = db.execute("INSERT INTO users VALUES (2), (1)");
+        assert!(result.is_err());
+        assert!(db.in_transaction());
+        assert_eq!(user_ids(&mut db), ids(&[1]));
+        db.execute("INSERT INTO users VALUES (2)").unwrap();
+        db.execute("COMMIT").unwrap();
+        assert_eq!(user_ids(&mut db), ids(&[1, 2]));
+    }
+
+    #[test]
+    fn test_savepoints() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT PRIMARY KEY)")
+            .unwrap();
+
+        let result = db.execute("SAVEPOINT a");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "SAVEPOINT can only be used in a transaction"
+        );
+
+        db.execute("BEGIN").unwrap();
+        db.execute("INSERT INTO users VALUES (1)").unwrap();
+        db.execute("SAVEPOINT a").unwrap();
+        db.execute("INSERT INTO users VALUES (2)").unwrap();
+        db.execute("SAVEPOINT b").unwrap();
+        db.execute("UPDATE users SET id = 3 WHERE id = 1").unwrap();
+        db.execute("ROLLBACK TO SAVEPOINT b").unwrap();
+        assert_eq!(user_ids(&mut db), ids(&[1, 2]));
+        db.execute("ROLLBACK TO a").unwrap();
+        assert_eq!(user_ids(&mut db), ids(&[1]));
+        // Savepoints set after the one rolled back to are gone, that one stays
+        let result = db.execute("ROLLBACK TO SAVEPOINT b");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Savepoint 'b' does not exist"
+        );
+        db.execute("INSERT INTO users VALUES (4)").unwrap();
+        db.execute("ROLLBACK WORK TO SAVEPOINT A;").unwrap();
+        db.execute("INSERT INTO users VALUES (5)").unwrap();
+        db.execute("RELEASE SAVEPOINT a").unwrap();
+        assert!(db.execute("ROLLBACK TO SAVEPOINT a").is_err());
+        db.execute("COMMIT").unwrap();
+        assert_eq!(user_ids(&mut db), ids(&[1, 5]));
+    }
+
+    #[test]
+    fn test_transaction_rolls_back_foreign_key_actions() {
+        let mut db = Database::new();
+        setup_departments(&mut db, "REFERENCES departments (id) ON DELETE CASCADE");
+
+        db.execute("BEGIN").unwrap();
+        db.execute("DELETE FROM departments WHERE id = 1").unwrap();
+        db.execute("INSERT INTO departments VALUES (1, 'Research')")
+            .unwrap();
+        db.execute("INSERT INTO employees VALUES (5, 'Eve', 1)")
+            .unwrap();
+        db.execute("ROLLBACK").unwrap();
+
+        assert_eq!(
+            select_rows(&mut db, "SELECT name FROM departments WHERE id = 1"),
+            vec![vec![Value::Text("Engineering".to_string())]]
+        );
+        // Restored rows may come back in another order
+        assert_eq!(
+            select_rows(
+                &mut db,
+                "SELECT id, department_id FROM employees ORDER BY id"
+            ),
+            vec![
+                vec![Value::Integer(1), Value::Integer(1)],
+                vec![Value::Integer(2), Value::Integer(2)],
+                vec![Value::Integer(3), Value::Integer(1)],
+                vec![Value::Integer(4), Value::Null],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_schema_change_commits_transaction() {
+        let mut db = Database::new();
+        db.execute("CREATE TABLE users (id INT PRIMARY KEY)")
+            .unwrap();
+
+        db.execute("BEGIN").unwrap();
+        db.execute("INSERT INTO users VALUES (1)").unwrap();
+        db.execute("CREATE TABLE logins (id INT)").unwrap();
+        assert!(!db.in_transaction());
+        db.execute("ROLLBACK").unwrap();
+        assert_eq!(user_ids(&mut db), ids(&[1]));
+
+        // Starting a transaction commits the open one
+        db.execute("BEGIN").unwrap();
+        db.execute("INSERT INTO users VALUES (2)").unwrap();
+        db.execute("BEGIN").unwrap();
+        db.execute("INSERT INTO users VALUES (3)").unwrap();
+        db.execute("ROLLBACK").unwrap();
+        assert_eq!(user_ids(&mut db), ids(&[1, 2]));
+    }
+
+    #[test]
+    fn test_commit_that_cannot_be_logged_rolls_back() {
+        let dir = temp_data_dir("commit-failure");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE users (id INT PRIMARY KEY)")
+                .unwrap();
+            db.execute("INSERT INTO users VALUES (1)").unwrap();
+            db.execute("BEGIN").unwrap();
+            db.execute("INSERT INTO users VALUES (2)").unwrap();
+            db.execute("DELETE FROM users WHERE id = 1").unwrap();
+            db.break_wal().unwrap();
+            let result = db.execute("COMMIT");
+            assert!(result
+                .unwrap_err()
+                .to_string()
+                .starts_with("COMMIT failed, the transaction was rolled back"));
+            assert!(!db.in_transaction());
+            assert_eq!(user_ids(&mut db), ids(&[1]));
+        }
+        let mut db = Database::open(&dir).unwrap();
+        assert_eq!(user_ids(&mut db), ids(&[1]));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_sessions_keep_transactions_apart() {
+        let dir = temp_data_dir("sessions");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE users (id INT PRIMARY KEY)")
+                .unwrap();
+            let (mut a, mut b) = (Session::default(), Session::default());
+            a.execute(&mut db, "BEGIN").unwrap();
+            a.execute(&mut db, "INSERT INTO users VALUES (1)").unwrap();
+            assert!(!db.in_transaction());
+
+            // Another session neither waits for the transaction nor sees its changes
+            assert_eq!(user_ids(&mut db), ids(&[]));
+            b.execute(&mut db, "INSERT INTO users VALUES (2)").unwrap();
+            let rows = a.execute(&mut db, "SELECT id FROM users ORDER BY id");
+            if let QueryResult::Rows(data) = rows.unwrap() {
+                assert_eq!(data.rows, ids(&[1, 2]));
+            } else {
+                panic!("Expected Rows QueryResult");
+            }
+            a.execute(&mut db, "SAVEPOINT s").unwrap();
+            a.execute(&mut db, "INSERT INTO users VALUES (3)").unwrap();
+            a.execute(&mut db, "ROLLBACK TO SAVEPOINT s").unwrap();
+            a.execute(&mut db, "COMMIT").unwrap();
+            assert_eq!(user_ids(&mut db), ids(&[1, 2]));
+
+            // A statement that a change committed meanwhile makes fail ends the transaction
+            a.execute(&mut db, "BEGIN").unwrap();
+            a.execute(&mut db, "INSERT INTO users VALUES (4)").unwrap();
+            b.execute(&mut db, "INSERT INTO users VALUES (4)").unwrap();
+            let result = a.execute(&mut db, "INSERT INTO users VALUES (5)");
+            assert!(result
+                .unwrap_err()
+                .to_string()
+                .starts_with("Transaction rolled back: statement 1 of it fails"));
+            assert!(matches!(
+                a.execute(&mut db, "COMMIT").unwrap(),
+                QueryResult::Success(message) if message == "No transaction to commit"
+            ));
+
+            // Never committed
+            b.execute(&mut db, "BEGIN").unwrap();
+            b.execute(&mut db, "DELETE FROM users").unwrap();
+            assert_eq!(user_ids(&mut db), ids(&[1, 2, 4]));
+        }
+        let mut db = Database::open(&dir).unwrap();
+        assert_eq!(user_ids(&mut db), ids(&[1, 2, 4]));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_only_committed_transactions_survive_restart() {
+        let dir = temp_data_dir("transactions");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE users (id INT PRIMARY KEY) ENGINE = log")
+                .unwrap();
+            db.execute("BEGIN").unwrap();
+            db.execute("INSERT INTO users VALUES (1), (2)").unwrap();
+            db.execute("SAVEPOINT a").unwrap();
+            db.execute("DELETE FROM users WHERE id = 2").unwrap();
+            db.execute("ROLLBACK TO SAVEPOINT a").unwrap();
+            let result = db.execute("CHECKPOINT");
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                "CHECKPOINT cannot be used in a transaction"
+            );
+            db.execute("COMMIT").unwrap();
+
+            db.execute("BEGIN").unwrap();
+            db.execute("INSERT INTO users VALUES (3)").unwrap();
+            db.execute("ROLLBACK").unwrap();
+            // Never committed
+            db.execute("BEGIN").unwrap();
+            db.execute("INSERT INTO users VALUES (4)").unwrap();
+        }
+        let mut db = Database::open(&dir).unwrap();
+        assert!(!db.in_transaction());
+        assert_eq!(user_ids(&mut db), ids(&[1, 2]));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_transaction_cut_short_in_wal_is_rolled_back() {
+        let dir = temp_data_dir("transaction-cut-short");
+        {
+            let mut db = Database::open(&dir).unwrap();
+            db.execute("CREATE TABLE users (id INT PRIMARY KEY)")
+                .unwrap();
+            db.execute("BEGIN").unwrap();
+            db.execute("INSERT INTO users VALUES (1)").unwrap();
+            db.execute("COMMIT").unwrap();
+        }
+        // Lose the COMMIT record: header, LSN and "COMMIT"
+        let wal = std::fs::OpenOptions::new()
+            .write(true)
+            .open(dir.join("wal.log"))
+            .unwrap();
+        let length = wal.metadata().unwrap().len();
+        wal.set_len(length - (8 + 8 + 6)).unwrap();
+        drop(wal);
+
+        let mut db = Database::open(&dir).unwrap();
+        assert_eq!(
+            db.take_warnings(),
+            vec!["Rolled back a transaction cut short at the end of the WAL".to_string()]
+        );
+        assert_eq!(user_ids(&mut db), ids(&[]));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }